@@ -55,6 +55,10 @@ pub enum GenesisSubcommand {
         /// Contract admin, the account who can migrate the contract
         #[arg(long)]
         admin: Option<String>,
+
+        /// Reject calls to the contract while it's already being executed
+        #[arg(long)]
+        non_reentrant: bool,
     },
 
     /// Add an "execute contract" message to the genesis state
@@ -124,6 +128,7 @@ impl GenesisCmd {
                 funds,
                 label,
                 admin,
+                non_reentrant,
             } => {
                 if funds.is_some() {
                     return Err(Error::unsupported_feature("sending funds"));
@@ -134,6 +139,7 @@ impl GenesisCmd {
                     funds: vec![],
                     label,
                     admin,
+                    non_reentrant,
                 });
                 update_and_write(&mut genesis, &app_state, &genesis_path)
             },
@@ -4,6 +4,7 @@ mod init;
 mod keys;
 mod query;
 mod reset;
+mod rollback;
 mod start;
 mod tendermint;
 mod tx;
@@ -17,7 +18,8 @@ use tracing_subscriber::filter::LevelFilter;
 
 use crate::{
     debug::DebugCmd, genesis::GenesisCmd, init::InitCmd, keys::KeysCmd, query::QueryCmd,
-    reset::ResetCmd, start::StartCmd, tendermint::TendermintCmd, tx::TxCmd,
+    reset::ResetCmd, rollback::RollbackCmd, start::StartCmd, tendermint::TendermintCmd,
+    tx::TxCmd,
 };
 
 #[derive(Parser)]
@@ -53,6 +55,10 @@ pub enum Command {
     #[command(alias = "q")]
     Query(QueryCmd),
 
+    /// Revert the application state by one block, e.g. after a bad block was
+    /// committed
+    Rollback(RollbackCmd),
+
     /// Start the ABCI server
     Start(StartCmd),
 
@@ -89,6 +95,7 @@ async fn run() -> Result<()> {
         Command::Init(cmd) => cmd.run(&home_dir),
         Command::Keys(cmd) => cmd.run(&home_dir),
         Command::Query(cmd) => cmd.run(&home_dir).await,
+        Command::Rollback(cmd) => cmd.run(&home_dir),
         Command::Start(cmd) => cmd.run(&home_dir),
         Command::Tendermint(cmd) => cmd.run(&home_dir).await,
         Command::Tx(cmd) => cmd.run(&home_dir).await,
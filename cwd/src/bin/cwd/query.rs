@@ -8,8 +8,9 @@ use chrono::{DateTime, SecondsFormat, Utc};
 use clap::{Args, Subcommand};
 use cosmwasm_std::{BlockInfo, ContractResult};
 use cw_sdk::{
-    hash::sha256, AccountResponse, CodeResponse, ContractResponse, InfoResponse, SdkQuery,
-    WasmRawResponse, WasmSmartResponse,
+    hash::sha256, AccountResponse, AllowanceResponse, BlockResponse, CodeInfoResponse, CodeResponse,
+    Config, ContractResponse, DebugMessage, InfoResponse, JobResponse, SdkQuery, WasmRawResponse,
+    WasmSmartResponse,
 };
 use cwd::{
     client::{create_http_client, do_abci_query},
@@ -17,6 +18,8 @@ use cwd::{
 };
 use serde::Serialize;
 use serde_json::Value;
+use tendermint::Hash;
+use tendermint_rpc::{endpoint::tx, Client};
 use tracing::{error, info};
 
 use crate::Result;
@@ -36,6 +39,16 @@ pub enum QuerySubcmd {
     /// Query the application's global state
     Info,
 
+    /// Query the chain-level parameters
+    Config,
+
+    /// Query the summary of a committed block: its number of txs, the gas they
+    /// consumed, the hashes of the failed ones, and the hash of its events
+    Block {
+        /// Block height
+        height: u64,
+    },
+
     /// Query an account's public key and sequence number
     Account {
         /// Account address
@@ -53,6 +66,20 @@ pub enum QuerySubcmd {
         limit: Option<u32>,
     },
 
+    /// Enumerate all base accounts with the given public key
+    AccountsByPubkey {
+        /// The secp256k1 public key, in hex encoding
+        pubkey: String,
+
+        /// Start after this address
+        #[arg(long)]
+        start_after: Option<String>,
+
+        /// The maximum number of results to be returned in this query
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+
     /// Query a single contract by label
     Contract {
         /// Contract label
@@ -70,6 +97,56 @@ pub enum QuerySubcmd {
         limit: Option<u32>,
     },
 
+    /// Enumerate all contracts instantiated from the given code id
+    ContractsByCode {
+        /// Code id
+        code_id: u64,
+
+        /// Start after this contract address
+        #[arg(long)]
+        start_after: Option<String>,
+
+        /// The maximum number of results to be returned in this query
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+
+    /// Enumerate all contracts administered by the given account
+    ContractsByAdmin {
+        /// Admin address
+        admin: String,
+
+        /// Start after this contract address
+        #[arg(long)]
+        start_after: Option<String>,
+
+        /// The maximum number of results to be returned in this query
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+
+    /// Query the fee allowance granted by one account to another
+    Allowance {
+        /// Granter address
+        granter: String,
+        /// Grantee address
+        grantee: String,
+    },
+
+    /// Enumerate all fee allowances granted by an account
+    Allowances {
+        /// Granter address
+        granter: String,
+
+        /// Start after this grantee address
+        #[arg(long)]
+        start_after: Option<String>,
+
+        /// The maximum number of results to be returned in this query
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+
     /// Retrieve the metadata and wasm byte code corresponding to the given code id
     Code {
         /// Code id
@@ -91,6 +168,40 @@ pub enum QuerySubcmd {
         limit: Option<u32>,
     },
 
+    /// Enumerate the ids of all pinned wasm byte codes
+    PinnedCodes {
+        /// Start after this code id
+        #[arg(long)]
+        start_after: Option<u64>,
+
+        /// The maximum number of results to be returned in this query
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+
+    /// Retrieve the metadata of a wasm byte code, without downloading the byte code
+    CodeInfo {
+        /// Code id
+        code_id: u64,
+    },
+
+    /// Query a scheduled message that has not yet been executed
+    Job {
+        /// Job id
+        job_id: u64,
+    },
+
+    /// Enumerate all scheduled messages that have not yet been executed
+    Jobs {
+        /// Start after this job id
+        #[arg(long)]
+        start_after: Option<u64>,
+
+        /// The maximum number of results to be returned in this query
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+
     /// Perform a wasm raw query
     WasmRaw {
         /// Contract address
@@ -106,6 +217,13 @@ pub enum QuerySubcmd {
         /// Query message in JSON format
         msg: String,
     },
+
+    /// Query the result of a tx that has been included in a block, including
+    /// the debug messages printed by contracts if the node captures them
+    Tx {
+        /// Tx hash, in hex encoding
+        hash: String,
+    },
 }
 
 impl QueryCmd {
@@ -120,6 +238,26 @@ impl QueryCmd {
                 print::json(PrettyInfoResponse::from(response))?;
             },
 
+            QuerySubcmd::Config => {
+                let response: Config<String> = do_abci_query(&client, SdkQuery::Config {}).await?;
+
+                print::json(response)?;
+            },
+
+            QuerySubcmd::Block {
+                height,
+            } => {
+                let response: BlockResponse = do_abci_query(
+                    &client,
+                    SdkQuery::Block {
+                        height,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
             QuerySubcmd::Account {
                 address,
             } => {
@@ -150,6 +288,24 @@ impl QueryCmd {
                 print::json(response)?;
             },
 
+            QuerySubcmd::AccountsByPubkey {
+                pubkey,
+                start_after,
+                limit,
+            } => {
+                let response: Vec<AccountResponse> = do_abci_query(
+                    &client,
+                    SdkQuery::AccountsByPubkey {
+                        pubkey: hex::decode(pubkey)?.into(),
+                        start_after,
+                        limit,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
             QuerySubcmd::Contract {
                 label,
             } => {
@@ -180,6 +336,76 @@ impl QueryCmd {
                 print::json(response)?;
             },
 
+            QuerySubcmd::ContractsByCode {
+                code_id,
+                start_after,
+                limit,
+            } => {
+                let response: Vec<ContractResponse> = do_abci_query(
+                    &client,
+                    SdkQuery::ContractsByCode {
+                        code_id,
+                        start_after,
+                        limit,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
+            QuerySubcmd::ContractsByAdmin {
+                admin,
+                start_after,
+                limit,
+            } => {
+                let response: Vec<ContractResponse> = do_abci_query(
+                    &client,
+                    SdkQuery::ContractsByAdmin {
+                        admin,
+                        start_after,
+                        limit,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
+            QuerySubcmd::Allowance {
+                granter,
+                grantee,
+            } => {
+                let response: AllowanceResponse = do_abci_query(
+                    &client,
+                    SdkQuery::Allowance {
+                        granter,
+                        grantee,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
+            QuerySubcmd::Allowances {
+                granter,
+                start_after,
+                limit,
+            } => {
+                let response: Vec<AllowanceResponse> = do_abci_query(
+                    &client,
+                    SdkQuery::Allowances {
+                        granter,
+                        start_after,
+                        limit,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
             QuerySubcmd::Code {
                 code_id,
                 output,
@@ -221,6 +447,66 @@ impl QueryCmd {
                 print::json(response)?;
             },
 
+            QuerySubcmd::PinnedCodes {
+                start_after,
+                limit,
+            } => {
+                let response: Vec<u64> = do_abci_query(
+                    &client,
+                    SdkQuery::PinnedCodes {
+                        start_after,
+                        limit,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
+            QuerySubcmd::CodeInfo {
+                code_id,
+            } => {
+                let response: CodeInfoResponse = do_abci_query(
+                    &client,
+                    SdkQuery::CodeInfo {
+                        code_id,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
+            QuerySubcmd::Job {
+                job_id,
+            } => {
+                let response: JobResponse = do_abci_query(
+                    &client,
+                    SdkQuery::Job {
+                        job_id,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
+            QuerySubcmd::Jobs {
+                start_after,
+                limit,
+            } => {
+                let response: Vec<JobResponse> = do_abci_query(
+                    &client,
+                    SdkQuery::Jobs {
+                        start_after,
+                        limit,
+                    },
+                )
+                .await?;
+
+                print::json(response)?;
+            },
+
             QuerySubcmd::WasmRaw {
                 contract,
                 key,
@@ -265,6 +551,14 @@ impl QueryCmd {
                     ContractResult::Err(err) => error!("Query failed: {err}"),
                 }
             },
+
+            QuerySubcmd::Tx {
+                hash,
+            } => {
+                let response = client.tx(hash.to_uppercase().parse::<Hash>()?, false).await?;
+
+                print::json(PrettyTxResponse::try_from(response)?)?;
+            },
         };
 
         Ok(())
@@ -288,6 +582,35 @@ impl From<&CodeResponse> for HashedCodeResponse {
     }
 }
 
+/// The result of a tx, with the debug messages, which the node returns in the
+/// `info` field as a JSON string, decoded. Used for CLI output.
+#[derive(Serialize)]
+pub struct PrettyTxResponse {
+    height: u64,
+    code: u32,
+    log: String,
+    debug_messages: Vec<DebugMessage>,
+}
+
+impl TryFrom<tx::Response> for PrettyTxResponse {
+    type Error = serde_json::Error;
+
+    fn try_from(res: tx::Response) -> std::result::Result<Self, Self::Error> {
+        let debug_messages = if res.tx_result.info.is_empty() {
+            vec![]
+        } else {
+            serde_json::from_str(&res.tx_result.info)?
+        };
+
+        Ok(Self {
+            height: res.height.value(),
+            code: res.tx_result.code.value(),
+            log: res.tx_result.log,
+            debug_messages,
+        })
+    }
+}
+
 /// Like InfoResponse but BlockInfo is substituted with PrettyBlockInfo.
 #[derive(Serialize)]
 pub struct PrettyInfoResponse {
@@ -0,0 +1,30 @@
+use std::path::Path;
+
+use clap::Args;
+use tracing::info;
+
+use cw_state_machine::state::BLOCK;
+use cw_store::{CommitStore, TreeStore};
+
+use crate::Result;
+
+#[derive(Args)]
+pub struct RollbackCmd;
+
+impl RollbackCmd {
+    pub fn run(&self, home_dir: &Path) -> Result<()> {
+        // opening the store also undoes the last commit, if it was interrupted
+        let store = TreeStore::open(home_dir.join("./data"))?;
+        let version = store.rollback()?;
+
+        // Tendermint's state must be rolled back to the same height, using its
+        // own `rollback` command, before the node is restarted
+        let height = BLOCK.may_load(&store.wrap())?.map_or(0, |block| block.height);
+        info!(
+            "Rolled back application state to height {height} (version {version}), app hash {}",
+            hex::encode(store.root_hash()),
+        );
+
+        Ok(())
+    }
+}
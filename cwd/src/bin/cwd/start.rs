@@ -6,8 +6,8 @@ use tendermint_abci::ServerBuilder;
 use tracing::info;
 
 use cw_server::{App, AppDriver};
-use cw_state_machine::StateMachine;
-use cw_store::Store;
+use cw_state_machine::{changeset::ChangesetWriter, layout::AppLayout, Options, StateMachine};
+use cw_store::TreeStore;
 use cwd::AppConfig;
 
 use crate::Result;
@@ -21,17 +21,33 @@ impl StartCmd {
         let app_cfg = AppConfig::load(home_dir)?;
         info!("Loaded application config");
 
-        // load merk store from disk
-        let store = Store::open(home_dir.join("./data"))?;
-        info!("Loaded Merk store");
+        // load the tree store from disk, laid out as a Merkle forest
+        let mut store = TreeStore::open(home_dir.join("./data"))?
+            .with_layout(AppLayout)
+            .with_pruning((&app_cfg.pruning).into());
+        if app_cfg.write_changesets {
+            store = store.with_listener(ChangesetWriter::new(home_dir.join("./changesets"))?);
+        }
+        info!("Loaded tree store");
 
         // create a new state machine instance wrapping the store
-        let state_machine = StateMachine::new(store);
+        let state_machine = StateMachine::new(
+            store,
+            Options {
+                vm_cache_dir: home_dir.join("./wasm"),
+                query_gas_limit: app_cfg.query_gas_limit,
+                trace_file: app_cfg.trace_storage.then(|| home_dir.join("./trace.jsonl")),
+                capture_debug: app_cfg.capture_debug,
+            },
+        )?;
+        info!("Loaded VM cache");
 
-        // create a channel between the App and AppDriver
+        // create a channel between the App and AppDriver. queries are served by
+        // the App from snapshots of the committed state instead
         let (cmd_tx, cmd_rx) = mpsc::channel();
         let app = App {
             cmd_tx,
+            query_handle: state_machine.query_handle(),
         };
         let mut driver = AppDriver {
             state_machine,
@@ -45,7 +61,9 @@ impl StartCmd {
         std::thread::spawn(move || server.listen().unwrap());
 
         // NOTE: in basecoin, the app driver is spawned in threads.
-        // here we can't do the same because the Merk store is not thread safe.
+        // here we can't do the same because the store is not thread safe. this
+        // doesn't hold up queries, which are handled in the ABCI server's
+        // threads using thread-safe snapshots of the store.
         driver.run();
 
         Ok(())
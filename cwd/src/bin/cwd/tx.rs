@@ -6,13 +6,15 @@ use std::{
 
 use clap::{Args, Subcommand};
 use colored::*;
-use cosmwasm_std::Addr;
-use cw_sdk::{Account, AccountResponse, SdkMsg, SdkQuery, TxBody};
+use cosmwasm_std::{Addr, Binary, Timestamp};
+use cw_sdk::{Account, AccountResponse, MultisigKey, SdkMsg, SdkQuery, Tx, TxBody};
+use cw_utils::Scheduled;
 use cwd::{
     client::{create_http_client, do_abci_query},
+    multisig::{self, PartialSignature},
     print, prompt, ClientConfig, Error, Keyring,
 };
-use tendermint_rpc::Client;
+use tendermint_rpc::{Client, HttpClient};
 use tracing::warn;
 
 use crate::Result;
@@ -24,7 +26,17 @@ pub struct TxCmd {
 
     /// Name of the key which will sign the transaction
     #[arg(long)]
-    from: String,
+    from: Option<String>,
+
+    /// Address of the sending account, if not the one of the signing key, e.g.
+    /// a multisig account
+    #[arg(long)]
+    sender: Option<String>,
+
+    /// Print the unsigned transaction body instead of signing and broadcasting
+    /// it; used for offline signing
+    #[arg(long)]
+    generate_only: bool,
 
     /// Chain id; overrides default value in client config
     #[arg(long)]
@@ -34,6 +46,33 @@ pub struct TxCmd {
     #[arg(long)]
     sequence: Option<u64>,
 
+    /// Send an unordered transaction with this nonce, instead of using the
+    /// sequence number; requires --timeout-height
+    #[arg(long, conflicts_with = "sequence", requires = "timeout_height")]
+    nonce: Option<u64>,
+
+    /// Block height after which the transaction is no longer valid
+    #[arg(long)]
+    timeout_height: Option<u64>,
+
+    /// A note to be attached to the transaction
+    #[arg(long)]
+    memo: Option<String>,
+
+    /// Coins to be paid as fee, in JSON format
+    #[arg(long)]
+    fee: Option<String>,
+
+    /// Address of the account who pays the fee, if not the sender. The fee
+    /// payer must either sign the transaction, or have granted the sender an
+    /// allowance
+    #[arg(long)]
+    fee_payer: Option<String>,
+
+    /// Name of the key which will sign the transaction as the fee payer
+    #[arg(long, requires = "fee_payer")]
+    fee_payer_key: Option<String>,
+
     /// Tendermint RPC endpoint; overrides default value in client config
     #[arg(long)]
     node: Option<String>,
@@ -41,6 +80,95 @@ pub struct TxCmd {
 
 #[derive(Subcommand)]
 pub enum TxSubcmd {
+    /// Update the chain-level parameters; only callable by the owner
+    UpdateConfig {
+        /// The new config in JSON format
+        new_config: String,
+    },
+
+    /// Pin a wasm byte code in the nodes' memory; only callable by the owner
+    PinCode {
+        /// Code id
+        code_id: u64,
+    },
+
+    /// Unpin a wasm byte code; only callable by the owner
+    UnpinCode {
+        /// Code id
+        code_id: u64,
+    },
+
+    /// Opt the signing account into, or out of, unordered transactions
+    SetUnorderedTxs {
+        /// Whether to accept unordered transactions
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+
+    /// Grant an account an allowance to have its fees paid by the signer
+    GrantAllowance {
+        /// Grantee address
+        grantee: String,
+        /// The allowance in JSON format
+        allowance: String,
+    },
+
+    /// Revoke the allowance granted by the signer to an account
+    RevokeAllowance {
+        /// Grantee address
+        grantee: String,
+    },
+
+    /// Schedule a message to be executed by the state machine at a future block
+    /// height or time, on behalf of the signer
+    Schedule {
+        /// The message in JSON format
+        msg: String,
+
+        /// Block height at which the message is to be executed
+        #[arg(long, conflicts_with = "at_time", required_unless_present = "at_time")]
+        at_height: Option<u64>,
+
+        /// Block time, in seconds since the UNIX epoch, at which the message is
+        /// to be executed
+        #[arg(long)]
+        at_time: Option<u64>,
+
+        /// Maximum amount of gas the message's contract calls can consume
+        #[arg(long)]
+        gas_limit: u64,
+    },
+
+    /// Cancel a scheduled message that has not yet been executed
+    CancelJob {
+        /// The scheduled message's job id
+        job_id: u64,
+    },
+
+    /// Sign a transaction body, generated with --generate-only, as one of the
+    /// signers of a multisig account
+    Sign {
+        /// Path to the transaction body in JSON format
+        body_path: PathBuf,
+    },
+
+    /// Combine the signatures of a multisig account's signers into a
+    /// transaction, and broadcast it
+    Multisign {
+        /// Path to the transaction body in JSON format
+        body_path: PathBuf,
+        /// Paths to the signers' signatures, generated by `cwd tx sign`
+        signature_paths: Vec<PathBuf>,
+
+        /// The multisig account's pubkeys, hex-encoded and comma-separated
+        #[arg(long, value_delimiter = ',')]
+        pubkeys: Vec<String>,
+
+        /// The multisig account's threshold
+        #[arg(long)]
+        threshold: u32,
+    },
+
     /// Upload wasm byte code
     Store {
         /// Path to the wasm byte code
@@ -65,6 +193,10 @@ pub enum TxSubcmd {
         /// Contract admin, the account who can migrate the contract
         #[arg(long)]
         admin: Option<String>,
+
+        /// Reject calls to the contract while it's already being executed
+        #[arg(long)]
+        non_reentrant: bool,
     },
 
     /// Execute a contract
@@ -92,20 +224,70 @@ pub enum TxSubcmd {
 
 impl TxCmd {
     pub async fn run(self, home_dir: &Path) -> Result<()> {
-        // load sender key
+        // load signing key
         let keyring = Keyring::new(home_dir.join("keys"))?;
-        let key = keyring.get(&self.from)?;
-        let sender_addr = key.address()?;
+        let key = self.from.as_ref().map(|from| keyring.get(from)).transpose()?;
 
         // create tendermint client
         let client_cfg = ClientConfig::load(home_dir)?;
         let client = create_http_client(self.node.as_ref(), &client_cfg)?;
 
+        // offline signing: these subcommands work on a previously generated tx
+        // body, instead of creating a new one
+        match &self.subcommand {
+            TxSubcmd::Sign {
+                body_path,
+            } => {
+                let key = key.ok_or(Error::NoSigningKey)?;
+                let body: TxBody = serde_json::from_slice(&fs::read(body_path)?)?;
+                return print::json(key.sign_tx_partial(&body)?);
+            },
+
+            TxSubcmd::Multisign {
+                body_path,
+                signature_paths,
+                pubkeys,
+                threshold,
+            } => {
+                let body: TxBody = serde_json::from_slice(&fs::read(body_path)?)?;
+                let signatures = signature_paths
+                    .iter()
+                    .map(|path| -> Result<PartialSignature> {
+                        Ok(serde_json::from_slice(&fs::read(path)?)?)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let multisig_key = MultisigKey {
+                    pubkeys: pubkeys
+                        .iter()
+                        .map(|pubkey| hex::decode(pubkey).map(Binary::from))
+                        .collect::<std::result::Result<_, _>>()?,
+                    threshold: *threshold,
+                };
+
+                let mut tx = multisig::combine(body, multisig_key, signatures)?;
+                if let Some(fee_payer_key) = &self.fee_payer_key {
+                    keyring.get(fee_payer_key)?.sign_tx_as_fee_payer(&mut tx)?;
+                }
+
+                return broadcast(&client, &tx).await;
+            },
+
+            _ => {},
+        }
+
+        let sender_addr = match (&self.sender, &key) {
+            (Some(sender), _) => Addr::unchecked(sender),
+            (None, Some(key)) => key.address()?,
+            (None, None) => return Err(Error::NoSigningKey),
+        };
+
         // find chain id
         let chain_id = self.chain_id.as_ref().unwrap_or(&client_cfg.chain_id);
 
-        // query the sender's sequence number if not provided
+        // query the sender's sequence number if not provided.
+        // unordered txs don't use the sequence number, so we don't query it
         let sequence = match self.sequence {
+            None if self.nonce.is_some() => 0,
             None => {
                 let result = do_abci_query::<_, AccountResponse>(
                     &client,
@@ -119,10 +301,15 @@ impl TxCmd {
                     // if the account exists and is a base account, we take the
                     // sequence number
                     Ok(AccountResponse {
-                        account: Account::Base {
-                            sequence,
-                            ..
-                        },
+                        account:
+                            Account::Base {
+                                sequence,
+                                ..
+                            }
+                            | Account::Multisig {
+                                sequence,
+                                ..
+                            },
                         ..
                     }) => sequence,
 
@@ -163,6 +350,71 @@ impl TxCmd {
         };
 
         let msg = match self.subcommand {
+            TxSubcmd::UpdateConfig {
+                new_config,
+            } => SdkMsg::UpdateConfig {
+                new_config: serde_json::from_str(&new_config)?,
+            },
+
+            TxSubcmd::PinCode {
+                code_id,
+            } => SdkMsg::PinCode {
+                code_id,
+            },
+
+            TxSubcmd::UnpinCode {
+                code_id,
+            } => SdkMsg::UnpinCode {
+                code_id,
+            },
+
+            TxSubcmd::SetUnorderedTxs {
+                enabled,
+            } => SdkMsg::SetUnorderedTxs {
+                enabled,
+            },
+
+            TxSubcmd::GrantAllowance {
+                grantee,
+                allowance,
+            } => SdkMsg::GrantAllowance {
+                grantee,
+                allowance: serde_json::from_str(&allowance)?,
+            },
+
+            TxSubcmd::RevokeAllowance {
+                grantee,
+            } => SdkMsg::RevokeAllowance {
+                grantee,
+            },
+
+            TxSubcmd::Schedule {
+                msg,
+                at_height,
+                at_time,
+                gas_limit,
+            } => SdkMsg::Schedule {
+                msg: Box::new(serde_json::from_str(&msg)?),
+                at: at_height
+                    .map(Scheduled::AtHeight)
+                    .or_else(|| at_time.map(Timestamp::from_seconds).map(Scheduled::AtTime))
+                    .expect("either --at-height or --at-time is required"),
+                gas_limit,
+            },
+
+            TxSubcmd::CancelJob {
+                job_id,
+            } => SdkMsg::CancelJob {
+                job_id,
+            },
+
+            TxSubcmd::Sign {
+                ..
+            }
+            | TxSubcmd::Multisign {
+                ..
+            } => unreachable!("offline signing subcommands are handled above"),
+
             TxSubcmd::Store {
                 wasm_byte_code_path,
             } => {
@@ -179,6 +431,7 @@ impl TxCmd {
                 funds,
                 label,
                 admin,
+                non_reentrant,
             } => {
                 if funds.is_some() {
                     return Err(Error::unsupported_feature("sending funds"));
@@ -189,6 +442,7 @@ impl TxCmd {
                     funds: vec![],
                     label,
                     admin,
+                    non_reentrant,
                 }
             },
 
@@ -223,20 +477,38 @@ impl TxCmd {
             msgs: vec![msg],
             chain_id: chain_id.into(),
             sequence,
+            nonce: self.nonce,
+            timeout_height: self.timeout_height,
+            memo: self.memo,
+            fee: self.fee.map(|fee| serde_json::from_str(&fee)).transpose()?.unwrap_or_default(),
+            fee_payer: self.fee_payer,
         };
 
-        let tx = key.sign_tx(&body)?;
-        let tx_bytes = serde_json::to_vec(&tx)?;
-
-        println!("{}", "🤖 Transaction signed:".bold());
-        print::json(&tx)?;
+        if self.generate_only {
+            return print::json(&body);
+        }
 
-        if prompt::confirm(format!("{}", "🤔 Broadcast?".bold()))? {
-            let response = client.broadcast_tx_async(tx_bytes).await?;
-            print::json(response)?;
-            println!("{}", "🙌 Successfully broadcasted!".bold());
+        let mut tx = key.ok_or(Error::NoSigningKey)?.sign_tx(&body)?;
+        if let Some(fee_payer_key) = &self.fee_payer_key {
+            keyring.get(fee_payer_key)?.sign_tx_as_fee_payer(&mut tx)?;
         }
 
-        Ok(())
+        broadcast(&client, &tx).await
     }
 }
+
+/// Print a signed tx, and broadcast it if the user confirms.
+async fn broadcast(client: &HttpClient, tx: &Tx) -> Result<()> {
+    let tx_bytes = serde_json::to_vec(tx)?;
+
+    println!("{}", "🤖 Transaction signed:".bold());
+    print::json(tx)?;
+
+    if prompt::confirm(format!("{}", "🤔 Broadcast?".bold()))? {
+        let response = client.broadcast_tx_async(tx_bytes).await?;
+        print::json(response)?;
+        println!("{}", "🙌 Successfully broadcasted!".bold());
+    }
+
+    Ok(())
+}
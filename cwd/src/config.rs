@@ -1,14 +1,30 @@
 use std::fs;
 use std::path::Path;
 
+use cw_state_machine::DEFAULT_QUERY_GAS_LIMIT;
+use cw_store::Pruning;
 use serde::{Deserialize, Serialize};
 
 use crate::Result;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConfig {
     /// Address to listen for ABCI requests
     pub listen_addr: String,
+    /// Maximum amount of CosmWasm gas a smart query can consume
+    pub query_gas_limit: u64,
+    /// Whether to record the storage operations of every delivered tx to
+    /// `trace.jsonl` under the home directory
+    pub trace_storage: bool,
+    /// Whether to capture the debug messages printed by contracts in txs, and
+    /// return them in the txs' results, instead of printing them to stderr
+    pub capture_debug: bool,
+    /// Whether to write the state changes of every block to `changesets/`
+    /// under the home directory, one file per height
+    pub write_changesets: bool,
+    /// Which historical versions of the state to delete from disk
+    pub pruning: PruningConfig,
 }
 
 impl Default for AppConfig {
@@ -16,6 +32,56 @@ impl Default for AppConfig {
         Self {
             // including the `tcp://` prefix causes an error...?
             listen_addr: "127.0.0.1:26658".into(),
+            query_gas_limit: DEFAULT_QUERY_GAS_LIMIT,
+            trace_storage: false,
+            capture_debug: false,
+            write_changesets: false,
+            pruning: PruningConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PruningStrategy {
+    /// Keep all versions, e.g. for archive nodes
+    Nothing,
+    /// Keep only the latest version, pruning after every block
+    Everything,
+    /// Keep the `keep_recent` most recent versions, pruning every `interval`
+    /// blocks
+    KeepRecent,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PruningConfig {
+    pub strategy: PruningStrategy,
+    /// Number of recent versions to keep, for the `keep-recent` strategy
+    pub keep_recent: u64,
+    /// Number of blocks between two prunings, for the `keep-recent` strategy
+    pub interval: u64,
+}
+
+impl Default for PruningConfig {
+    fn default() -> Self {
+        Self {
+            strategy: PruningStrategy::KeepRecent,
+            keep_recent: 100,
+            interval: 10,
+        }
+    }
+}
+
+impl From<&PruningConfig> for Pruning {
+    fn from(cfg: &PruningConfig) -> Self {
+        match cfg.strategy {
+            PruningStrategy::Nothing => Pruning::Nothing,
+            PruningStrategy::Everything => Pruning::Everything,
+            PruningStrategy::KeepRecent => Pruning::KeepRecent {
+                keep_recent: cfg.keep_recent,
+                interval: cfg.interval,
+            },
         }
     }
 }
@@ -34,6 +34,18 @@ pub enum Error {
     #[error(transparent)]
     Merk(#[from] cw_store::MerkError),
 
+    #[error(transparent)]
+    Db(#[from] cw_store::DbError),
+
+    #[error(transparent)]
+    Store(#[from] cw_store::StoreError),
+
+    #[error(transparent)]
+    Std(#[from] cosmwasm_std::StdError),
+
+    #[error(transparent)]
+    StateMachine(#[from] cw_state_machine::error::Error),
+
     #[error(transparent)]
     Tendermint(#[from] tendermint::Error),
 
@@ -58,6 +70,9 @@ pub enum Error {
     #[error("password is incorrect")]
     IncorrectPassword,
 
+    #[error("no signing key is specified; use --from")]
+    NoSigningKey,
+
     #[error("file already exists: {filename}")]
     FileExists {
         filename: String,
@@ -87,6 +102,11 @@ pub enum Error {
     UnsupportedFeature {
         feature: String,
     },
+
+    #[error("pubkey {pubkey} is not one of the multisig account's pubkeys")]
+    UnknownSigner {
+        pubkey: String,
+    },
 }
 
 impl Error {
@@ -125,6 +145,12 @@ impl Error {
             feature: feature.into(),
         }
     }
+
+    pub fn unknown_signer(pubkey: impl Into<String>) -> Self {
+        Self::UnknownSigner {
+            pubkey: pubkey.into(),
+        }
+    }
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -5,7 +5,7 @@ use k256::ecdsa::{signature::Signer, Signature, SigningKey, VerifyingKey};
 
 use cw_sdk::{address, Tx, TxBody};
 
-use crate::{Error, Result};
+use crate::{multisig::PartialSignature, Error, Result};
 
 /// Represents a private key that is to be saved in the keyring.
 ///
@@ -81,8 +81,29 @@ impl Key {
             body: body.clone(),
             pubkey: Some(self.pubkey().to_bytes().to_vec().into()),
             signature: signature.to_vec().into(),
+            multi_signature: None,
+            fee_payer_signature: None,
         })
     }
+
+    /// Sign a tx body as one of the signers of a multisig account. The partial
+    /// signature is to be combined with the other signers' ones.
+    pub fn sign_tx_partial(&self, body: &TxBody) -> Result<PartialSignature> {
+        let body_bytes = serde_json::to_vec(body)?;
+        let signature = self.sign_bytes(&body_bytes);
+        Ok(PartialSignature {
+            pubkey: self.pubkey().to_bytes().to_vec().into(),
+            signature: signature.to_vec().into(),
+        })
+    }
+
+    /// Sign a tx as its fee payer, agreeing to pay the fee.
+    pub fn sign_tx_as_fee_payer(&self, tx: &mut Tx) -> Result<()> {
+        let body_bytes = serde_json::to_vec(&tx.body)?;
+        let signature = self.sign_bytes(&body_bytes);
+        tx.fee_payer_signature = Some(signature.to_vec().into());
+        Ok(())
+    }
 }
 
 impl TryFrom<Key> for JwtPayload {
@@ -3,12 +3,13 @@ mod config;
 mod error;
 mod key;
 mod keyring;
+pub mod multisig;
 pub mod path;
 pub mod print;
 pub mod prompt;
 
 pub use crate::{
-    config::{AppConfig, ClientConfig},
+    config::{AppConfig, ClientConfig, PruningConfig, PruningStrategy},
     error::Error,
     key::Key,
     keyring::Keyring,
@@ -0,0 +1,52 @@
+use cosmwasm_std::Binary;
+use serde::{Deserialize, Serialize};
+
+use cw_sdk::{MultiSignature, MultisigKey, Tx, TxBody};
+
+use crate::{Error, Result};
+
+/// A signature over a tx body produced by one of a multisig account's signers,
+/// to be combined with the other signers' ones by `cwd tx multisign`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartialSignature {
+    /// The signer's secp256k1 pubkey
+    pub pubkey: Binary,
+    /// The signer's signature over the tx body
+    pub signature: Binary,
+}
+
+/// Combine the partial signatures of a multisig account's signers into a tx.
+///
+/// The signatures can be provided in any order; they are sorted, and the
+/// bitmap is built, according to the order of the sorted pubkeys.
+pub fn combine(body: TxBody, key: MultisigKey, signatures: Vec<PartialSignature>) -> Result<Tx> {
+    let mut pubkeys = key.pubkeys.clone();
+    pubkeys.sort();
+
+    let mut bitmap = vec![0u8; (pubkeys.len() + 7) / 8];
+    let mut sorted_signatures = vec![None; pubkeys.len()];
+    for PartialSignature {
+        pubkey,
+        signature,
+    } in signatures
+    {
+        let index = pubkeys
+            .iter()
+            .position(|pk| *pk == pubkey)
+            .ok_or_else(|| Error::unknown_signer(hex::encode(pubkey.as_slice())))?;
+        bitmap[index / 8] |= 0x80 >> (index % 8);
+        sorted_signatures[index] = Some(signature);
+    }
+
+    Ok(Tx {
+        body,
+        pubkey: None,
+        signature: Binary::default(),
+        multi_signature: Some(MultiSignature {
+            key: Some(key),
+            bitmap: bitmap.into(),
+            signatures: sorted_signatures.into_iter().flatten().collect(),
+        }),
+        fee_payer_signature: None,
+    })
+}
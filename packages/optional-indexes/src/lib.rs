@@ -1,5 +1,5 @@
+mod multi;
 mod unique;
 
+pub use multi::OptionalMultiIndex;
 pub use unique::OptionalUniqueIndex;
-
-// TODO: add OptionalMultiIndex
@@ -0,0 +1,241 @@
+use std::marker::PhantomData;
+
+use cosmwasm_std::{Empty, Order, StdResult, Storage};
+use cw_storage_plus::{Bound, Index, KeyDeserialize, Map, Prefixer, PrimaryKey, RawBound};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Similar to `MultiIndex`, but the index function returns an _optional_ index
+/// key. Only saves an entry in the index map if it is `Some`.
+///
+/// Entries are stored as (index_key, primary_key) => empty, so that multiple
+/// values can share the same index key, and all values under a given index key
+/// can be iterated in the order of their primary keys. Same as `MultiIndex`,
+/// the values themselves are not duplicated in the index, but loaded from the
+/// primary map under `pk_namespace`.
+///
+/// In cw-sdk, this is used in the `ACCOUNTS` map, where smart contract accounts
+/// are indexed by their code ids and admins, and base accounts by their public
+/// keys, while other accounts are not indexed.
+pub struct OptionalMultiIndex<'a, IK, T, PK = ()> {
+    index: fn(&T) -> Option<IK>,
+    idx_map: Map<'a, (IK, Vec<u8>), Empty>,
+    pk_map: Map<'a, Vec<u8>, T>,
+    phantom: PhantomData<PK>,
+}
+
+impl<'a, IK, T, PK> OptionalMultiIndex<'a, IK, T, PK> {
+    pub const fn new(
+        idx_fn: fn(&T) -> Option<IK>,
+        pk_namespace: &'a str,
+        idx_namespace: &'a str,
+    ) -> Self {
+        Self {
+            index: idx_fn,
+            idx_map: Map::new(idx_namespace),
+            pk_map: Map::new(pk_namespace),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, IK, T, PK> OptionalMultiIndex<'a, IK, T, PK>
+where
+    PK: PrimaryKey<'a> + KeyDeserialize,
+    IK: PrimaryKey<'a> + Prefixer<'a> + KeyDeserialize,
+    T: Serialize + DeserializeOwned + Clone,
+{
+    /// Iterate the entries under the given index key, in the order of their
+    /// primary keys.
+    pub fn range<'c>(
+        &self,
+        store: &'c dyn Storage,
+        key: IK,
+        min: Option<Bound<'a, PK>>,
+        max: Option<Bound<'a, PK>>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = StdResult<(PK::Output, T)>> + 'c>
+    where
+        'a: 'c,
+        T: 'c,
+        PK::Output: 'static,
+    {
+        let pk_map = self.pk_map.clone();
+        let iter = self
+            .idx_map
+            .prefix(key)
+            .keys_raw(store, min.map(raw_bound), max.map(raw_bound), order)
+            .map(move |pk| {
+                let value = pk_map.load(store, pk.clone())?;
+                let key = PK::from_vec(pk)?;
+                Ok((key, value))
+            });
+        Box::new(iter)
+    }
+}
+
+impl<'a, IK, T, PK> Index<T> for OptionalMultiIndex<'a, IK, T, PK>
+where
+    T: Serialize + DeserializeOwned + Clone,
+    IK: PrimaryKey<'a> + Prefixer<'a> + KeyDeserialize,
+{
+    fn save(&self, store: &mut dyn Storage, pk: &[u8], data: &T) -> StdResult<()> {
+        // only save data in idx_map if the index in `Some`
+        if let Some(idx) = (self.index)(data) {
+            self.idx_map.save(store, (idx, pk.to_vec()), &Empty {})?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, store: &mut dyn Storage, pk: &[u8], old_data: &T) -> StdResult<()> {
+        if let Some(idx) = (self.index)(old_data) {
+            self.idx_map.remove(store, (idx, pk.to_vec()));
+        }
+        Ok(())
+    }
+}
+
+/// Convert a bound on the primary key to a raw bound on the index map's suffix,
+/// which is the primary key's raw bytes.
+fn raw_bound<'a, PK: PrimaryKey<'a>>(bound: Bound<'a, PK>) -> Bound<'a, Vec<u8>> {
+    match bound.to_raw_bound() {
+        RawBound::Inclusive(bytes) => Bound::InclusiveRaw(bytes),
+        RawBound::Exclusive(bytes) => Bound::ExclusiveRaw(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::MockStorage;
+    use cw_storage_plus::{IndexList, IndexedMap};
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Pet {
+        species: String,
+        owner: Option<String>,
+    }
+
+    struct PetIndexes<'a> {
+        owner: OptionalMultiIndex<'a, String, Pet, &'a str>,
+    }
+
+    impl<'a> IndexList<Pet> for PetIndexes<'a> {
+        fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Pet>> + '_> {
+            let v: Vec<&dyn Index<Pet>> = vec![&self.owner];
+            Box::new(v.into_iter())
+        }
+    }
+
+    const PETS: IndexedMap<&str, Pet, PetIndexes> = IndexedMap::new("pets", PetIndexes {
+        owner: OptionalMultiIndex::new(|pet| pet.owner.clone(), "pets", "pets__owner"),
+    });
+
+    fn pet(species: &str, owner: Option<&str>) -> Pet {
+        Pet {
+            species: species.into(),
+            owner: owner.map(String::from),
+        }
+    }
+
+    fn save_mock_pets(store: &mut dyn Storage) {
+        PETS.save(store, "rex", &pet("dog", Some("larry"))).unwrap();
+        PETS.save(store, "tom", &pet("cat", Some("jake"))).unwrap();
+        PETS.save(store, "felix", &pet("cat", Some("larry"))).unwrap();
+        PETS.save(store, "stray", &pet("cat", None)).unwrap();
+    }
+
+    fn pets_of(
+        store: &dyn Storage,
+        owner: &str,
+        start_after: Option<&'static str>,
+    ) -> Vec<(String, Pet)> {
+        let start = start_after.map(Bound::exclusive);
+        PETS.idx
+            .owner
+            .range(store, owner.into(), start, None, Order::Ascending)
+            .collect::<StdResult<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn saving() {
+        let mut store = MockStorage::new();
+        save_mock_pets(&mut store);
+
+        // values are loaded from the primary map, in the order of primary keys
+        assert_eq!(pets_of(&store, "larry", None), vec![
+            ("felix".to_string(), pet("cat", Some("larry"))),
+            ("rex".to_string(), pet("dog", Some("larry"))),
+        ]);
+        assert_eq!(pets_of(&store, "jake", None), vec![
+            ("tom".to_string(), pet("cat", Some("jake"))),
+        ]);
+
+        // values whose index is `None` are not indexed
+        let keys = PETS.idx.owner.idx_map.keys_raw(&store, None, None, Order::Ascending);
+        assert_eq!(keys.count(), 3);
+    }
+
+    #[test]
+    fn removing() {
+        let mut store = MockStorage::new();
+        save_mock_pets(&mut store);
+
+        PETS.remove(&mut store, "rex").unwrap();
+        PETS.remove(&mut store, "stray").unwrap();
+        assert_eq!(pets_of(&store, "larry", None), vec![
+            ("felix".to_string(), pet("cat", Some("larry"))),
+        ]);
+
+        PETS.remove(&mut store, "felix").unwrap();
+        assert!(pets_of(&store, "larry", None).is_empty());
+    }
+
+    #[test]
+    fn replacing() {
+        let mut store = MockStorage::new();
+        save_mock_pets(&mut store);
+
+        // changing the index key moves the entry
+        PETS.save(&mut store, "rex", &pet("dog", Some("jake"))).unwrap();
+        assert_eq!(pets_of(&store, "larry", None), vec![
+            ("felix".to_string(), pet("cat", Some("larry"))),
+        ]);
+        assert_eq!(pets_of(&store, "jake", None), vec![
+            ("rex".to_string(), pet("dog", Some("jake"))),
+            ("tom".to_string(), pet("cat", Some("jake"))),
+        ]);
+
+        // changing other fields is reflected, as values aren't copied into the
+        // index
+        PETS.save(&mut store, "tom", &pet("tiger", Some("jake"))).unwrap();
+        assert_eq!(pets_of(&store, "jake", None)[1].1, pet("tiger", Some("jake")));
+
+        // changing the index key to `None` removes the entry
+        PETS.save(&mut store, "felix", &pet("cat", None)).unwrap();
+        assert!(pets_of(&store, "larry", None).is_empty());
+    }
+
+    #[test]
+    fn paginating() {
+        let mut store = MockStorage::new();
+        for name in ["a", "b", "c", "d"] {
+            PETS.save(&mut store, name, &pet("fish", Some("larry"))).unwrap();
+        }
+        PETS.save(&mut store, "bb", &pet("fish", Some("jake"))).unwrap();
+
+        let names = |start_after| {
+            pets_of(&store, "larry", start_after)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(None), ["a", "b", "c", "d"]);
+        assert_eq!(names(Some("a")), ["b", "c", "d"]);
+        assert_eq!(names(Some("b")), ["c", "d"]);
+        assert_eq!(names(Some("bb")), ["c", "d"]);
+        assert!(names(Some("d")).is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Binary};
+use cosmwasm_std::{Addr, Binary, Coin};
 use cw_address_like::AddressLike;
 
 /// The account type to be stored on-chain.
@@ -14,6 +14,32 @@ pub enum Account<T: AddressLike> {
         /// The account's sequence number, used to prevent replay attacks.
         /// The first tx ever to be submitted by the account should come with the sequence of 1.
         sequence: u64,
+
+        /// Whether the account accepts unordered txs, which are protected from
+        /// replay attacks by nonces instead of the sequence number.
+        #[serde(default)]
+        unordered: bool,
+    },
+
+    /// An account that is controlled by multiple secp256k1 key pairs, at least
+    /// `threshold` of which must sign each tx.
+    ///
+    /// Unlike multisig contracts (e.g. cw3), the signatures are collected
+    /// offline and submitted in a single tx.
+    Multisig {
+        /// The signers' secp256k1 public keys, in sorted order
+        pubkeys: Vec<Binary>,
+
+        /// The minimum number of signatures required to authorize a tx
+        threshold: u32,
+
+        /// The account's sequence number, same as that of base accounts.
+        sequence: u64,
+
+        /// Whether the account accepts unordered txs, same as that of base
+        /// accounts.
+        #[serde(default)]
+        unordered: bool,
     },
 
     /// An account that is controlled by wasm code.
@@ -26,27 +52,62 @@ pub enum Account<T: AddressLike> {
 
         /// Account who is allowed to migrate the contract
         admin: Option<T>,
+
+        /// If true, the contract can't be called again while it's already
+        /// being executed, e.g. by a contract it calls.
+        #[serde(default)]
+        non_reentrant: bool,
     },
 }
 
+/// How much storage a contract uses, and the deposits held for it.
+#[cw_serde]
+#[derive(Default)]
+pub struct StorageUsage {
+    /// Total size, in bytes, of the keys and values the contract has stored
+    pub bytes: u64,
+
+    /// Coins deposited by the accounts who called the contract for the bytes
+    /// it has stored, in total. Each account's deposit is recorded separately:
+    /// as a call frees bytes, the caller is refunded a proportional part of
+    /// its own deposit, at the prices it paid, for up to as many bytes as it
+    /// has paid for.
+    pub deposits: Vec<Coin>,
+}
+
 impl From<Account<Addr>> for Account<String> {
     fn from(acct: Account<Addr>) -> Self {
         match acct {
             Account::Base {
                 pubkey,
                 sequence,
+                unordered,
             } => Account::Base {
                 pubkey,
                 sequence,
+                unordered,
+            },
+            Account::Multisig {
+                pubkeys,
+                threshold,
+                sequence,
+                unordered,
+            } => Account::Multisig {
+                pubkeys,
+                threshold,
+                sequence,
+                unordered,
             },
             Account::Contract {
                 code_id,
                 label,
                 admin,
+                non_reentrant,
             } => Account::Contract {
                 code_id,
                 label,
                 admin: admin.map(String::from),
+                non_reentrant,
             },
         }
     }
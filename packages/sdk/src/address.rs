@@ -1,5 +1,5 @@
 use bech32::{FromBase32, ToBase32, Variant};
-use cosmwasm_std::{Addr, CanonicalAddr};
+use cosmwasm_std::{Addr, Binary, CanonicalAddr};
 use thiserror::Error;
 
 use crate::hash::sha256;
@@ -101,6 +101,28 @@ pub fn derive_from_label(label: &str) -> Result<Addr, AddressError> {
     humanize_prehash(&bytes)
 }
 
+/// Derive the address of a multisig account based on its public keys and
+/// threshold.
+///
+/// ```plain
+/// address_bytes := sha256("multisig" | threshold | len_1 | pubkey_1 | ...)[:ADDRESS_LENGTH]
+/// ```
+///
+/// Where the pubkeys are sorted, the threshold is a 32-bit big-endian integer,
+/// and each `len_i` is the length of `pubkey_i` in one byte.
+pub fn derive_from_multisig(pubkeys: &[Binary], threshold: u32) -> Result<Addr, AddressError> {
+    let mut pubkeys = pubkeys.iter().collect::<Vec<_>>();
+    pubkeys.sort();
+
+    let mut bytes = "multisig".to_string().into_bytes();
+    bytes.extend(threshold.to_be_bytes());
+    for pubkey in pubkeys {
+        bytes.push(pubkey.len() as u8);
+        bytes.extend(pubkey.as_slice());
+    }
+    humanize_prehash(&bytes)
+}
+
 /// Just a helper function for the `derive_from_*` methods.
 /// Performs the last steps of the address derivation process according to
 /// ADR-028: take the hash, truncate to the standard length, and humanize.
@@ -0,0 +1,135 @@
+use std::collections::BTreeSet;
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, Coin};
+use cw_address_like::AddressLike;
+
+/// By default, reject wasm byte codes larger than 800 KiB, the same limit as
+/// used by wasmd.
+pub const DEFAULT_MAX_CODE_SIZE: u64 = 800 * 1024;
+
+/// By default, allow contracts to call each other up to 10 levels deep.
+pub const DEFAULT_MAX_CALL_DEPTH: u32 = 10;
+
+/// By default, allow scheduled jobs to consume up to 3,000,000 SDK gas, the
+/// same as the default limit of smart queries. One SDK gas is 140,000 CosmWasm
+/// gas.
+pub const DEFAULT_MAX_JOB_GAS_LIMIT: u64 = 3_000_000 * 140_000;
+
+/// Chain-level parameters of the state machine.
+///
+/// These are set in the genesis state and saved in the state machine's storage
+/// during InitChain. Afterwards, they can be updated by the owner.
+#[cw_serde]
+pub struct Config<T: AddressLike> {
+    /// The account who is allowed to update the config, and to pin or unpin
+    /// codes. If left empty, the config can't be changed after genesis.
+    pub owner: Option<T>,
+
+    /// Who is allowed to upload wasm byte codes.
+    ///
+    /// The owner is always allowed to, regardless of this setting. Note that
+    /// this also applies to the genesis messages, so if this is set to anything
+    /// other than `Everybody`, the deployer must be the owner or whitelisted.
+    pub upload_permission: Permission<T>,
+
+    /// The maximum size, in bytes, of wasm byte codes that can be uploaded.
+    pub max_code_size: u64,
+
+    /// The maximum number of nested contract calls, counting the outermost
+    /// one. E.g. a contract calling itself recursively fails once the depth
+    /// exceeds this number.
+    #[serde(default = "default_max_call_depth")]
+    pub max_call_depth: u32,
+
+    /// The refundable deposit charged for each byte a contract call adds to
+    /// the contract's storage. If left empty, no deposit is charged.
+    #[serde(default)]
+    pub storage_deposit_per_byte: Option<Coin>,
+
+    /// The maximum amount of CosmWasm gas a scheduled job can be given.
+    #[serde(default = "default_max_job_gas_limit")]
+    pub max_job_gas_limit: u64,
+
+    /// The price of each unit of CosmWasm gas a scheduled job is given. The
+    /// job's gas limit times this price is escrowed from its creator when it
+    /// is scheduled, and the part for the gas it doesn't use is refunded once
+    /// it is run or cancelled. If left empty, jobs are free.
+    #[serde(default)]
+    pub job_gas_price: Option<Coin>,
+}
+
+fn default_max_call_depth() -> u32 {
+    DEFAULT_MAX_CALL_DEPTH
+}
+
+fn default_max_job_gas_limit() -> u64 {
+    DEFAULT_MAX_JOB_GAS_LIMIT
+}
+
+impl<T: AddressLike> Default for Config<T> {
+    fn default() -> Self {
+        Self {
+            owner: None,
+            upload_permission: Permission::Everybody,
+            max_code_size: DEFAULT_MAX_CODE_SIZE,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            storage_deposit_per_byte: None,
+            max_job_gas_limit: DEFAULT_MAX_JOB_GAS_LIMIT,
+            job_gas_price: None,
+        }
+    }
+}
+
+impl From<Config<Addr>> for Config<String> {
+    fn from(cfg: Config<Addr>) -> Self {
+        Self {
+            owner: cfg.owner.map(String::from),
+            upload_permission: cfg.upload_permission.into(),
+            max_code_size: cfg.max_code_size,
+            max_call_depth: cfg.max_call_depth,
+            storage_deposit_per_byte: cfg.storage_deposit_per_byte,
+            max_job_gas_limit: cfg.max_job_gas_limit,
+            job_gas_price: cfg.job_gas_price,
+        }
+    }
+}
+
+/// Describes who is allowed to perform a certain action, such as uploading
+/// wasm byte codes.
+#[cw_serde]
+pub enum Permission<T: AddressLike> {
+    /// Anyone can perform the action
+    Everybody,
+
+    /// Only the owner can perform the action
+    Nobody,
+
+    /// Only the owner and addresses in the whitelist can perform the action
+    Whitelist(BTreeSet<T>),
+}
+
+impl<T: AddressLike> Permission<T> {
+    /// Return whether the given address is allowed by this permission.
+    /// Note that this does not take into account the owner, who is always
+    /// allowed.
+    pub fn allows(&self, addr: &T) -> bool {
+        match self {
+            Permission::Everybody => true,
+            Permission::Nobody => false,
+            Permission::Whitelist(whitelist) => whitelist.contains(addr),
+        }
+    }
+}
+
+impl From<Permission<Addr>> for Permission<String> {
+    fn from(permission: Permission<Addr>) -> Self {
+        match permission {
+            Permission::Everybody => Permission::Everybody,
+            Permission::Nobody => Permission::Nobody,
+            Permission::Whitelist(whitelist) => {
+                Permission::Whitelist(whitelist.into_iter().map(String::from).collect())
+            },
+        }
+    }
+}
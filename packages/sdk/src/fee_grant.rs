@@ -0,0 +1,22 @@
+use std::collections::BTreeSet;
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::Coin;
+
+/// An allowance granted by one account (the granter) to another (the grantee),
+/// which allows the grantee to have its tx fees paid by the granter.
+#[cw_serde]
+#[derive(Default)]
+pub struct Allowance {
+    /// The maximum total amount of coins the grantee can spend on fees.
+    /// Reduced each time the allowance is used. Unlimited if not provided.
+    pub spend_limit: Option<Vec<Coin>>,
+
+    /// The block height after which the allowance can no longer be used.
+    /// Never expires if not provided.
+    pub expiration: Option<u64>,
+
+    /// Types of messages, in snake_case (e.g. `execute`), that txs paid for
+    /// using this allowance may contain. All types are allowed if not provided.
+    pub allowed_msgs: Option<BTreeSet<String>>,
+}
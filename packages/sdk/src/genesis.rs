@@ -1,6 +1,6 @@
 use cosmwasm_schema::cw_serde;
 
-use crate::msg::SdkMsg;
+use crate::{config::Config, msg::SdkMsg};
 
 /// This should be included inside `~/.tendermint/genesis.json`, under the
 /// `app_state` field.
@@ -19,6 +19,11 @@ pub struct GenesisState {
     /// The application developers must provide a trusted deployer account.
     pub deployer: String,
 
+    /// Chain-level parameters of the state machine.
+    /// If not provided, the default values are used.
+    #[serde(default)]
+    pub config: Config<String>,
+
     /// Messages to be executed in order during the InitChain call.
     pub msgs: Vec<SdkMsg>,
 }
@@ -0,0 +1,43 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, Coin};
+use cw_address_like::AddressLike;
+use cw_utils::Scheduled;
+
+use crate::msg::SdkMsg;
+
+/// A message scheduled to be executed in the future, on behalf of the account
+/// who scheduled it.
+#[cw_serde]
+pub struct Job<T: AddressLike> {
+    /// The account who scheduled the job, who is the sender of the message,
+    /// and who can cancel the job before it is run
+    pub creator: T,
+
+    /// The message to be executed
+    pub msg: SdkMsg,
+
+    /// The job is run in the BeginBlock of the first block at or after this
+    /// height or time
+    pub at: Scheduled,
+
+    /// Maximum amount of CosmWasm gas the contracts called by the message can
+    /// consume in total. The message fails if the budget is exceeded.
+    pub gas_limit: u64,
+
+    /// The coins escrowed from the creator to pay for the gas limit, at the
+    /// gas price as of when the job was scheduled. `None` if jobs were free.
+    #[serde(default)]
+    pub deposit: Option<Coin>,
+}
+
+impl From<Job<Addr>> for Job<String> {
+    fn from(job: Job<Addr>) -> Self {
+        Self {
+            creator: job.creator.into(),
+            msg: job.msg,
+            at: job.at,
+            gas_limit: job.gas_limit,
+            deposit: job.deposit,
+        }
+    }
+}
@@ -9,13 +9,19 @@
 /// JSON file.
 mod genesis;
 
+/// Defines the chain-level parameters of the state machine, such as the chain
+/// owner, who is allowed to upload wasm byte codes, and the maximum size of
+/// the codes.
+mod config;
+
 /// Defines the account types.
 ///
-/// Cw-sdk supports two types of accounts:
+/// Cw-sdk supports three types of accounts:
 ///
 /// - base account: a.k.a. externally-owned account (EoA), is an account
 ///   controlled by a single public/private key pair. For cw-sdk we use
 ///   secp256k1 keys.
+/// - multisig account: an account controlled by k-of-n secp256k1 key pairs.
 /// - contract account: an account controlled by a wasm binary code.
 ///
 /// Each account is identified an address. The algorithms for deriving addresses
@@ -36,6 +42,13 @@ mod msg;
 /// for preventing replay attacks, and the user's signature.
 mod tx;
 
+/// Defines fee allowances.
+///
+/// An account can grant another account an allowance, so that the latter can
+/// submit txs whose fees are paid by the former. This is useful for onboarding
+/// new users, who don't own any coins yet.
+mod fee_grant;
+
 /// Defines the required API for core contracts.
 ///
 /// A cw-sdk chain requires at least a few "core" contracts to function, which
@@ -60,8 +73,33 @@ mod tx;
 /// a chain where governance is a multisig, if you wish.)
 mod contracts;
 
+/// Defines scheduled jobs.
+///
+/// An account can schedule a message to be executed at a future block height
+/// or time, e.g. to release vested tokens or to settle an auction. Due jobs are
+/// run during BeginBlock, on behalf of the account who scheduled them.
+mod job;
+
+/// Defines the records of the storage tracing layer, and of contracts' debug
+/// output.
+///
+/// When enabled, every storage operation made during the execution of a tx is
+/// recorded, both on the contracts' substores and on the tx's store as a whole,
+/// so that the state transitions made by txs can be inspected and compared
+/// between versions of the state machine.
+///
+/// Similarly, the debug messages printed by contracts can be captured per tx,
+/// instead of being printed to the node's output.
+///
+/// Also defines the state changes written to the changeset files, which record
+/// every key-value pair changed in each block, e.g. for indexers.
+mod trace;
+
 // export types for easy access
-pub use crate::{account::*, contracts::*, genesis::*, msg::*, tx::*};
+pub use crate::{
+    account::*, config::*, contracts::*, fee_grant::*, genesis::*, job::*, msg::*, trace::*,
+    tx::*,
+};
 
 //------------------------------------------------------------------------------
 // Functions
@@ -76,6 +114,7 @@ pub use crate::{account::*, contracts::*, genesis::*, msg::*, tx::*};
 /// deterministically from the account data:
 ///
 /// - a base account's address is derived from its public key
+/// - a multisig account's address is derived from its public keys and threshold
 /// - a contract account's address is derived from its label
 ///
 /// ## Contract labels
@@ -1,11 +1,51 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Binary, BlockInfo, Coin, ContractResult};
+use cosmwasm_std::{Binary, BlockInfo, Coin, ContractResult, HexBinary};
+use cw_utils::Scheduled;
 use serde_json::Value;
 
-use crate::account::Account;
+use crate::{
+    account::{Account, StorageUsage},
+    config::Config,
+    fee_grant::Allowance,
+    job::Job,
+};
 
 #[cw_serde]
 pub enum SdkMsg {
+    /// Update the chain-level parameters. Only callable by the owner.
+    UpdateConfig {
+        new_config: Config<String>,
+    },
+
+    /// Keep the compiled module of a wasm byte code in memory, so that
+    /// frequently used contracts (e.g. bank) don't need to be loaded from disk
+    /// each time they are called. Only callable by the owner.
+    PinCode {
+        code_id: u64,
+    },
+
+    /// Remove a wasm byte code from the pinned set. Only callable by the owner.
+    UnpinCode {
+        code_id: u64,
+    },
+
+    /// Opt the sender account into, or out of, unordered txs.
+    SetUnorderedTxs {
+        enabled: bool,
+    },
+
+    /// Grant an account an allowance to have its tx fees paid by the sender.
+    /// Overwrites the existing allowance, if any.
+    GrantAllowance {
+        grantee: String,
+        allowance: Allowance,
+    },
+
+    /// Revoke the allowance granted by the sender to an account.
+    RevokeAllowance {
+        grantee: String,
+    },
+
     /// Store a binary code to the blockchain's state.
     StoreCode {
         wasm_byte_code: Binary,
@@ -41,6 +81,11 @@ pub enum SdkMsg {
         /// Account who is allowed to migrate the contract.
         /// To make the contract immutable, leave this field empty.
         admin: Option<String>,
+
+        /// Whether the contract should reject being called while it's already
+        /// being executed.
+        #[serde(default)]
+        non_reentrant: bool,
     },
 
     /// Execute a contract
@@ -56,6 +101,72 @@ pub enum SdkMsg {
         code_id: u64,
         msg: Value,
     },
+
+    /// Schedule a message to be executed on behalf of the sender at a future
+    /// block height or time. The message can't itself be a `Schedule` message.
+    Schedule {
+        msg: Box<SdkMsg>,
+
+        /// The message is executed in the BeginBlock of the first block at or
+        /// after this height or time
+        at: Scheduled,
+
+        /// Maximum amount of CosmWasm gas the contracts called by the message
+        /// can consume in total, up to the config's `max_job_gas_limit`. It is
+        /// paid for in advance at the config's `job_gas_price`.
+        gas_limit: u64,
+    },
+
+    /// Cancel a job scheduled by the sender that has not yet been run, and
+    /// refund the deposit paid for its gas.
+    CancelJob {
+        job_id: u64,
+    },
+}
+
+impl SdkMsg {
+    /// Return the message's type, which is the snake_case name of the variant,
+    /// i.e. the same as its key when serialized into JSON.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SdkMsg::UpdateConfig {
+                ..
+            } => "update_config",
+            SdkMsg::PinCode {
+                ..
+            } => "pin_code",
+            SdkMsg::UnpinCode {
+                ..
+            } => "unpin_code",
+            SdkMsg::SetUnorderedTxs {
+                ..
+            } => "set_unordered_txs",
+            SdkMsg::GrantAllowance {
+                ..
+            } => "grant_allowance",
+            SdkMsg::RevokeAllowance {
+                ..
+            } => "revoke_allowance",
+            SdkMsg::StoreCode {
+                ..
+            } => "store_code",
+            SdkMsg::Instantiate {
+                ..
+            } => "instantiate",
+            SdkMsg::Execute {
+                ..
+            } => "execute",
+            SdkMsg::Migrate {
+                ..
+            } => "migrate",
+            SdkMsg::Schedule {
+                ..
+            } => "schedule",
+            SdkMsg::CancelJob {
+                ..
+            } => "cancel_job",
+        }
+    }
 }
 
 #[cw_serde]
@@ -65,6 +176,17 @@ pub enum SdkQuery {
     #[returns(InfoResponse)]
     Info {},
 
+    /// Query the chain-level parameters
+    #[returns(Config<String>)]
+    Config {},
+
+    /// Query the summary of a committed block, such as the number of txs it
+    /// includes and which of them failed
+    #[returns(BlockResponse)]
+    Block {
+        height: u64,
+    },
+
     /// Query a single account by address
     #[returns(AccountResponse)]
     Account {
@@ -78,6 +200,14 @@ pub enum SdkQuery {
         limit: Option<u32>,
     },
 
+    /// Enumerate all base accounts with the given public key, by address
+    #[returns(Vec<AccountResponse>)]
+    AccountsByPubkey {
+        pubkey: Binary,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
     /// Query a single contract by label
     #[returns(ContractResponse)]
     Contract {
@@ -91,6 +221,37 @@ pub enum SdkQuery {
         limit: Option<u32>,
     },
 
+    /// Enumerate all contracts instantiated from the given code, by address
+    #[returns(Vec<ContractResponse>)]
+    ContractsByCode {
+        code_id: u64,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    /// Enumerate all contracts administered by the given account, by address
+    #[returns(Vec<ContractResponse>)]
+    ContractsByAdmin {
+        admin: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    /// Query the fee allowance granted by one account to another
+    #[returns(AllowanceResponse)]
+    Allowance {
+        granter: String,
+        grantee: String,
+    },
+
+    /// Enumerate all fee allowances granted by an account, by grantee
+    #[returns(Vec<AllowanceResponse>)]
+    Allowances {
+        granter: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
     /// Query a single wasm byte code by id
     #[returns(CodeResponse)]
     Code {
@@ -104,6 +265,33 @@ pub enum SdkQuery {
         limit: Option<u32>,
     },
 
+    /// Enumerate the ids of all pinned wasm byte codes
+    #[returns(Vec<u64>)]
+    PinnedCodes {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// Query the metadata of a single wasm byte code by id, without returning
+    /// the byte code itself
+    #[returns(CodeInfoResponse)]
+    CodeInfo {
+        code_id: u64,
+    },
+
+    /// Query a single scheduled job that has not yet been run
+    #[returns(JobResponse)]
+    Job {
+        job_id: u64,
+    },
+
+    /// Enumerate all scheduled jobs that have not yet been run, by job id
+    #[returns(Vec<JobResponse>)]
+    Jobs {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
     /// Perform raw query on a wasm contract
     #[returns(WasmRawResponse)]
     WasmRaw {
@@ -125,10 +313,25 @@ pub struct InfoResponse {
     pub code_count: u64,
 }
 
+#[cw_serde]
+pub struct BlockResponse {
+    pub block: BlockInfo,
+    /// Number of txs included in the block, including failed ones
+    pub tx_count: u32,
+    /// Total CosmWasm gas consumed by the contract calls made by the txs
+    pub gas_used: u64,
+    /// Hashes of the txs that failed
+    pub failed_txs: Vec<HexBinary>,
+    /// SHA-256 hash of the events emitted in the block, in JSON format
+    pub events_hash: HexBinary,
+}
+
 #[cw_serde]
 pub struct AccountResponse {
     pub address: String,
     pub account: Account<String>,
+    /// Only provided for contract accounts
+    pub storage_usage: Option<StorageUsage>,
 }
 
 #[cw_serde]
@@ -137,6 +340,20 @@ pub struct ContractResponse {
     pub code_id: u64,
     pub label: String,
     pub admin: Option<String>,
+    pub non_reentrant: bool,
+}
+
+#[cw_serde]
+pub struct AllowanceResponse {
+    pub granter: String,
+    pub grantee: String,
+    pub allowance: Allowance,
+}
+
+#[cw_serde]
+pub struct JobResponse {
+    pub job_id: u64,
+    pub job: Job<String>,
 }
 
 #[cw_serde]
@@ -145,6 +362,21 @@ pub struct CodeResponse {
     pub wasm_byte_code: Binary,
 }
 
+#[cw_serde]
+pub struct CodeInfoResponse {
+    pub code_id: u64,
+    /// Address of the account who uploaded the code
+    pub uploader: String,
+    /// The block height at which the code was uploaded
+    pub upload_height: u64,
+    /// SHA-256 hash of the wasm byte code
+    pub checksum: HexBinary,
+    /// Size of the wasm byte code in bytes
+    pub size: u64,
+    /// Capabilities that the code requires the host to support
+    pub required_capabilities: Vec<String>,
+}
+
 #[cw_serde]
 pub struct WasmRawResponse {
     /// Raw value in the contract storage under the given key.
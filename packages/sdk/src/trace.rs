@@ -0,0 +1,113 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, HexBinary};
+
+/// A storage operation made during the execution of a tx, as recorded by the
+/// storage tracing layer.
+#[cw_serde]
+pub struct StorageTrace {
+    /// The contract whose substore the operation was made on. `None` if it was
+    /// made on the tx's store, in which case the key is the full key, including
+    /// the namespace if it belongs to a contract.
+    pub contract: Option<Addr>,
+
+    /// The operation and the sizes of the key and value involved
+    pub op: StorageOp,
+}
+
+#[cw_serde]
+pub enum StorageOp {
+    Get {
+        key: HexBinary,
+        key_size: u64,
+        /// Size of the value read, or `None` if the key doesn't exist
+        value_size: Option<u64>,
+    },
+    Set {
+        key: HexBinary,
+        key_size: u64,
+        value_size: u64,
+    },
+    Remove {
+        key: HexBinary,
+        key_size: u64,
+    },
+    Scan {
+        /// Lower bound of the range, inclusive
+        start: Option<HexBinary>,
+        /// Upper bound of the range, exclusive
+        end: Option<HexBinary>,
+        descending: bool,
+    },
+}
+
+impl StorageOp {
+    pub fn get(key: &[u8], value: Option<&[u8]>) -> Self {
+        StorageOp::Get {
+            key: key.into(),
+            key_size: key.len() as u64,
+            value_size: value.map(|value| value.len() as u64),
+        }
+    }
+
+    pub fn set(key: &[u8], value: &[u8]) -> Self {
+        StorageOp::Set {
+            key: key.into(),
+            key_size: key.len() as u64,
+            value_size: value.len() as u64,
+        }
+    }
+
+    pub fn remove(key: &[u8]) -> Self {
+        StorageOp::Remove {
+            key: key.into(),
+            key_size: key.len() as u64,
+        }
+    }
+
+    pub fn scan(start: Option<&[u8]>, end: Option<&[u8]>, descending: bool) -> Self {
+        StorageOp::Scan {
+            start: start.map(Into::into),
+            end: end.map(Into::into),
+            descending,
+        }
+    }
+}
+
+/// The storage operations made by a tx, as written to the trace file, one per
+/// line, in the order the txs are delivered.
+#[cw_serde]
+pub struct TxTrace {
+    /// Height of the block that includes the tx
+    pub height: u64,
+    pub sender: String,
+    pub sequence: u64,
+    pub nonce: Option<u64>,
+    /// The error the tx failed with, if any, in which case its state changes
+    /// are discarded
+    pub error: Option<String>,
+    pub traces: Vec<StorageTrace>,
+}
+
+/// A change made to the state in a block, as written to the changeset files.
+#[cw_serde]
+pub struct StateChange {
+    /// The contract whose storage the key belongs to, or `None` if it belongs to
+    /// the state machine's own state
+    pub contract: Option<Addr>,
+    /// The key, relative to the contract's namespace if it belongs to a contract
+    pub key: HexBinary,
+    /// The new value, or `None` if the key was deleted
+    pub value: Option<HexBinary>,
+}
+
+/// A debug message printed by a contract using `deps.api.debug`, as captured
+/// during the execution of a tx if the node is configured to do so.
+#[cw_serde]
+pub struct DebugMessage {
+    /// Index of the tx's message during whose execution the debug message was
+    /// printed, or `None` if it was printed while deducting the tx's fee
+    pub msg_index: Option<u32>,
+    /// The contract that printed the message
+    pub contract: Addr,
+    pub message: String,
+}
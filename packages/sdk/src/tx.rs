@@ -1,8 +1,17 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::Binary;
+use cosmwasm_std::{Binary, Coin};
 
 use crate::msg::SdkMsg;
 
+/// The maximum length, in bytes, of a transaction's memo. Same as the default
+/// in the Cosmos SDK.
+pub const MAX_MEMO_LENGTH: usize = 256;
+
+/// The maximum number of blocks an unordered tx's timeout height can be ahead
+/// of the current block height. The nonces of unordered txs are stored until
+/// they time out, so this bounds the size of the storage needed.
+pub const MAX_UNORDERED_TX_TIMEOUT: u64 = 100;
+
 /// Tendermint will provide this as JSON bytes by in the CheckTx and DeliverTx
 /// requests. The state machine should deserialize the bytes upon receipt.
 #[cw_serde]
@@ -19,6 +28,45 @@ pub struct Tx {
     /// The content is `sha256(JSON.stringify(txbody))`, signed by the
     /// corresponding private key.
     pub signature: Binary,
+
+    /// If the sender is a multisig account, the signatures of its signers.
+    /// In this case, `pubkey` and `signature` are ignored.
+    pub multi_signature: Option<MultiSignature>,
+
+    /// The fee payer's secp256k1 signature over the same content as above.
+    ///
+    /// If the tx body specifies a fee payer other than the sender, the fee
+    /// payer either provides this signature to agree to pay the fee, or must
+    /// have granted the sender a fee allowance.
+    pub fee_payer_signature: Option<Binary>,
+}
+
+/// The public keys and threshold of a multisig account, from which the address
+/// is derived.
+#[cw_serde]
+pub struct MultisigKey {
+    /// The signers' secp256k1 public keys, in any order
+    pub pubkeys: Vec<Binary>,
+
+    /// The minimum number of signatures required to authorize a tx
+    pub threshold: u32,
+}
+
+/// Signatures of a multisig account's signers.
+#[cw_serde]
+pub struct MultiSignature {
+    /// The multisig account's public keys and threshold.
+    /// Optional if the account already exists in the state.
+    pub key: Option<MultisigKey>,
+
+    /// Indicates which of the account's public keys, in sorted order, have
+    /// signed the tx. The i-th public key corresponds to the (i % 8)-th most
+    /// significant bit of the (i / 8)-th byte.
+    pub bitmap: Binary,
+
+    /// Secp256k1 signatures over the same content as a single-signer tx, in
+    /// the order of the public keys that have signed.
+    pub signatures: Vec<Binary>,
 }
 
 /// Body of the transaction. This is what the sender needs to sign.
@@ -32,9 +80,34 @@ pub struct TxBody {
     pub chain_id: String,
 
     /// The sender's sequence number.
-    /// Used to prvent replay attacks.
+    /// Used to prvent replay attacks. Ignored if the tx is unordered.
     pub sequence: u64,
 
+    /// If provided, the tx is unordered: instead of the sequence number,
+    /// replay attacks are prevented by this nonce, which must not have been
+    /// used by any of the sender's unexpired unordered txs.
+    ///
+    /// Unordered txs must come with a timeout height no more than
+    /// `MAX_UNORDERED_TX_TIMEOUT` blocks ahead, and the sender must have opted
+    /// into unordered txs.
+    pub nonce: Option<u64>,
+
+    /// If provided, the tx is rejected if it is included in a block higher than
+    /// this height. Prevents signed txs from staying valid indefinitely.
+    pub timeout_height: Option<u64>,
+
+    /// An arbitrary note attached to the tx, e.g. to identify deposits to
+    /// exchanges. Can't be longer than `MAX_MEMO_LENGTH` bytes.
+    pub memo: Option<String>,
+
+    /// Coins to be paid as fee, which are transferred to the distribution
+    /// contract by the bank contract.
+    #[serde(default)]
+    pub fee: Vec<Coin>,
+
+    /// The account who pays the fee. Defaults to the sender if not provided.
+    pub fee_payer: Option<String>,
+
     /// Wasm messages to be executed in order
     pub msgs: Vec<SdkMsg>,
 }
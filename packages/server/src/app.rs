@@ -1,14 +1,19 @@
 use std::sync::mpsc::{channel, Receiver, Sender};
 
 use cosmwasm_std::{Attribute as WasmAttribute, BlockInfo, Event as WasmEvent, Timestamp};
-use cw_sdk::{GenesisState, SdkQuery, Tx};
+use cw_sdk::{hash::sha256, GenesisState, SdkQuery, Tx};
+use cw_state_machine::query::QueryHandle;
 use tendermint_proto::abci::{self, Event, EventAttribute};
 
 use crate::AppCommand;
 
-#[derive(Clone, Debug)]
+/// The ABCI server's side of the app. Requests on the consensus connection are
+/// sent to the `AppDriver` as commands, whereas queries are handled directly,
+/// on the query connection's own thread, against the last committed state.
+#[derive(Clone)]
 pub struct App {
     pub cmd_tx: Sender<AppCommand>,
+    pub query_handle: QueryHandle,
 }
 
 impl App {
@@ -95,21 +100,13 @@ impl tendermint_abci::Application for App {
 
         match &path[0] {
             &"app" => {
-                let (result_tx, result_rx) = channel();
-
                 let query: SdkQuery = serde_json::from_slice(&request.data).unwrap_or_else(|err| {
                     panic!("failed to deserialize query message: {err}");
                 });
 
-                let result = self.execute_command(
-                    AppCommand::Query {
-                        query,
-                        result_tx,
-                    },
-                    &result_rx,
-                );
-
-                match result {
+                // queries don't wait for the driver, which may be executing a
+                // block in the meantime
+                match self.query_handle.query(query) {
                     Ok(response) => abci::ResponseQuery {
                         code: 0,
                         value: response.to_vec().into(),
@@ -201,26 +198,37 @@ impl tendermint_abci::Application for App {
             panic!("failed to deserialize tx: {err}");
         });
 
-        let result = self.execute_command(
+        let (result, debug_messages) = self.execute_command(
             AppCommand::DeliverTx {
                 tx,
+                tx_hash: sha256(&request.tx).into(),
                 result_tx,
             },
             &result_rx,
         );
 
+        // the debug messages printed by contracts, if captured, are returned
+        // in the `info` field, regardless of whether the tx is successful
+        let info = if debug_messages.is_empty() {
+            String::new()
+        } else {
+            serde_json::to_string(&debug_messages).unwrap()
+        };
+
         match result {
             // TODO: what should we put in `data` and `log` fields?
             // for now i just serialize the events into a JSON string as log
             Ok(events) => abci::ResponseDeliverTx {
                 code: 0,
                 log: serde_json::to_string(&events).unwrap(),
+                info,
                 events: wasm_event_to_abci(events),
                 ..Default::default()
             },
             Err(error) => abci::ResponseDeliverTx {
                 code: 1,
                 log: error.to_string(),
+                info,
                 ..Default::default()
             },
         }
@@ -1,8 +1,8 @@
 use std::sync::mpsc::Sender;
 
-use cosmwasm_std::{Binary, BlockInfo, Event};
+use cosmwasm_std::{BlockInfo, Event, HexBinary};
 
-use cw_sdk::{hash::HASH_LENGTH, GenesisState, SdkQuery, Tx};
+use cw_sdk::{hash::HASH_LENGTH, DebugMessage, GenesisState, Tx};
 use cw_state_machine::error::Result as StateMachineResult;
 
 /// The ABCI server and the driver maintains a channel between them, and
@@ -22,12 +22,6 @@ pub enum AppCommand {
         result_tx: Sender<StateMachineResult<[u8; HASH_LENGTH]>>,
     },
 
-    /// Provide the query message, returns the query response in binary format.
-    Query {
-        query: SdkQuery,
-        result_tx: Sender<StateMachineResult<Binary>>,
-    },
-
     /// Provide chain id, block height and time, return events emitted during
     /// the begin block process.
     BeginBlock {
@@ -35,10 +29,13 @@ pub enum AppCommand {
         result_tx: Sender<StateMachineResult<Vec<Event>>>,
     },
 
-    /// Provide a tx, returns the events emitted during tx execution.
+    /// Provide a tx and its hash, returns the events emitted during tx
+    /// execution, and the debug messages printed by contracts, if capturing
+    /// them is enabled.
     DeliverTx {
         tx: Tx,
-        result_tx: Sender<StateMachineResult<Vec<Event>>>,
+        tx_hash: HexBinary,
+        result_tx: Sender<(StateMachineResult<Vec<Event>>, Vec<DebugMessage>)>,
     },
 
     /// Returns the block height and app hash that was committed.
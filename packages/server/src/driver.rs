@@ -5,8 +5,9 @@ use cw_state_machine::StateMachine;
 use crate::AppCommand;
 
 /// The driver is a wrapper around the actual state machine.
-/// It maintains a channel with the ABCI server, and performs actions or queries
-/// on the state machine on request for the ABCI server.
+/// It maintains a channel with the ABCI server, and performs actions on the state
+/// machine on request for the ABCI server. Queries don't go through the driver;
+/// see `App`.
 pub struct AppDriver {
     pub state_machine: StateMachine,
     pub cmd_rx: Receiver<AppCommand>,
@@ -24,18 +25,15 @@ impl AppDriver {
                     gen_state,
                     result_tx,
                 } => result_tx.send(self.state_machine.init_chain(chain_id, gen_state)).unwrap(),
-                AppCommand::Query {
-                    query,
-                    result_tx,
-                } => result_tx.send(self.state_machine.query(query)).unwrap(),
                 AppCommand::BeginBlock {
                     block,
                     result_tx,
                 } => result_tx.send(self.state_machine.begin_block(block)).unwrap(),
                 AppCommand::DeliverTx {
                     tx,
+                    tx_hash,
                     result_tx,
-                } => result_tx.send(self.state_machine.deliver_tx(tx)).unwrap(),
+                } => result_tx.send(self.state_machine.deliver_tx(tx, tx_hash)).unwrap(),
                 AppCommand::Commit {
                     result_tx,
                 } => result_tx.send(self.state_machine.commit()).unwrap(),
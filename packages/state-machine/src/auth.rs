@@ -1,11 +1,12 @@
-use cosmwasm_std::{Addr, BlockInfo, Storage};
+use cosmwasm_std::{Addr, Binary, BlockInfo, Coin, Order, StdResult, Storage};
+use cw_storage_plus::PrefixBound;
 use k256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
 
-use cw_sdk::{address, Account, Tx};
+use cw_sdk::{address, Account, Tx, MAX_MEMO_LENGTH, MAX_UNORDERED_TX_TIMEOUT};
 
 use crate::{
     error::{Error, Result},
-    state::ACCOUNTS,
+    state::{ACCOUNTS, ALLOWANCES, UNORDERED_NONCES, UNORDERED_NONCES_BY_TIMEOUT},
 };
 
 /// The response type of `authenticate_tx` function.
@@ -14,7 +15,20 @@ pub struct Sender {
     pub account: Account<Addr>,
 }
 
-/// Authenticate the signer's address, pubkey, signature, sequence, and chain id.
+/// The key(s) that must sign a tx, depending on the sender's account type.
+enum Signer {
+    Single {
+        pubkey: Binary,
+    },
+    Multi {
+        pubkeys: Vec<Binary>,
+        threshold: u32,
+    },
+}
+
+/// Authenticate the signer's address, pubkey, signature, sequence (or nonce, if
+/// the tx is unordered), and chain id, and check the tx's timeout height and
+/// memo length.
 /// Return error if any one fails.
 /// Returns the sender address and account info if succeeds.
 pub fn authenticate_tx(store: &dyn Storage, pending_block: &BlockInfo, tx: &Tx) -> Result<Sender> {
@@ -22,7 +36,7 @@ pub fn authenticate_tx(store: &dyn Storage, pending_block: &BlockInfo, tx: &Tx)
     let sender_addr = address::validate(sender)?;
 
     // find the user's account
-    let (pubkey, mut sequence) = match ACCOUNTS.may_load(store, &sender_addr)? {
+    let (signer, mut sequence, unordered) = match ACCOUNTS.may_load(store, &sender_addr)? {
         // If the sender account is a contract, throw error because contracts
         // can't sign txs.
         Some(Account::Contract {
@@ -37,6 +51,7 @@ pub fn authenticate_tx(store: &dyn Storage, pending_block: &BlockInfo, tx: &Tx)
         Some(Account::Base {
             pubkey,
             sequence,
+            unordered,
         }) => {
             if let Some(sender_pubkey) = &tx.pubkey {
                 if pubkey != *sender_pubkey {
@@ -44,23 +59,84 @@ pub fn authenticate_tx(store: &dyn Storage, pending_block: &BlockInfo, tx: &Tx)
                 }
             }
 
-            (pubkey, sequence)
+            (
+                Signer::Single {
+                    pubkey,
+                },
+                sequence,
+                unordered,
+            )
+        },
+
+        // Same for multisig accounts: the pubkeys and threshold, if included in
+        // the tx, must match the ones stored on-chain.
+        Some(Account::Multisig {
+            pubkeys,
+            threshold,
+            sequence,
+            unordered,
+        }) => {
+            if let Some(key) = tx.multi_signature.as_ref().and_then(|ms| ms.key.as_ref()) {
+                if sorted(&key.pubkeys) != pubkeys || key.threshold != threshold {
+                    return Err(Error::invalid_multisig("pubkeys or threshold mismatch"));
+                }
+            }
+
+            (
+                Signer::Multi {
+                    pubkeys,
+                    threshold,
+                },
+                sequence,
+                unordered,
+            )
         },
 
         // If not found, meaning it's the first time the account every sends a
-        // tx, use the pubkey provided by the tx and initialize sequence to be 0.
+        // tx, use the pubkey (or the multisig pubkeys and threshold) provided
+        // by the tx and initialize sequence to be 0.
+        // The account has not opted into unordered txs.
         // Note, the pubkey must match the sender address.
         None => {
-            let Some(pubkey) = &tx.pubkey else {
-                return Err(Error::account_not_found(sender));
-            };
+            if let Some(multi_signature) = &tx.multi_signature {
+                let Some(key) = &multi_signature.key else {
+                    return Err(Error::account_not_found(sender));
+                };
 
-            let address = address::derive_from_pubkey(pubkey.as_slice())?;
-            if *sender != address {
-                return Err(Error::address_mismatch(address, sender));
-            }
+                let address = address::derive_from_multisig(&key.pubkeys, key.threshold)?;
+                if *sender != address {
+                    return Err(Error::address_mismatch(address, sender));
+                }
+
+                let pubkeys = sorted(&key.pubkeys);
+                validate_multisig(&pubkeys, key.threshold)?;
+
+                (
+                    Signer::Multi {
+                        pubkeys,
+                        threshold: key.threshold,
+                    },
+                    0,
+                    false,
+                )
+            } else {
+                let Some(pubkey) = &tx.pubkey else {
+                    return Err(Error::account_not_found(sender));
+                };
+
+                let address = address::derive_from_pubkey(pubkey.as_slice())?;
+                if *sender != address {
+                    return Err(Error::address_mismatch(address, sender));
+                }
 
-            (pubkey.clone(), 0)
+                (
+                    Signer::Single {
+                        pubkey: pubkey.clone(),
+                    },
+                    0,
+                    false,
+                )
+            }
         },
     };
 
@@ -69,27 +145,477 @@ pub fn authenticate_tx(store: &dyn Storage, pending_block: &BlockInfo, tx: &Tx)
         return Err(Error::chain_id_mismatch(&pending_block.chain_id, &tx.body.chain_id));
     }
 
-    // the account sequence mush match
-    sequence += 1;
-    if sequence != tx.body.sequence {
-        return Err(Error::sequence_mismatch(sender, sequence, tx.body.sequence));
+    // the tx must not have timed out
+    if let Some(timeout_height) = tx.body.timeout_height {
+        if pending_block.height > timeout_height {
+            return Err(Error::tx_timed_out(timeout_height, pending_block.height));
+        }
     }
 
-    // verify the signature
+    // the memo must not be too long
+    if let Some(memo) = &tx.body.memo {
+        if memo.len() > MAX_MEMO_LENGTH {
+            return Err(Error::memo_too_long(memo.len(), MAX_MEMO_LENGTH));
+        }
+    }
+
+    if let Some(nonce) = tx.body.nonce {
+        // for unordered txs, the account must have opted in, the timeout
+        // height must be provided and not too far ahead, and the nonce must not
+        // have been used. the sequence is ignored and not incremented
+        if !unordered {
+            return Err(Error::unordered_txs_disabled(sender));
+        }
+
+        match tx.body.timeout_height {
+            Some(timeout_height)
+                if timeout_height <= pending_block.height + MAX_UNORDERED_TX_TIMEOUT => {},
+            _ => return Err(Error::invalid_unordered_timeout(MAX_UNORDERED_TX_TIMEOUT)),
+        }
+
+        if UNORDERED_NONCES.has(store, (&sender_addr, nonce)) {
+            return Err(Error::nonce_used(sender, nonce));
+        }
+    } else {
+        // the account sequence mush match
+        sequence += 1;
+        if sequence != tx.body.sequence {
+            return Err(Error::sequence_mismatch(sender, sequence, tx.body.sequence));
+        }
+    }
+
+    // verify the signature(s)
     // the content to be signed is (the sha256 hash of) the tx body
     let body_bytes = serde_json::to_vec(&tx.body)?;
-    let signature = Signature::try_from(tx.signature.as_slice())?;
 
     // if signature is valid, return the sender address and updated account info
     // otherwise, return error
-    VerifyingKey::from_sec1_bytes(pubkey.as_slice())?
-        .verify(&body_bytes, &signature)
-        .map(|_| Sender {
-            address: sender_addr,
-            account: Account::Base {
+    let account = match signer {
+        Signer::Single {
+            pubkey,
+        } => {
+            if tx.multi_signature.is_some() {
+                return Err(Error::invalid_multisig("sender is not a multisig account"));
+            }
+
+            let signature = Signature::try_from(tx.signature.as_slice())?;
+            VerifyingKey::from_sec1_bytes(pubkey.as_slice())?.verify(&body_bytes, &signature)?;
+
+            Account::Base {
                 pubkey,
                 sequence,
+                unordered,
+            }
+        },
+        Signer::Multi {
+            pubkeys,
+            threshold,
+        } => {
+            let Some(multi_signature) = &tx.multi_signature else {
+                return Err(Error::invalid_multisig("missing multi-signature"));
+            };
+
+            verify_multi_signature(
+                &pubkeys,
+                threshold,
+                &multi_signature.bitmap,
+                &multi_signature.signatures,
+                &body_bytes,
+            )?;
+
+            Account::Multisig {
+                pubkeys,
+                threshold,
+                sequence,
+                unordered,
+            }
+        },
+    };
+
+    Ok(Sender {
+        address: sender_addr,
+        account,
+    })
+}
+
+/// Return a sorted copy of the given pubkeys. Multisig accounts store their
+/// pubkeys in sorted order, which is also the order used by the bitmap.
+fn sorted(pubkeys: &[Binary]) -> Vec<Binary> {
+    let mut pubkeys = pubkeys.to_vec();
+    pubkeys.sort();
+    pubkeys
+}
+
+/// Check that the pubkeys (which must be sorted) and threshold of a multisig
+/// account to be created are valid.
+fn validate_multisig(pubkeys: &[Binary], threshold: u32) -> Result<()> {
+    if threshold == 0 || threshold as usize > pubkeys.len() {
+        return Err(Error::invalid_multisig(format!(
+            "threshold must be between 1 and the number of pubkeys ({}), found {threshold}",
+            pubkeys.len(),
+        )));
+    }
+
+    if pubkeys.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(Error::invalid_multisig("duplicate pubkeys"));
+    }
+
+    for pubkey in pubkeys {
+        VerifyingKey::from_sec1_bytes(pubkey.as_slice())?;
+    }
+
+    Ok(())
+}
+
+/// Verify the signatures of a multisig account's signers.
+///
+/// The bitmap indicates which of the pubkeys have signed; the signatures must
+/// be in the same order as those pubkeys, and there must be at least as many as
+/// the threshold.
+fn verify_multi_signature(
+    pubkeys: &[Binary],
+    threshold: u32,
+    bitmap: &[u8],
+    signatures: &[Binary],
+    body_bytes: &[u8],
+) -> Result<()> {
+    if bitmap.len() != (pubkeys.len() + 7) / 8 {
+        return Err(Error::invalid_multisig("incorrect bitmap length"));
+    }
+
+    let signers = pubkeys
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| bitmap[i / 8] & (0x80 >> (i % 8)) != 0)
+        .map(|(_, pubkey)| pubkey)
+        .collect::<Vec<_>>();
+
+    if signers.len() != signatures.len() {
+        return Err(Error::invalid_multisig("number of signatures does not match the bitmap"));
+    }
+
+    if signers.len() < threshold as usize {
+        return Err(Error::threshold_not_met(threshold, signers.len()));
+    }
+
+    for (pubkey, signature) in signers.into_iter().zip(signatures) {
+        let signature = Signature::try_from(signature.as_slice())?;
+        VerifyingKey::from_sec1_bytes(pubkey.as_slice())?.verify(body_bytes, &signature)?;
+    }
+
+    Ok(())
+}
+
+/// Authenticate the account who pays the tx's fee, and return its address.
+///
+/// If the tx doesn't specify a fee payer, the sender pays. Otherwise, the fee
+/// payer must either have signed the tx, or have granted the sender a fee
+/// allowance, in which case the allowance is checked and its spend limit is
+/// reduced by the fee.
+pub fn authenticate_fee_payer(
+    store: &mut dyn Storage,
+    pending_block: &BlockInfo,
+    tx: &Tx,
+    sender_addr: &Addr,
+) -> Result<Addr> {
+    let Some(fee_payer) = &tx.body.fee_payer else {
+        return Ok(sender_addr.clone());
+    };
+
+    let fee_payer_addr = address::validate(fee_payer)?;
+    if fee_payer_addr == *sender_addr {
+        return Ok(fee_payer_addr);
+    }
+
+    // if the fee payer has signed the tx, it has agreed to pay the fee, and no
+    // allowance is needed
+    if let Some(signature) = &tx.fee_payer_signature {
+        let pubkey = match ACCOUNTS.may_load(store, &fee_payer_addr)? {
+            Some(Account::Base {
+                pubkey,
+                ..
+            }) => pubkey,
+            Some(Account::Contract {
+                ..
+            }) => {
+                return Err(Error::account_is_contract(fee_payer));
+            },
+            Some(Account::Multisig {
+                ..
+            }) => {
+                return Err(Error::invalid_multisig("multisig fee payers must use allowances"));
             },
-        })
-        .map_err(Error::from)
+            None => {
+                return Err(Error::account_not_found(fee_payer));
+            },
+        };
+
+        let body_bytes = serde_json::to_vec(&tx.body)?;
+        let signature = Signature::try_from(signature.as_slice())?;
+
+        return VerifyingKey::from_sec1_bytes(pubkey.as_slice())?
+            .verify(&body_bytes, &signature)
+            .map(|_| fee_payer_addr)
+            .map_err(Error::from);
+    }
+
+    // otherwise, the fee payer must have granted the sender an allowance
+    let mut allowance = ALLOWANCES
+        .may_load(store, (&fee_payer_addr, sender_addr))?
+        .ok_or_else(|| Error::allowance_not_found(fee_payer, sender_addr))?;
+
+    if let Some(expiration) = allowance.expiration {
+        if pending_block.height > expiration {
+            return Err(Error::allowance_expired(fee_payer, sender_addr, expiration));
+        }
+    }
+
+    if let Some(allowed_msgs) = &allowance.allowed_msgs {
+        if let Some(msg) = tx.body.msgs.iter().find(|msg| !allowed_msgs.contains(msg.kind())) {
+            return Err(Error::msg_not_allowed(msg.kind()));
+        }
+    }
+
+    if let Some(spend_limit) = &mut allowance.spend_limit {
+        deduct_coins(spend_limit, &tx.body.fee)?;
+    }
+
+    ALLOWANCES.save(store, (&fee_payer_addr, sender_addr), &allowance)?;
+
+    Ok(fee_payer_addr)
+}
+
+/// Deduct the given coins from an allowance's spend limit. Error if the spend
+/// limit doesn't contain enough of any of the coins.
+fn deduct_coins(spend_limit: &mut [Coin], coins: &[Coin]) -> Result<()> {
+    for coin in coins {
+        let limit = spend_limit
+            .iter_mut()
+            .find(|limit| limit.denom == coin.denom)
+            .ok_or_else(|| {
+                Error::spend_limit_exceeded(format!("denom {} not allowed", coin.denom))
+            })?;
+        limit.amount = limit
+            .amount
+            .checked_sub(coin.amount)
+            .map_err(Error::spend_limit_exceeded)?;
+    }
+    Ok(())
+}
+
+/// Record the nonce of an unordered tx as used, until the tx times out.
+pub fn record_nonce(
+    store: &mut dyn Storage,
+    sender_addr: &Addr,
+    nonce: u64,
+    timeout_height: u64,
+) -> Result<()> {
+    UNORDERED_NONCES.save(store, (sender_addr, nonce), &timeout_height)?;
+    UNORDERED_NONCES_BY_TIMEOUT.update(store, (timeout_height, sender_addr), |nonces| -> Result<_> {
+        let mut nonces = nonces.unwrap_or_default();
+        nonces.insert(nonce);
+        Ok(nonces)
+    })?;
+    Ok(())
+}
+
+/// Delete the nonces of unordered txs that have timed out as of the given
+/// block height, i.e. whose timeout heights are lower than it. Such txs are
+/// rejected by their timeout heights, so their nonces no longer need to be
+/// stored.
+pub fn prune_nonces(store: &mut dyn Storage, height: u64) -> Result<()> {
+    let expired = UNORDERED_NONCES_BY_TIMEOUT
+        .prefix_range(store, None, Some(PrefixBound::exclusive(height)), Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    for ((timeout_height, sender_addr), nonces) in expired {
+        for nonce in nonces {
+            UNORDERED_NONCES.remove(store, (&sender_addr, nonce));
+        }
+        UNORDERED_NONCES_BY_TIMEOUT.remove(store, (timeout_height, &sender_addr));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::{coins, testing::MockStorage, Timestamp};
+    use cw_sdk::{Allowance, MultiSignature, MultisigKey, SdkMsg, TxBody};
+    use k256::ecdsa::{signature::Signer as _, SigningKey};
+
+    use super::*;
+
+    fn mock_tx(sender: &Addr, fee_payer: &Addr, fee: u128, msg: SdkMsg) -> Tx {
+        Tx {
+            body: TxBody {
+                sender: sender.into(),
+                chain_id: "cw-test".into(),
+                sequence: 1,
+                nonce: None,
+                timeout_height: None,
+                memo: None,
+                fee: coins(fee, "uatom"),
+                fee_payer: Some(fee_payer.into()),
+                msgs: vec![msg],
+            },
+            pubkey: None,
+            signature: Binary::default(),
+            multi_signature: None,
+            fee_payer_signature: None,
+        }
+    }
+
+    fn mock_block(height: u64) -> BlockInfo {
+        BlockInfo {
+            height,
+            time: Timestamp::default(),
+            chain_id: "cw-test".into(),
+        }
+    }
+
+    #[test]
+    fn using_allowances() {
+        let mut store = MockStorage::new();
+
+        let granter = address::derive_from_label("granter").unwrap();
+        let grantee = address::derive_from_label("grantee").unwrap();
+        let execute_msg = SdkMsg::Execute {
+            contract: "bank".into(),
+            msg: Default::default(),
+            funds: vec![],
+        };
+        let store_code_msg = SdkMsg::StoreCode {
+            wasm_byte_code: Binary::default(),
+        };
+
+        // no allowance has been granted
+        let tx = mock_tx(&grantee, &granter, 60, execute_msg.clone());
+        let err = authenticate_fee_payer(&mut store, &mock_block(1), &tx, &grantee).unwrap_err();
+        assert!(matches!(err, Error::AllowanceNotFound { .. }));
+
+        ALLOWANCES
+            .save(&mut store, (&granter, &grantee), &Allowance {
+                spend_limit: Some(coins(100, "uatom")),
+                expiration: Some(10),
+                allowed_msgs: Some(["execute".to_string()].into()),
+            })
+            .unwrap();
+
+        // the fee should be deducted from the spend limit
+        let fee_payer = authenticate_fee_payer(&mut store, &mock_block(1), &tx, &grantee).unwrap();
+        assert_eq!(fee_payer, granter);
+        let allowance = ALLOWANCES.load(&store, (&granter, &grantee)).unwrap();
+        assert_eq!(allowance.spend_limit, Some(coins(40, "uatom")));
+
+        // the remaining spend limit is not enough
+        let err = authenticate_fee_payer(&mut store, &mock_block(1), &tx, &grantee).unwrap_err();
+        assert!(matches!(err, Error::SpendLimitExceeded { .. }));
+
+        // message type not allowed
+        let tx = mock_tx(&grantee, &granter, 10, store_code_msg);
+        let err = authenticate_fee_payer(&mut store, &mock_block(1), &tx, &grantee).unwrap_err();
+        assert!(matches!(err, Error::MsgNotAllowed { .. }));
+
+        // allowance expired
+        let tx = mock_tx(&grantee, &granter, 10, execute_msg);
+        let err = authenticate_fee_payer(&mut store, &mock_block(11), &tx, &grantee).unwrap_err();
+        assert!(matches!(err, Error::AllowanceExpired { .. }));
+    }
+
+    #[test]
+    fn authenticating_multisig_txs() {
+        let store = MockStorage::new();
+
+        // a 2-of-3 multisig, with the signers sorted by their pubkeys
+        let mut signers = (1..=3u8)
+            .map(|i| {
+                let sk = SigningKey::from_bytes(&[i; 32].into()).unwrap();
+                let pubkey = Binary::from(sk.verifying_key().to_sec1_bytes().to_vec());
+                (pubkey, sk)
+            })
+            .collect::<Vec<_>>();
+        signers.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let pubkeys = signers.iter().map(|(pubkey, _)| pubkey.clone()).collect::<Vec<_>>();
+        let sender = address::derive_from_multisig(&pubkeys, 2).unwrap();
+
+        let mut tx = mock_tx(&sender, &sender, 0, SdkMsg::SetUnorderedTxs {
+            enabled: true,
+        });
+        let body_bytes = serde_json::to_vec(&tx.body).unwrap();
+        let sign = |i: usize| -> Binary {
+            let signature: Signature = signers[i].1.sign(&body_bytes);
+            signature.to_vec().into()
+        };
+        let multi_signature = |threshold: u32, bitmap: u8, signatures: Vec<Binary>| {
+            Some(MultiSignature {
+                key: Some(MultisigKey {
+                    pubkeys: pubkeys.clone(),
+                    threshold,
+                }),
+                bitmap: vec![bitmap].into(),
+                signatures,
+            })
+        };
+
+        // the threshold doesn't match the one the address is derived from
+        tx.multi_signature = multi_signature(3, 0b1110_0000, vec![sign(0), sign(1), sign(2)]);
+        let result = authenticate_tx(&store, &mock_block(1), &tx);
+        assert!(matches!(result, Err(Error::AddressMismatch { .. })));
+
+        // only one signer has signed
+        tx.multi_signature = multi_signature(2, 0b1000_0000, vec![sign(0)]);
+        let result = authenticate_tx(&store, &mock_block(1), &tx);
+        assert!(matches!(result, Err(Error::ThresholdNotMet { .. })));
+
+        // the signatures don't match the bitmap
+        tx.multi_signature = multi_signature(2, 0b1100_0000, vec![sign(0), sign(2)]);
+        assert!(authenticate_tx(&store, &mock_block(1), &tx).is_err());
+
+        // two signers have signed
+        tx.multi_signature = multi_signature(2, 0b1010_0000, vec![sign(0), sign(2)]);
+        let result = authenticate_tx(&store, &mock_block(1), &tx);
+        assert!(matches!(
+            result,
+            Ok(Sender {
+                account: Account::Multisig {
+                    threshold: 2,
+                    sequence: 1,
+                    ..
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn pruning_nonces() {
+        let mut store = MockStorage::new();
+
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+
+        record_nonce(&mut store, &alice, 1, 10).unwrap();
+        record_nonce(&mut store, &alice, 2, 10).unwrap();
+        record_nonce(&mut store, &bob, 1, 10).unwrap();
+        record_nonce(&mut store, &bob, 2, 11).unwrap();
+
+        // at height 10, none of the txs has timed out
+        prune_nonces(&mut store, 10).unwrap();
+        assert!(UNORDERED_NONCES.has(&store, (&alice, 1)));
+        assert!(UNORDERED_NONCES.has(&store, (&alice, 2)));
+        assert!(UNORDERED_NONCES.has(&store, (&bob, 1)));
+        assert!(UNORDERED_NONCES.has(&store, (&bob, 2)));
+
+        // at height 11, the txs with timeout height 10 have timed out
+        prune_nonces(&mut store, 11).unwrap();
+        assert!(!UNORDERED_NONCES.has(&store, (&alice, 1)));
+        assert!(!UNORDERED_NONCES.has(&store, (&alice, 2)));
+        assert!(!UNORDERED_NONCES.has(&store, (&bob, 1)));
+        assert!(UNORDERED_NONCES.has(&store, (&bob, 2)));
+        assert!(!UNORDERED_NONCES_BY_TIMEOUT.has(&store, (10, &alice)));
+        assert!(!UNORDERED_NONCES_BY_TIMEOUT.has(&store, (10, &bob)));
+        assert!(UNORDERED_NONCES_BY_TIMEOUT.has(&store, (11, &bob)));
+    }
 }
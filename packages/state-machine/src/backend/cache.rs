@@ -0,0 +1,49 @@
+use cosmwasm_std::HexBinary;
+use cosmwasm_vm::{Backend, Cache, Checksum, Instance, InstanceOptions, VmResult};
+
+use super::{BackendApi, BackendQuerier, ContractSubstore, DebugLog};
+
+/// The VM cache, which holds the compiled wasm modules, either in memory or on
+/// disk, so that they don't need to be recompiled each time a contract is
+/// called.
+pub type VmCache = Cache<BackendApi, ContractSubstore, BackendQuerier>;
+
+/// The wasm instance type created from the VM cache.
+pub type VmInstance = Instance<BackendApi, ContractSubstore, BackendQuerier>;
+
+/// Create a wasm instance of the code with the given checksum, using the VM
+/// cache. The code must have already been saved in the cache.
+///
+/// The instance errors with out-of-gas once it has consumed `gas_limit` units
+/// of CosmWasm gas.
+///
+/// If a debug log is provided, the debug messages printed by the contract are
+/// recorded in it. Otherwise, they are printed to stderr.
+pub fn create_instance(
+    cache: &VmCache,
+    checksum: &HexBinary,
+    storage: ContractSubstore,
+    gas_limit: u64,
+    debug_log: Option<DebugLog>,
+) -> VmResult<VmInstance> {
+    let contract_addr = storage.contract_addr().clone();
+
+    let mut instance = cache.get_instance(
+        &Checksum::try_from(checksum.as_slice())?,
+        Backend {
+            api: BackendApi,
+            storage,
+            querier: BackendQuerier,
+        },
+        InstanceOptions {
+            gas_limit,
+            print_debug: debug_log.is_none(),
+        },
+    )?;
+
+    if let Some(debug_log) = debug_log {
+        instance.set_debug_handler(move |message, _info| debug_log.record(&contract_addr, message));
+    }
+
+    Ok(instance)
+}
@@ -0,0 +1,78 @@
+use std::{cell::RefCell, rc::Rc};
+
+use cosmwasm_std::Addr;
+use cw_sdk::DebugMessage;
+
+/// Collects the debug messages printed by contracts during the execution of a
+/// tx, so that they can be returned along with the tx's result instead of
+/// being printed to the node's output, interleaved across txs.
+///
+/// Cloning a debug log gives another handle to the same collection.
+#[derive(Clone, Debug, Default)]
+pub struct DebugLog {
+    inner: Rc<RefCell<DebugLogInner>>,
+}
+
+#[derive(Debug, Default)]
+struct DebugLogInner {
+    msg_index: Option<u32>,
+    messages: Vec<DebugMessage>,
+}
+
+impl DebugLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attribute the debug messages printed from now on to the tx's message of
+    /// the given index.
+    pub fn set_msg_index(&self, msg_index: u32) {
+        self.inner.borrow_mut().msg_index = Some(msg_index);
+    }
+
+    /// Record a debug message printed by the given contract.
+    pub fn record(&self, contract: &Addr, message: &str) {
+        let mut inner = self.inner.borrow_mut();
+        let msg_index = inner.msg_index;
+        inner.messages.push(DebugMessage {
+            msg_index,
+            contract: contract.clone(),
+            message: message.into(),
+        });
+    }
+
+    /// Return the debug messages recorded so far, in order, leaving the log
+    /// empty.
+    pub fn take(&self) -> Vec<DebugMessage> {
+        self.inner.take().messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributing_messages() {
+        let debug_log = DebugLog::new();
+        let bank = Addr::unchecked("bank");
+        let counter = Addr::unchecked("counter");
+
+        // printed while deducting the fee, before any message is executed
+        debug_log.record(&bank, "deducting fee");
+        debug_log.set_msg_index(0);
+        debug_log.record(&counter, "incrementing");
+        debug_log.set_msg_index(1);
+        debug_log.record(&bank, "transferring");
+
+        let messages = debug_log.take();
+        assert_eq!(
+            messages.iter().map(|m| (m.msg_index, m.message.as_str())).collect::<Vec<_>>(),
+            vec![(None, "deducting fee"), (Some(0), "incrementing"), (Some(1), "transferring")],
+        );
+        assert_eq!(messages[1].contract, counter);
+
+        // taking the messages resets the log
+        assert!(debug_log.take().is_empty());
+    }
+}
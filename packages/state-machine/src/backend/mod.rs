@@ -1,8 +1,12 @@
 mod api;
+mod cache;
+mod debug;
 mod querier;
 mod storage;
 
 pub use api::BackendApi;
+pub use cache::{create_instance, VmCache, VmInstance};
+pub use debug::DebugLog;
 pub use querier::BackendQuerier;
 pub use storage::ContractSubstore;
 
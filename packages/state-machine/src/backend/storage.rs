@@ -3,56 +3,115 @@ use std::collections::HashMap;
 use cosmwasm_std::{Addr, Order, Record, Storage};
 use cosmwasm_vm::{BackendError, BackendResult, GasInfo};
 
+use cw_sdk::StorageOp;
 use cw_store::{
-    iterators::MemIter,
+    iterators::RangeCursor,
     prefix::{concat, namespace_upper_bound, trim},
+    Tracer,
 };
 
+use super::into_backend_err;
+use crate::state::update_storage_usage;
+
 /// NOTE: cosmwasm-vm requires the backend store to be of 'static lifetime.
 /// This requirement comes from wasmer so not something we can change.
 ///
 /// We obviously can't borrow a reference of the store with static lifetime,
 /// So it has to be an owned type.
 ///
-/// Here we need both the `store` and `iterators` map be owned.
-pub struct ContractSubstore<T: Storage> {
-    store: T,
+/// Here we need both the `store` and `iterators` map be owned. The iterators
+/// are therefore cursors, which don't borrow the store, and read records from
+/// it a page at a time as the contract advances them.
+///
+/// The store is boxed as a trait object, so that the substore is of the same
+/// type regardless of the underlying store. This is required by the VM cache,
+/// which is generic over the backend storage type.
+///
+/// Each write also updates the contract's storage usage, which is saved in the
+/// underlying store outside of the contract's namespace.
+///
+/// If a tracer is attached, every operation made by the contract is recorded,
+/// with the keys relative to the contract's namespace.
+pub struct ContractSubstore {
+    store: Box<dyn Storage>,
+    contract_addr: Addr,
     namespace: Vec<u8>,
-    iterators: HashMap<u32, MemIter>,
+    iterators: HashMap<u32, RangeCursor>,
+    tracer: Option<Tracer>,
 }
 
-impl<T: Storage> ContractSubstore<T> {
-    pub fn new(store: T, contract_addr: &Addr) -> Self {
+impl ContractSubstore {
+    pub fn new(store: impl Storage + 'static, contract_addr: &Addr) -> Self {
         Self {
-            store,
+            store: Box::new(store),
+            contract_addr: contract_addr.clone(),
             namespace: contract_addr.to_string().into_bytes(),
             iterators: HashMap::new(),
+            tracer: None,
+        }
+    }
+
+    /// Attach a tracer, if provided, to record the operations made by the
+    /// contract.
+    pub fn with_tracer(self, tracer: Option<Tracer>) -> Self {
+        Self {
+            tracer,
+            ..self
         }
     }
 
-    pub fn recycle(self) -> T {
+    pub fn contract_addr(&self) -> &Addr {
+        &self.contract_addr
+    }
+
+    pub fn recycle(self) -> Box<dyn Storage> {
         self.store
     }
 
     fn key(&self, k: &[u8]) -> Vec<u8> {
         concat(&self.namespace, k)
     }
+
+    /// Size of the entry under the given key, not including the namespace, or
+    /// zero if it doesn't exist.
+    fn entry_size(&self, k: &[u8]) -> u64 {
+        self.store
+            .get(&self.key(k))
+            .map(|v| (k.len() + v.len()) as u64)
+            .unwrap_or(0)
+    }
+
+    fn trace(&self, op: impl FnOnce() -> StorageOp) {
+        if let Some(tracer) = &self.tracer {
+            tracer.record(Some(&self.contract_addr), op());
+        }
+    }
 }
 
-impl<T: Storage> cosmwasm_vm::Storage for ContractSubstore<T> {
+impl cosmwasm_vm::Storage for ContractSubstore {
     fn get(&self, key: &[u8]) -> BackendResult<Option<Vec<u8>>> {
         let value = self.store.get(&self.key(key));
+        self.trace(|| StorageOp::get(key, value.as_deref()));
         (Ok(value), GasInfo::free())
     }
 
     fn set(&mut self, key: &[u8], value: &[u8]) -> BackendResult<()> {
+        self.trace(|| StorageOp::set(key, value));
+        let old_size = self.entry_size(key);
         self.store.set(&self.key(key), value);
-        (Ok(()), GasInfo::free())
+        let new_size = (key.len() + value.len()) as u64;
+        let result = update_storage_usage(&mut *self.store, &self.contract_addr, old_size, new_size)
+            .map_err(into_backend_err);
+        (result, GasInfo::free())
     }
 
     fn remove(&mut self, key: &[u8]) -> BackendResult<()> {
+        self.trace(|| StorageOp::remove(key));
+        let old_size = self.entry_size(key);
         self.store.remove(&self.key(key));
-        (Ok(()), GasInfo::free())
+        let result = update_storage_usage(&mut *self.store, &self.contract_addr, old_size, 0)
+            .map_err(into_backend_err);
+        (result, GasInfo::free())
     }
 
     fn scan(
@@ -61,6 +120,8 @@ impl<T: Storage> cosmwasm_vm::Storage for ContractSubstore<T> {
         end: Option<&[u8]>,
         order: Order,
     ) -> BackendResult<u32> {
+        self.trace(|| StorageOp::scan(start, end, order == Order::Descending));
+
         let start = match start {
             Some(s) => concat(&self.namespace, s),
             None => self.namespace.to_vec(),
@@ -71,10 +132,7 @@ impl<T: Storage> cosmwasm_vm::Storage for ContractSubstore<T> {
             None => namespace_upper_bound(&self.namespace),
         };
 
-        let iter = MemIter::new(self
-            .store
-            .range(Some(&start), Some(&end), order)
-            .map(|(k, v)| (trim(&self.namespace, &k), v)));
+        let iter = RangeCursor::new(Some(&start), Some(&end), order);
         let iter_count: u32 = self
             .iterators
             .len()
@@ -89,9 +147,68 @@ impl<T: Storage> cosmwasm_vm::Storage for ContractSubstore<T> {
 
     fn next(&mut self, iterator_id: u32) -> BackendResult<Option<Record>> {
         if let Some(iter) = self.iterators.get_mut(&iterator_id) {
-            (Ok(iter.next()), GasInfo::free())
+            let record = iter.next_from(&*self.store).map(|(k, v)| (trim(&self.namespace, &k), v));
+            (Ok(record), GasInfo::free())
         } else {
             (Err(BackendError::iterator_does_not_exist(iterator_id)), GasInfo::free())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::MockStorage;
+    use cosmwasm_vm::Storage as VmStorage;
+
+    use cw_store::Shared;
+
+    use super::*;
+    use crate::state::STORAGE_USAGE;
+
+    #[test]
+    fn tracking_storage_usage() {
+        let store = Shared::new(MockStorage::new());
+        let contract_addr = Addr::unchecked("contract");
+        let mut substore = ContractSubstore::new(store.share(), &contract_addr);
+
+        let bytes = |store: &Shared<MockStorage>| {
+            STORAGE_USAGE.load(store, &contract_addr).unwrap().bytes
+        };
+
+        // writing a new entry adds its key and value
+        substore.set(b"foo", b"hello").0.unwrap();
+        substore.set(b"bar", b"world").0.unwrap();
+        assert_eq!(bytes(&store), 16);
+
+        // overwriting an entry only counts the difference
+        substore.set(b"foo", b"hi").0.unwrap();
+        assert_eq!(bytes(&store), 13);
+
+        // removing an entry frees it, and removing a nonexistent one is a no-op
+        substore.remove(b"bar").0.unwrap();
+        substore.remove(b"baz").0.unwrap();
+        assert_eq!(bytes(&store), 5);
+    }
+
+    #[test]
+    fn tracing_ops() {
+        let tracer = Tracer::new();
+        let contract_addr = Addr::unchecked("contract");
+        let mut substore = ContractSubstore::new(MockStorage::new(), &contract_addr)
+            .with_tracer(Some(tracer.clone()));
+
+        substore.set(b"foo", b"hello").0.unwrap();
+        substore.get(b"foo").0.unwrap();
+        substore.remove(b"foo").0.unwrap();
+
+        // the ops are recorded under the contract's address, with keys relative
+        // to its namespace
+        let traces = tracer.take();
+        assert!(traces.iter().all(|trace| trace.contract.as_ref() == Some(&contract_addr)));
+        assert_eq!(traces.into_iter().map(|trace| trace.op).collect::<Vec<_>>(), vec![
+            StorageOp::set(b"foo", b"hello"),
+            StorageOp::get(b"foo", Some(b"hello")),
+            StorageOp::remove(b"foo"),
+        ]);
+    }
+}
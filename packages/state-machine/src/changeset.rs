@@ -0,0 +1,166 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use cosmwasm_std::Addr;
+use cw_sdk::StateChange;
+use cw_store::{CommitListener, Op};
+use tracing::warn;
+
+use crate::layout::contract_of;
+
+/// Writes the changes made to the state in each block to a file named after the
+/// block's height, e.g. `123.bin`, in the given directory, so that indexers can
+/// consume every change rather than only the emitted events.
+///
+/// Each file holds the block's changes sorted by key, each a JSON-encoded
+/// `StateChange` prefixed with its length as four big-endian bytes. Keys in a
+/// contract's storage are split into the contract's address and the key
+/// relative to its namespace.
+///
+/// The store is committed once per block starting from height 1, so the
+/// versions of the store it is notified of are the heights of the blocks.
+pub struct ChangesetWriter {
+    dir: PathBuf,
+}
+
+impl ChangesetWriter {
+    /// Create a writer, creating the directory if it doesn't exist yet.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+        })
+    }
+
+    /// Path of the file holding the changes made in the block of the given
+    /// height.
+    pub fn path(&self, height: u64) -> PathBuf {
+        self.dir.join(format!("{height}.bin"))
+    }
+}
+
+impl CommitListener for ChangesetWriter {
+    /// The changesets are not part of the chain's state, so failing to write
+    /// one is logged instead of halting the node.
+    ///
+    /// A file is written under a temporary name first, and then renamed, so
+    /// that it is never read half-written, and is replaced if the block is
+    /// committed again.
+    fn on_commit(&mut self, version: u64, changeset: &BTreeMap<Vec<u8>, Op>) {
+        let path = self.path(version);
+        let result = encode_changeset(changeset).and_then(|bytes| {
+            let tmp_path = path.with_extension("tmp");
+            fs::write(&tmp_path, bytes)?;
+            fs::rename(tmp_path, &path)
+        });
+
+        if let Err(err) = result {
+            warn!(target: "Failed to write changeset", height = version, reason = err.to_string());
+        }
+    }
+}
+
+/// Read the changes from a file written by `ChangesetWriter`.
+pub fn read_changeset(path: impl AsRef<Path>) -> io::Result<Vec<StateChange>> {
+    let bytes = fs::read(path)?;
+
+    let mut changes = vec![];
+    let mut rest = bytes.as_slice();
+    while !rest.is_empty() {
+        let Some(len) = rest.get(..4) else {
+            return Err(ErrorKind::UnexpectedEof.into());
+        };
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        let Some(change) = rest.get(4..4 + len) else {
+            return Err(ErrorKind::UnexpectedEof.into());
+        };
+        changes.push(serde_json::from_slice(change)?);
+        rest = &rest[4 + len..];
+    }
+
+    Ok(changes)
+}
+
+fn encode_changeset(changeset: &BTreeMap<Vec<u8>, Op>) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    for (key, op) in changeset {
+        let change = serde_json::to_vec(&decode_change(key, op))?;
+        bytes.extend((change.len() as u32).to_be_bytes());
+        bytes.extend(change);
+    }
+    Ok(bytes)
+}
+
+/// If the key is in a contract's storage, split it into the contract's address
+/// and the key relative to the contract's namespace.
+fn decode_change(key: &[u8], op: &Op) -> StateChange {
+    let (contract, key) = match contract_of(key) {
+        Some(contract) => (Some(Addr::unchecked(contract)), &key[contract.len()..]),
+        None => (None, key),
+    };
+
+    StateChange {
+        contract,
+        key: key.into(),
+        value: match op {
+            Op::Put(value) => Some(value.clone().into()),
+            Op::Delete => None,
+        },
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use cw_sdk::address;
+
+    use super::*;
+    use crate::state::BLOCK;
+
+    #[test]
+    fn writing_changesets() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = ChangesetWriter::new(dir.path().join("changesets")).unwrap();
+        let contract = address::derive_from_pubkey(b"contract").unwrap();
+
+        let changeset = BTreeMap::from([
+            (BLOCK.as_slice().to_vec(), Op::Put(b"block".to_vec())),
+            ([contract.as_bytes(), b"key1"].concat(), Op::Put(b"value1".to_vec())),
+            ([contract.as_bytes(), b"key2"].concat(), Op::Delete),
+        ]);
+        writer.on_commit(7, &changeset);
+
+        // keys in the contract's storage are decoded, and changes are in the
+        // order of the full keys
+        let changes = read_changeset(writer.path(7)).unwrap();
+        assert_eq!(changes, [
+            StateChange {
+                contract: None,
+                key: b"block".into(),
+                value: Some(b"block".into()),
+            },
+            StateChange {
+                contract: Some(contract.clone()),
+                key: b"key1".into(),
+                value: Some(b"value1".into()),
+            },
+            StateChange {
+                contract: Some(contract),
+                key: b"key2".into(),
+                value: None,
+            },
+        ]);
+
+        // committing the same height again replaces the file
+        writer.on_commit(7, &BTreeMap::new());
+        assert!(read_changeset(writer.path(7)).unwrap().is_empty());
+    }
+}
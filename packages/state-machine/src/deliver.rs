@@ -0,0 +1,462 @@
+use cosmwasm_std::{
+    Addr, BlockInfo, ContractInfo, Env, Event, MessageInfo, Storage, TransactionInfo,
+};
+use cw_sdk::{address, SdkMsg, Tx};
+use cw_store::{Cached, Shared, Tracer};
+
+use crate::{
+    auth,
+    backend::{DebugLog, VmCache},
+    error::{Error, Result},
+    execute::{self, CallStack, GasMeter},
+    schedule,
+    state::{ACCOUNTS, CONFIG},
+};
+
+/// Authenticate a tx and execute its messages on top of the given store,
+/// without writing to the store.
+///
+/// Returns the result of the tx, and a cache holding the state changes to be
+/// flushed by the caller. If the tx is successful, these are all the changes it
+/// has made. If its messages fail, these are only the ones made before they are
+/// executed, i.e. the sender's sequence or nonce, the fee payer's allowance and
+/// the fee deduction, so that a failed tx still pays its fee. If the tx fails
+/// authentication, or its fee can't be paid, no cache is returned, as no state
+/// change is to be made.
+///
+/// If a tracer is provided, the storage operations made by the tx are recorded.
+/// If a debug log is provided, the debug messages printed by contracts are
+/// recorded, attributed to the messages during which they are printed. If a
+/// gas meter is provided, the gas consumed by contracts is counted.
+pub fn execute_tx<S>(
+    vm: &VmCache,
+    block: &BlockInfo,
+    store: S,
+    tx: Tx,
+    tracer: Option<Tracer>,
+    debug_log: Option<DebugLog>,
+    gas_meter: Option<GasMeter>,
+) -> (Result<Vec<Event>>, Option<Cached<S>>)
+where
+    S: Storage + 'static,
+{
+    // make a cache of the store, wrapped in a `Rc<RefCell<T>>` so that it can
+    // be shared as an owned value across the contract calls made by the tx
+    let cache = Shared::new(Cached::new(store).with_tracer(tracer.clone()));
+
+    let (sender_addr, mut call_stack, mut events) =
+        match charge_tx(vm, block, cache.share(), &tx, tracer, debug_log.clone(), gas_meter) {
+            Ok(charged) => charged,
+            Err(err) => return (Err(err), None),
+        };
+
+    // execute the messages under a checkpoint of the cache. if any of them
+    // fails, the state changes made by all of them are rolled back, while the
+    // ones made above are kept
+    let checkpoint = cache.borrow_mut().checkpoint();
+    let result = handle_msgs(
+        vm,
+        &cache,
+        block,
+        &sender_addr,
+        tx.body.msgs,
+        &mut call_stack,
+        debug_log.as_ref(),
+    )
+    .map(|msg_events| {
+        events.extend(msg_events);
+        events
+    });
+
+    match &result {
+        Ok(_) => cache.borrow_mut().commit(checkpoint),
+        Err(_) => cache.borrow_mut().rollback(checkpoint),
+    }
+
+    (result, Some(cache.into_inner()))
+}
+
+/// Authenticate a tx's sender and fee payer, update their accounts, and deduct
+/// the fee.
+///
+/// Returns the sender's address, the call stack to be shared by the tx's
+/// messages, and the events emitted by the fee deduction.
+fn charge_tx<S>(
+    vm: &VmCache,
+    block: &BlockInfo,
+    mut cache: Shared<Cached<S>>,
+    tx: &Tx,
+    tracer: Option<Tracer>,
+    debug_log: Option<DebugLog>,
+    gas_meter: Option<GasMeter>,
+) -> Result<(Addr, CallStack, Vec<Event>)>
+where
+    S: Storage + 'static,
+{
+    // authenticate signature, chain id, sequence, etc.
+    let sender = auth::authenticate_tx(&cache, block, tx)?;
+
+    // update the sender's account in the store
+    ACCOUNTS.save(&mut cache, &sender.address, &sender.account)?;
+
+    // if the tx is unordered, record its nonce so that it can't be replayed.
+    // authentication ensures the timeout height is provided in this case
+    if let (Some(nonce), Some(timeout_height)) = (tx.body.nonce, tx.body.timeout_height) {
+        auth::record_nonce(&mut cache, &sender.address, nonce, timeout_height)?;
+    }
+
+    // all contract calls made by the tx, including the fee deduction, share
+    // the same call stack, with the max depth as of the start of the tx
+    let mut call_stack = CallStack::new(CONFIG.load(&cache)?.max_call_depth)
+        .with_tracer(tracer)
+        .with_debug_log(debug_log)
+        .with_gas_meter(gas_meter);
+
+    // authenticate the fee payer, consuming its allowance if needed, and
+    // deduct the fee from its account
+    let fee_payer = auth::authenticate_fee_payer(&mut cache, block, tx, &sender.address)?;
+    let events = if !tx.body.fee.is_empty() {
+        execute::deduct_fee(&cache, vm, &mut call_stack, block, &fee_payer, &tx.body.fee)?
+    } else {
+        vec![]
+    };
+
+    Ok((sender.address, call_stack, events))
+}
+
+/// Execute a tx's messages in order, sharing the given call stack, and return
+/// the events they emit. Stops at the first message that fails.
+fn handle_msgs<S>(
+    vm: &VmCache,
+    cache: &Shared<Cached<S>>,
+    block: &BlockInfo,
+    sender_addr: &Addr,
+    msgs: Vec<SdkMsg>,
+    call_stack: &mut CallStack,
+    debug_log: Option<&DebugLog>,
+) -> Result<Vec<Event>>
+where
+    S: Storage + 'static,
+{
+    let mut events = vec![];
+
+    for (index, msg) in msgs.into_iter().enumerate() {
+        if let Some(debug_log) = debug_log {
+            debug_log.set_msg_index(index as u32);
+        }
+
+        events.extend(handle_msg(
+            vm,
+            cache.share(),
+            block.clone(),
+            None,
+            sender_addr,
+            msg,
+            call_stack,
+        )?);
+    }
+
+    Ok(events)
+}
+
+/// Execute a single message on top of the given cached store. Contracts are
+/// called using the given call stack, each under a checkpoint of the cache.
+pub fn handle_msg<S>(
+    vm: &VmCache,
+    mut store: Shared<Cached<S>>,
+    block: BlockInfo,
+    transaction: Option<TransactionInfo>,
+    sender_addr: &Addr,
+    msg: SdkMsg,
+    call_stack: &mut CallStack,
+) -> Result<Vec<Event>>
+where
+    S: Storage + 'static,
+{
+    match msg {
+        SdkMsg::UpdateConfig {
+            new_config,
+        } => {
+            let event = execute::update_config(&mut store, sender_addr, new_config)?;
+            Ok(vec![event])
+        },
+        SdkMsg::PinCode {
+            code_id,
+        } => {
+            let event = execute::pin_code(&mut store, sender_addr, code_id)?;
+            Ok(vec![event])
+        },
+        SdkMsg::UnpinCode {
+            code_id,
+        } => {
+            let event = execute::unpin_code(&mut store, sender_addr, code_id)?;
+            Ok(vec![event])
+        },
+        SdkMsg::SetUnorderedTxs {
+            enabled,
+        } => {
+            let event = execute::set_unordered_txs(&mut store, sender_addr, enabled)?;
+            Ok(vec![event])
+        },
+        SdkMsg::GrantAllowance {
+            grantee,
+            allowance,
+        } => {
+            let event = execute::grant_allowance(&mut store, sender_addr, &grantee, allowance)?;
+            Ok(vec![event])
+        },
+        SdkMsg::RevokeAllowance {
+            grantee,
+        } => {
+            let event = execute::revoke_allowance(&mut store, sender_addr, &grantee)?;
+            Ok(vec![event])
+        },
+        SdkMsg::Schedule {
+            msg,
+            at,
+            gas_limit,
+        } => schedule::schedule_job(
+            &store,
+            vm,
+            call_stack,
+            &block,
+            sender_addr,
+            *msg,
+            at,
+            gas_limit,
+        ),
+        SdkMsg::CancelJob {
+            job_id,
+        } => schedule::cancel_job(&store, vm, call_stack, &block, sender_addr, job_id),
+        SdkMsg::StoreCode {
+            wasm_byte_code,
+        } => {
+            let event = execute::store_code(&mut store, &block, sender_addr, &wasm_byte_code)?;
+
+            // save the code to the VM cache, so that it can be instantiated.
+            // if the tx fails later, the code remains in the cache, which
+            // is harmless as the cache is addressed by checksums.
+            vm.save_wasm_unchecked(&wasm_byte_code)?;
+
+            Ok(vec![event])
+        },
+        SdkMsg::Instantiate {
+            code_id,
+            msg,
+            funds,
+            label,
+            admin,
+            non_reentrant,
+        } => {
+            let admin_addr = admin.map(|admin| address::resolve_raw(&admin)).transpose()?;
+
+            if !funds.is_empty() {
+                return Err(Error::FundsUnsupported);
+            }
+            let info = MessageInfo {
+                sender: sender_addr.clone(),
+                funds,
+            };
+
+            let result = execute::instantiate_contract(
+                &store,
+                vm,
+                call_stack,
+                block,
+                transaction,
+                &info,
+                code_id,
+                &serde_json::to_vec(&msg)?,
+                label,
+                admin_addr,
+                non_reentrant,
+            )?
+            .into_result();
+
+            if let Ok(res) = &result {
+                if !res.messages.is_empty() {
+                    return Err(Error::SubmessagesUnsupported);
+                }
+            }
+
+            result.map(|res| res.events).map_err(Error::Contract)
+        },
+        SdkMsg::Execute {
+            contract,
+            msg,
+            funds,
+        } => {
+            let env = Env {
+                block,
+                transaction,
+                contract: ContractInfo {
+                    address: address::resolve_raw(&contract)?,
+                },
+            };
+
+            let info = MessageInfo {
+                sender: sender_addr.clone(),
+                funds,
+            };
+
+            let result = execute::execute_contract(
+                &store,
+                vm,
+                call_stack,
+                &env,
+                &info,
+                &serde_json::to_vec(&msg)?,
+            )?
+            .into_result();
+
+            if let Ok(res) = &result {
+                if !res.messages.is_empty() {
+                    return Err(Error::SubmessagesUnsupported);
+                }
+            }
+
+            result.map(|res| res.events).map_err(Error::Contract)
+        },
+        SdkMsg::Migrate {
+            contract,
+            code_id,
+            msg,
+        } => {
+            let env = Env {
+                block,
+                transaction,
+                contract: ContractInfo {
+                    address: address::resolve_raw(&contract)?,
+                },
+            };
+
+            let result =
+                execute::migrate_contract(store, &env, code_id, &serde_json::to_vec(&msg)?)?
+                    .into_result();
+
+            if let Ok(res) = &result {
+                if !res.messages.is_empty() {
+                    return Err(Error::SubmessagesUnsupported);
+                }
+            }
+
+            result.map(|res| res.events).map_err(Error::Contract)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, time::SystemTime};
+
+    use cosmwasm_std::{coins, testing::MockStorage, Coin, Timestamp};
+    use cosmwasm_vm::{capabilities_from_csv, CacheOptions};
+    use cw_sdk::{Account, Allowance, Config, TxBody};
+    use k256::ecdsa::{signature::Signer, Signature, SigningKey};
+
+    use super::*;
+    use crate::{
+        execute::AVAILABLE_CAPABILITIES, state::ALLOWANCES, INSTANCE_MEMORY_LIMIT,
+        MEMORY_CACHE_SIZE,
+    };
+
+    fn mock_vm() -> VmCache {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        unsafe {
+            VmCache::new(CacheOptions {
+                base_dir: temp_dir().join(format!("cw-state-machine-test-{nanos}")),
+                available_capabilities: capabilities_from_csv(AVAILABLE_CAPABILITIES),
+                memory_cache_size: MEMORY_CACHE_SIZE,
+                instance_memory_limit: INSTANCE_MEMORY_LIMIT,
+            })
+            .unwrap()
+        }
+    }
+
+    fn mock_tx(sequence: u64, fee: Vec<Coin>, fee_payer: Option<&Addr>, msgs: Vec<SdkMsg>) -> Tx {
+        let sk = SigningKey::from_bytes(&[1; 32].into()).unwrap();
+        let pubkey = sk.verifying_key().to_sec1_bytes().to_vec();
+        let body = TxBody {
+            sender: address::derive_from_pubkey(&pubkey).unwrap().into(),
+            chain_id: "cw-test".into(),
+            sequence,
+            nonce: None,
+            timeout_height: None,
+            memo: None,
+            fee,
+            fee_payer: fee_payer.map(Into::into),
+            msgs,
+        };
+        let signature: Signature = sk.sign(&serde_json::to_vec(&body).unwrap());
+        Tx {
+            body,
+            pubkey: Some(pubkey.into()),
+            signature: signature.to_vec().into(),
+            multi_signature: None,
+            fee_payer_signature: None,
+        }
+    }
+
+    #[test]
+    fn charging_failed_txs() {
+        let vm = mock_vm();
+        let block = BlockInfo {
+            height: 1,
+            time: Timestamp::default(),
+            chain_id: "cw-test".into(),
+        };
+
+        let mut store = Shared::new(MockStorage::new());
+        CONFIG.save(&mut store, &Config::default()).unwrap();
+
+        // the first message succeeds, but the second one fails, as the sender
+        // isn't the owner
+        let tx = mock_tx(1, vec![], None, vec![
+            SdkMsg::SetUnorderedTxs {
+                enabled: true,
+            },
+            SdkMsg::PinCode {
+                code_id: 1,
+            },
+        ]);
+        let sender_addr = address::validate(&tx.body.sender).unwrap();
+
+        let (result, cache) = execute_tx(&vm, &block, store.share(), tx, None, None, None);
+        assert!(matches!(result, Err(Error::NotOwner { .. })));
+        cache.unwrap().flush();
+
+        // the sender's sequence is consumed, while the state changes made by the
+        // messages are rolled back
+        let account = ACCOUNTS.load(&store, &sender_addr).unwrap();
+        assert!(matches!(account, Account::Base {
+            sequence: 1,
+            unordered: false,
+            ..
+        }));
+
+        // if the fee can't be paid, which is always the case here as there's no
+        // bank contract, the tx is rejected without any state change, neither
+        // to the fee payer's allowance nor to the sender's sequence
+        let granter = address::derive_from_label("granter").unwrap();
+        let allowance = Allowance {
+            spend_limit: Some(coins(100, "uatom")),
+            expiration: None,
+            allowed_msgs: None,
+        };
+        ALLOWANCES.save(&mut store, (&granter, &sender_addr), &allowance).unwrap();
+
+        let tx = mock_tx(2, coins(60, "uatom"), Some(&granter), vec![SdkMsg::SetUnorderedTxs {
+            enabled: true,
+        }]);
+        let (result, cache) = execute_tx(&vm, &block, store.share(), tx, None, None, None);
+        assert!(result.is_err());
+        assert!(cache.is_none());
+
+        assert_eq!(ALLOWANCES.load(&store, (&granter, &sender_addr)).unwrap(), allowance);
+        let account = ACCOUNTS.load(&store, &sender_addr).unwrap();
+        assert!(matches!(account, Account::Base {
+            sequence: 1,
+            ..
+        }));
+    }
+}
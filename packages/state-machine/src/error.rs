@@ -1,3 +1,5 @@
+use std::convert::Infallible;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)]
@@ -12,6 +14,9 @@ pub enum Error {
     #[error(transparent)]
     Merk(#[from] cw_store::MerkError),
 
+    #[error(transparent)]
+    Tree(#[from] cw_store::TreeError),
+
     #[error(transparent)]
     Address(#[from] cw_sdk::address::AddressError),
 
@@ -52,6 +57,22 @@ pub enum Error {
         code_id: u64,
     },
 
+    #[error("sender {sender} is not the owner")]
+    NotOwner {
+        sender: String,
+    },
+
+    #[error("sender {sender} is not allowed to upload wasm byte codes")]
+    UploadUnauthorized {
+        sender: String,
+    },
+
+    #[error("wasm byte code is too large: {size} bytes, max {max} bytes")]
+    CodeTooLarge {
+        size: u64,
+        max: u64,
+    },
+
     #[error("sender address does not match pubkey: expecting {expect}, found {found}")]
     AddressMismatch {
         // The sender address deduced from the provided pubkey
@@ -69,6 +90,17 @@ pub enum Error {
         found: String,
     },
 
+    #[error("invalid multisig: {reason}")]
+    InvalidMultisig {
+        reason: String,
+    },
+
+    #[error("not enough signatures: threshold {threshold}, found {found}")]
+    ThresholdNotMet {
+        threshold: u32,
+        found: usize,
+    },
+
     #[error("incorrect chain id: expecting {expect}, found {found}")]
     ChainIdMismatch {
         /// The chain id stored on-chain
@@ -86,11 +118,108 @@ pub enum Error {
         found: u64,
     },
 
+    #[error("tx timed out: timeout height {timeout_height}, current height {current_height}")]
+    TxTimedOut {
+        /// The timeout height provided by the tx
+        timeout_height: u64,
+        /// Height of the block being processed
+        current_height: u64,
+    },
+
+    #[error("account {sender} has not opted into unordered txs")]
+    UnorderedTxsDisabled {
+        sender: String,
+    },
+
+    #[error("unordered txs must have a timeout height no more than {max} blocks ahead")]
+    InvalidUnorderedTimeout {
+        max: u64,
+    },
+
+    #[error("nonce {nonce} has already been used by sender {sender}")]
+    NonceUsed {
+        sender: String,
+        nonce: u64,
+    },
+
+    #[error("tx memo too long: length {length}, max {max}")]
+    MemoTooLong {
+        length: usize,
+        max: usize,
+    },
+
+    #[error("no fee allowance found from granter {granter} to grantee {grantee}")]
+    AllowanceNotFound {
+        granter: String,
+        grantee: String,
+    },
+
+    #[error("fee allowance from {granter} to {grantee} expired at height {expiration}")]
+    AllowanceExpired {
+        granter: String,
+        grantee: String,
+        expiration: u64,
+    },
+
+    #[error("fee allowance does not allow messages of type `{kind}`")]
+    MsgNotAllowed {
+        kind: String,
+    },
+
+    #[error("fee exceeds the allowance's spend limit: {reason}")]
+    SpendLimitExceeded {
+        reason: String,
+    },
+
+    #[error("failed to deduct fee: {reason}")]
+    FeeDeductionFailed {
+        reason: String,
+    },
+
     #[error("failed to transfer funds: {reason}")]
     FundTransferFailed {
         reason: String,
     },
 
+    #[error("failed to settle storage deposit: {reason}")]
+    StorageDepositFailed {
+        reason: String,
+    },
+
+    #[error("max contract call depth ({max}) exceeded")]
+    MaxCallDepthExceeded {
+        max: u32,
+    },
+
+    #[error("contract {contract} is non-reentrant and is already being executed")]
+    Reentrancy {
+        contract: String,
+    },
+
+    #[error("scheduled job {job_id} not found")]
+    JobNotFound {
+        job_id: u64,
+    },
+
+    #[error("only the creator of scheduled job {job_id} can cancel it")]
+    NotJobCreator {
+        job_id: u64,
+    },
+
+    #[error("a scheduled message can't schedule another message")]
+    NestedSchedule,
+
+    #[error("gas limit of scheduled job ({gas_limit}) exceeds the maximum ({max})")]
+    JobGasLimitExceeded {
+        gas_limit: u64,
+        max: u64,
+    },
+
+    #[error("failed to transfer the gas deposit of a scheduled job: {reason}")]
+    JobDepositFailed {
+        reason: String,
+    },
+
     #[error("contract response includes submessages, which is not supported yet")]
     SubmessagesUnsupported,
 
@@ -135,6 +264,25 @@ impl Error {
         }
     }
 
+    pub fn not_owner(sender: impl Into<String>) -> Self {
+        Self::NotOwner {
+            sender: sender.into(),
+        }
+    }
+
+    pub fn upload_unauthorized(sender: impl Into<String>) -> Self {
+        Self::UploadUnauthorized {
+            sender: sender.into(),
+        }
+    }
+
+    pub fn code_too_large(size: u64, max: u64) -> Self {
+        Self::CodeTooLarge {
+            size,
+            max,
+        }
+    }
+
     pub fn address_mismatch(expect: impl Into<String>, found: impl Into<String>) -> Self {
         Self::AddressMismatch {
             expect: expect.into(),
@@ -150,6 +298,19 @@ impl Error {
         }
     }
 
+    pub fn invalid_multisig(reason: impl Into<String>) -> Self {
+        Self::InvalidMultisig {
+            reason: reason.into(),
+        }
+    }
+
+    pub fn threshold_not_met(threshold: u32, found: usize) -> Self {
+        Self::ThresholdNotMet {
+            threshold,
+            found,
+        }
+    }
+
     pub fn chain_id_mismatch(expect: impl Into<String>, found: impl Into<String>) -> Self {
         Self::ChainIdMismatch {
             expect: expect.into(),
@@ -165,11 +326,132 @@ impl Error {
         }
     }
 
+    pub fn tx_timed_out(timeout_height: u64, current_height: u64) -> Self {
+        Self::TxTimedOut {
+            timeout_height,
+            current_height,
+        }
+    }
+
+    pub fn unordered_txs_disabled(sender: impl Into<String>) -> Self {
+        Self::UnorderedTxsDisabled {
+            sender: sender.into(),
+        }
+    }
+
+    pub fn invalid_unordered_timeout(max: u64) -> Self {
+        Self::InvalidUnorderedTimeout {
+            max,
+        }
+    }
+
+    pub fn nonce_used(sender: impl Into<String>, nonce: u64) -> Self {
+        Self::NonceUsed {
+            sender: sender.into(),
+            nonce,
+        }
+    }
+
+    pub fn memo_too_long(length: usize, max: usize) -> Self {
+        Self::MemoTooLong {
+            length,
+            max,
+        }
+    }
+
+    pub fn allowance_not_found(granter: impl Into<String>, grantee: impl Into<String>) -> Self {
+        Self::AllowanceNotFound {
+            granter: granter.into(),
+            grantee: grantee.into(),
+        }
+    }
+
+    pub fn allowance_expired(
+        granter: impl Into<String>,
+        grantee: impl Into<String>,
+        expiration: u64,
+    ) -> Self {
+        Self::AllowanceExpired {
+            granter: granter.into(),
+            grantee: grantee.into(),
+            expiration,
+        }
+    }
+
+    pub fn msg_not_allowed(kind: impl Into<String>) -> Self {
+        Self::MsgNotAllowed {
+            kind: kind.into(),
+        }
+    }
+
+    pub fn spend_limit_exceeded(reason: impl ToString) -> Self {
+        Self::SpendLimitExceeded {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn fee_deduction_failed(reason: impl ToString) -> Self {
+        Self::FeeDeductionFailed {
+            reason: reason.to_string(),
+        }
+    }
+
     pub fn fund_transfer_failed(reason: impl ToString) -> Self {
         Self::FundTransferFailed {
             reason: reason.to_string(),
         }
     }
+
+    pub fn storage_deposit_failed(reason: impl ToString) -> Self {
+        Self::StorageDepositFailed {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn max_call_depth_exceeded(max: u32) -> Self {
+        Self::MaxCallDepthExceeded {
+            max,
+        }
+    }
+
+    pub fn reentrancy(contract: impl Into<String>) -> Self {
+        Self::Reentrancy {
+            contract: contract.into(),
+        }
+    }
+
+    pub fn job_not_found(job_id: u64) -> Self {
+        Self::JobNotFound {
+            job_id,
+        }
+    }
+
+    pub fn not_job_creator(job_id: u64) -> Self {
+        Self::NotJobCreator {
+            job_id,
+        }
+    }
+
+    pub fn job_gas_limit_exceeded(gas_limit: u64, max: u64) -> Self {
+        Self::JobGasLimitExceeded {
+            gas_limit,
+            max,
+        }
+    }
+
+    pub fn job_deposit_failed(reason: impl ToString) -> Self {
+        Self::JobDepositFailed {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+// stores that can't fail to commit, such as `MemStore`, use `Infallible` as
+// their error type
+impl From<Infallible> for Error {
+    fn from(err: Infallible) -> Self {
+        match err {}
+    }
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,120 @@
+use cosmwasm_std::{to_vec, Addr, HexBinary};
+use cw_sdk::{
+    address::{ADDRESS_LENGTH, ADDRESS_PREFIX},
+    hash::HASH_LENGTH,
+};
+use cw_store::{Layout, TreeId};
+
+use crate::state::CONTRACT_ROOTS;
+
+/// Length of a human-readable address: the prefix, the "1" separator, the
+/// address bytes in base32, and the 6-character checksum.
+const HUMAN_ADDRESS_LENGTH: usize = ADDRESS_PREFIX.len() + 1 + (ADDRESS_LENGTH * 8 + 4) / 5 + 6;
+
+/// Namespaces of the data stored in the Code tree.
+const CODE_NAMESPACES: &[&str] = &["code_count", "codes", "code_infos", "pinned_codes"];
+
+/// Namespaces of the data stored in the Account tree.
+const ACCOUNT_NAMESPACES: &[&str] = &[
+    "accounts",
+    "accounts__label",
+    "accounts__code_id",
+    "accounts__admin",
+    "accounts__pubkey",
+    "contract_roots",
+    "storage_usage",
+    "storage_deposits",
+    "allowances",
+    "unordered_nonces",
+    "unordered_nonces_by_timeout",
+];
+
+/// The layout of the app state as described in `docs/5-app-state.md`:
+///
+/// - a contract's storage, which is namespaced by its address (see
+///   `ContractSubstore`), goes to the contract's own tree;
+/// - wasm codes and their metadata go to the Code tree;
+/// - accounts, along with the data kept for each of them, go to the Account
+///   tree, where the root of each contract's tree is recorded in
+///   `CONTRACT_ROOTS`;
+/// - everything else goes to the Global tree.
+///
+/// Nothing is stored in the IBC tree yet.
+pub struct AppLayout;
+
+impl Layout for AppLayout {
+    fn locate(&self, key: &[u8]) -> TreeId {
+        if let Some(contract) = contract_of(key) {
+            return TreeId::Contract(contract.into());
+        }
+
+        let namespace = namespace_of(key);
+        if CODE_NAMESPACES.iter().any(|ns| ns.as_bytes() == namespace) {
+            TreeId::Code
+        } else if ACCOUNT_NAMESPACES.iter().any(|ns| ns.as_bytes() == namespace) {
+            TreeId::Account
+        } else {
+            TreeId::Global
+        }
+    }
+
+    fn contract_root_key(&self, contract: &str) -> Vec<u8> {
+        CONTRACT_ROOTS.key(&Addr::unchecked(contract)).to_vec()
+    }
+
+    /// Roots are serialized the same way as other values in the state, so
+    /// that `CONTRACT_ROOTS` can be loaded as a regular map.
+    fn encode_contract_root(&self, root_hash: &[u8; HASH_LENGTH]) -> Vec<u8> {
+        to_vec(&HexBinary::from(root_hash.as_slice())).unwrap()
+    }
+}
+
+/// If the key is in a contract's storage, return the contract's address.
+///
+/// None of the state machine's own namespaces starts with the address prefix,
+/// so it's enough to check the prefix and the length.
+pub(crate) fn contract_of(key: &[u8]) -> Option<&str> {
+    let prefix = [ADDRESS_PREFIX.as_bytes(), b"1"].concat();
+    if key.len() < HUMAN_ADDRESS_LENGTH || !key.starts_with(&prefix) {
+        return None;
+    }
+    std::str::from_utf8(&key[..HUMAN_ADDRESS_LENGTH]).ok()
+}
+
+/// The namespace of a cw-storage-plus key. Keys of maps and sets start with the
+/// length of the namespace as two big-endian bytes, whereas the key of an item
+/// is the namespace itself.
+fn namespace_of(key: &[u8]) -> &[u8] {
+    match key {
+        [0, len, rest @ ..] if rest.len() >= *len as usize => &rest[..*len as usize],
+        _ => key,
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use cw_sdk::address;
+
+    use super::*;
+    use crate::state::{BLOCK, CODES, CONFIG, JOBS};
+
+    #[test]
+    fn locating_keys() {
+        let contract = address::derive_from_pubkey(b"contract").unwrap();
+        assert_eq!(contract.as_str().len(), HUMAN_ADDRESS_LENGTH);
+
+        assert_eq!(AppLayout.locate(BLOCK.as_slice()), TreeId::Global);
+        assert_eq!(AppLayout.locate(CONFIG.as_slice()), TreeId::Global);
+        assert_eq!(AppLayout.locate(&JOBS.key(1)), TreeId::Global);
+        assert_eq!(AppLayout.locate(&CODES.key(1)), TreeId::Code);
+        assert_eq!(AppLayout.locate(&CONTRACT_ROOTS.key(&contract)), TreeId::Account);
+
+        // a contract's storage is namespaced by its address
+        let key = [contract.as_bytes(), b"key"].concat();
+        assert_eq!(AppLayout.locate(&key), TreeId::Contract(contract.into()));
+    }
+}
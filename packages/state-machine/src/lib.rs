@@ -1,28 +1,83 @@
 pub mod auth;
 pub mod backend;
+pub mod changeset;
+pub mod deliver;
 pub mod error;
 pub mod execute;
+pub mod layout;
+pub mod parallel;
 pub mod query;
+pub mod schedule;
 pub mod state;
 
-use cosmwasm_std::{
-    to_binary, Addr, Binary, BlockInfo, ContractInfo, Env, Event, MessageInfo, Storage, Timestamp,
-    TransactionInfo,
+use std::{
+    collections::BTreeSet,
+    fs::OpenOptions,
+    io::{self, Write},
+    mem,
+    path::PathBuf,
+    sync::Arc,
 };
-use cw_sdk::{address, hash::HASH_LENGTH, GenesisState, SdkMsg, SdkQuery, Tx};
-use cw_store::{Cached, Shared, Store};
+
+use cosmwasm_std::{Binary, BlockInfo, Event, HexBinary, Order, StdResult, Timestamp};
+use cosmwasm_vm::{capabilities_from_csv, CacheOptions, Checksum, Size};
+use cw_sdk::{
+    address,
+    hash::{sha256, HASH_LENGTH},
+    DebugMessage, GenesisState, SdkQuery, Tx, TxTrace,
+};
+use cw_store::{Cached, CommitStore, Shared, Tracer, TreeStore};
+use tracing::{debug, info, warn};
 
 use crate::{
+    backend::{DebugLog, VmCache},
     error::{Error, Result},
-    state::{ACCOUNTS, BLOCK, CODE_COUNT},
+    execute::{CallStack, GasMeter, AVAILABLE_CAPABILITIES},
+    query::QueryHandle,
+    state::{
+        BlockSummary, BLOCK, BLOCK_SUMMARIES, CODES, CODE_COUNT, CODE_INFOS, CONFIG, PINNED_CODES,
+    },
 };
 
-pub struct StateMachine {
+/// Size of the VM's in-memory cache of compiled modules. Pinned modules are not
+/// counted towards this limit.
+const MEMORY_CACHE_SIZE: Size = Size::mebi(100);
+
+/// Memory limit of each wasm instance.
+const INSTANCE_MEMORY_LIMIT: Size = Size::mebi(32);
+
+/// By default, limit smart queries to 3,000,000 SDK gas, the same as wasmd's
+/// default `smart_query_gas_limit`. One SDK gas is 140,000 CosmWasm gas.
+pub const DEFAULT_QUERY_GAS_LIMIT: u64 = 3_000_000 * 140_000;
+
+/// Node-local options of the state machine.
+///
+/// Unlike the chain-level `Config`, these are not part of the chain's state,
+/// and may differ between nodes.
+pub struct Options {
+    /// Directory in which the VM cache stores compiled wasm modules.
+    pub vm_cache_dir: PathBuf,
+
+    /// Maximum amount of CosmWasm gas a smart query can consume, so that a
+    /// runaway query can't hang the node.
+    pub query_gas_limit: u64,
+
+    /// If provided, the storage operations made by each delivered tx are
+    /// traced, and appended to this file in the JSON lines format.
+    pub trace_file: Option<PathBuf>,
+
+    /// Whether to capture the debug messages printed by contracts while
+    /// delivering txs, and return them along with the txs' results. Otherwise,
+    /// they are printed to stderr.
+    pub capture_debug: bool,
+}
+
+/// The state machine, generic over the store holding the blockchain state,
+/// which is the cw-tree-backed `TreeStore` in nodes, and can be substituted
+/// with the in-memory `MemStore` in tests.
+pub struct StateMachine<S = TreeStore> {
     /// The database backend, which stores blockchain state persistently.
-    ///
-    /// TODO: use a trait instead of a type, so that we can substitute this with
-    /// a mock store for tests.
-    store: Store,
+    store: S,
 
     /// The block that is being processed and not yet commmitted to the store.
     ///
@@ -35,15 +90,119 @@ pub struct StateMachine {
     ///   state using the BLOCK storage constant.
     pending_block: Option<BlockInfo>,
 
-    // TODO: load pinned contracts and codes
+    /// Results of the txs delivered in the pending block so far, which are
+    /// summarized and saved along with the block during Commit.
+    pending_results: BlockResults,
+
+    /// The VM cache, which holds the compiled wasm modules.
+    ///
+    /// Unlike the store, the content of the cache is not part of the chain's
+    /// state; it is local to the node and only exists for performance reasons.
+    ///
+    /// It is thread-safe, and shared with the query handle.
+    vm: Arc<VmCache>,
+
+    /// Ids of the codes that are currently pinned in the VM cache.
+    ///
+    /// Kept in sync with the PINNED_CODES set in the committed state, on
+    /// startup and after each commit.
+    pinned_codes: BTreeSet<u64>,
+
+    /// Serves queries against a snapshot of the committed state, which is
+    /// replaced after each commit.
+    query_handle: QueryHandle<S::Snapshot>,
+
+    /// File to append the storage traces of delivered txs to, if tracing is
+    /// enabled.
+    trace_file: Option<PathBuf>,
+
+    /// Whether to capture the debug messages printed by contracts in txs.
+    capture_debug: bool,
 }
 
-impl StateMachine {
-    pub fn new(store: Store) -> Self {
-        Self {
+impl<S> StateMachine<S>
+where
+    S: CommitStore,
+    Error: From<S::Error>,
+{
+    /// Create a new state machine instance with the given node-local options.
+    ///
+    /// Wasm byte codes that exist in the store but not in the VM cache (e.g.
+    /// when the cache directory has been deleted) are saved to the cache, and
+    /// the pinned codes are loaded into memory.
+    pub fn new(store: S, options: Options) -> Result<Self> {
+        // SAFETY: the cache directory is exclusively used by this node, and we
+        // trust its content hasn't been tampered with.
+        let vm = Arc::new(unsafe {
+            VmCache::new(CacheOptions {
+                base_dir: options.vm_cache_dir,
+                available_capabilities: capabilities_from_csv(AVAILABLE_CAPABILITIES),
+                memory_cache_size: MEMORY_CACHE_SIZE,
+                instance_memory_limit: INSTANCE_MEMORY_LIMIT,
+            })?
+        });
+
+        let query_handle =
+            QueryHandle::new(store.snapshot(), Arc::clone(&vm), options.query_gas_limit);
+
+        let mut state_machine = Self {
             store,
             pending_block: None,
+            pending_results: BlockResults::default(),
+            vm,
+            pinned_codes: BTreeSet::new(),
+            query_handle,
+            trace_file: options.trace_file,
+            capture_debug: options.capture_debug,
+        };
+
+        state_machine.sync_codes()?;
+        state_machine.sync_pinned_codes()?;
+
+        Ok(state_machine)
+    }
+
+    /// Save wasm byte codes that exist in the committed state but not in the
+    /// VM cache to the cache.
+    ///
+    /// This reads every code from the cache's directory in order to verify its
+    /// integrity, so should only be called on startup.
+    fn sync_codes(&self) -> Result<()> {
+        let store = self.store.wrap();
+        for res in CODE_INFOS.range(&store, None, None, Order::Ascending) {
+            let (code_id, info) = res?;
+            let checksum = Checksum::try_from(info.checksum.as_slice())?;
+            if self.vm.load_wasm(&checksum).is_err() {
+                self.vm.save_wasm_unchecked(&CODES.load(&store, code_id)?)?;
+                info!(target: "Saved code to VM cache", id = code_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pin or unpin codes in the VM cache, so that the set of pinned codes
+    /// matches the PINNED_CODES set in the committed state.
+    fn sync_pinned_codes(&mut self) -> Result<()> {
+        let store = self.store.wrap();
+        let pinned_codes = PINNED_CODES
+            .items(&store, None, None, Order::Ascending)
+            .collect::<StdResult<BTreeSet<_>>>()?;
+
+        for code_id in pinned_codes.difference(&self.pinned_codes) {
+            let checksum = CODE_INFOS.load(&store, *code_id)?.checksum;
+            self.vm.pin(&Checksum::try_from(checksum.as_slice())?)?;
+            info!(target: "Pinned code", id = code_id);
+        }
+
+        for code_id in self.pinned_codes.difference(&pinned_codes) {
+            let checksum = CODE_INFOS.load(&store, *code_id)?.checksum;
+            self.vm.unpin(&Checksum::try_from(checksum.as_slice())?)?;
+            info!(target: "Unpinned code", id = code_id);
         }
+
+        self.pinned_codes = pinned_codes;
+
+        Ok(())
     }
 
     /// Decode genesis bytes and run genesis messages. Return app hash.
@@ -63,19 +222,23 @@ impl StateMachine {
         };
 
         BLOCK.save(&mut cache, &block)?;
+        CONFIG.save(&mut cache, &execute::validate_config(gen_state.config)?)?;
         CODE_COUNT.save(&mut cache, &0)?;
 
         let deployer_addr = address::validate(&gen_state.deployer)?;
 
         // execute messages in order.
         // ResponseInitChain doesn't take events, so we discard the emitted events here.
+        let mut call_stack = CallStack::new(CONFIG.load(&cache)?.max_call_depth);
         for msg in gen_state.msgs {
-            self.handle_msg(
+            deliver::handle_msg(
+                &self.vm,
                 cache.share(),
                 block.clone(),
                 None,
                 &deployer_addr,
                 msg,
+                &mut call_stack,
             )?;
         }
 
@@ -86,170 +249,164 @@ impl StateMachine {
     }
 
     pub fn begin_block(&mut self, block: BlockInfo) -> Result<Vec<Event>> {
-        // current we just update pending block and do nothing else
-        // TODO: read cosmos-sdk code and see what to do here
+        // delete the nonces of unordered txs that have timed out
+        // TODO: read cosmos-sdk code and see what else to do here
+        auth::prune_nonces(&mut self.store.pending_wrap(), block.height)?;
+
+        // run the scheduled jobs that have become due
+        let events = schedule::run_due_jobs(&self.vm, &block, self.store.pending_wrap())?;
+
         self.pending_block = Some(block);
+        self.pending_results = BlockResults {
+            events: events.clone(),
+            ..Default::default()
+        };
 
-        Ok(vec![])
+        Ok(events)
     }
 
-    pub fn deliver_tx(&self, tx: Tx) -> Result<Vec<Event>> {
-        // make a cache of the store. it will only be flushed if the entire tx
-        // is successful
-        let mut cache = Cached::new(self.store.pending_wrap());
-
-        // authenticate signature, chain id, sequence, etc.
-        let sender = auth::authenticate_tx(&cache, self.pending_block.as_ref().unwrap(), &tx)?;
-
-        // update the sender's account in the store
-        ACCOUNTS.save(&mut cache, &sender.address, &sender.account)?;
-
-        // wrap the cached store in a `Rc<RefCell<T>>` so that it can be shared
-        // as an owned value across the execution of multiple messages
-        let mut cache = Shared::new(cache);
-
-        let mut events = vec![];
-
-        tx
-            .body
-            .msgs
-            .into_iter()
-            .map(|msg| {
-                self.handle_msg(
-                    cache.share(),
-                    self.pending_block.clone().unwrap(),
-                    None,
-                    &sender.address,
-                    msg,
-                )
-            })
-            .try_for_each(|res| -> Result<_> {
-                events.extend(res?);
-                Ok(())
-            })?;
+    /// Deliver a tx, returning its result, along with the debug messages printed
+    /// by contracts, if capturing them is enabled. The debug messages are
+    /// returned even if the tx fails.
+    ///
+    /// The tx hash is the one assigned by Tendermint, i.e. the SHA-256 hash of
+    /// the raw tx bytes, which is recorded in the block summary if it fails.
+    pub fn deliver_tx(
+        &mut self,
+        tx: Tx,
+        tx_hash: HexBinary,
+    ) -> (Result<Vec<Event>>, Vec<DebugMessage>) {
+        let block = self.pending_block.as_ref().unwrap();
+        let debug_log = self.capture_debug.then(DebugLog::new);
+        let gas_meter = GasMeter::new();
+
+        // if tracing is enabled, record the storage operations made by the tx,
+        // regardless of whether it succeeds
+        let trace = self.start_trace(&tx);
+        let tracer = trace.as_ref().map(|_| Tracer::new());
+
+        let (result, cache) = deliver::execute_tx(
+            &self.vm,
+            block,
+            self.store.pending_wrap(),
+            tx,
+            tracer.clone(),
+            debug_log.clone(),
+            Some(gas_meter.clone()),
+        );
+
+        if let (Some(tracer), Some(mut trace)) = (tracer, trace) {
+            trace.error = result.as_ref().err().map(ToString::to_string);
+            trace.traces = tracer.take();
+            self.write_trace(&trace);
+        }
 
-        // tx is successful: flush the state changes
-        cache.borrow_mut().flush();
+        let debug_messages = debug_log.map(|debug_log| debug_log.take()).unwrap_or_default();
 
-        Ok(events)
+        // flush the state changes, which are only the fee payment's if the tx's
+        // messages have failed
+        if let Some(mut cache) = cache {
+            cache.flush();
+        }
+
+        self.pending_results.gas_used += gas_meter.gas_used();
+        self.pending_results.record(&result, tx_hash);
+
+        (result, debug_messages)
     }
 
-    fn handle_msg(
-        &self,
-        mut store: impl Storage + 'static,
-        block: BlockInfo,
-        transaction: Option<TransactionInfo>,
-        sender_addr: &Addr,
-        msg: SdkMsg,
-    ) -> Result<Vec<Event>> {
-        match msg {
-            SdkMsg::StoreCode {
-                wasm_byte_code,
-            } => {
-                let event = execute::store_code(&mut store, sender_addr, &wasm_byte_code)?;
-                Ok(vec![event])
-            },
-            SdkMsg::Instantiate {
-                code_id,
-                msg,
-                funds,
-                label,
-                admin,
-            } => {
-                let admin_addr = admin.map(|admin| address::resolve_raw(&admin)).transpose()?;
-
-                if !funds.is_empty() {
-                    return Err(Error::FundsUnsupported);
-                }
-                let info = MessageInfo {
-                    sender: sender_addr.clone(),
-                    funds,
-                };
-
-                let result = execute::instantiate_contract(
-                    store,
-                    block,
-                    transaction,
-                    &info,
-                    code_id,
-                    &serde_json::to_vec(&msg)?,
-                    label,
-                    admin_addr,
-                )?
-                .into_result();
-
-                if let Ok(res) = &result {
-                    if !res.messages.is_empty() {
-                        return Err(Error::SubmessagesUnsupported);
-                    }
-                }
+    /// Start the storage trace of a tx, if tracing is enabled. The error and the
+    /// operations are filled in once the tx has been executed.
+    fn start_trace(&self, tx: &Tx) -> Option<TxTrace> {
+        self.trace_file.as_ref()?;
+        Some(TxTrace {
+            height: self.pending_block.as_ref().unwrap().height,
+            sender: tx.body.sender.clone(),
+            sequence: tx.body.sequence,
+            nonce: tx.body.nonce,
+            error: None,
+            traces: vec![],
+        })
+    }
 
-                result.map(|res| res.events).map_err(Error::Contract)
-            },
-            SdkMsg::Execute {
-                contract,
-                msg,
-                funds,
-            } => {
-                let env = Env {
-                    block,
-                    transaction,
-                    contract: ContractInfo {
-                        address: address::resolve_raw(&contract)?,
-                    },
-                };
-
-                let info = MessageInfo {
-                    sender: sender_addr.clone(),
-                    funds,
-                };
-
-                let result = execute::execute_contract(
-                    store,
-                    &env,
-                    &info,
-                    &serde_json::to_vec(&msg)?,
-                )?
-                .into_result();
-
-                if let Ok(res) = &result {
-                    if !res.messages.is_empty() {
-                        return Err(Error::SubmessagesUnsupported);
-                    }
-                }
+    /// Append a tx's storage trace to the trace file.
+    ///
+    /// The trace is not part of the chain's state, so failing to write it is
+    /// logged instead of failing the tx.
+    fn write_trace(&self, trace: &TxTrace) {
+        let Some(trace_file) = &self.trace_file else {
+            return;
+        };
 
-                result.map(|res| res.events).map_err(Error::Contract)
-            },
-            SdkMsg::Migrate {
-                contract,
-                code_id,
-                msg,
-            } => {
-                let env = Env {
-                    block,
-                    transaction,
-                    contract: ContractInfo {
-                        address: address::resolve_raw(&contract)?,
-                    },
-                };
-
-                let result = execute::migrate_contract(
-                    store,
-                    &env,
-                    code_id,
-                    &serde_json::to_vec(&msg)?,
-                )?
-                .into_result();
-
-                if let Ok(res) = &result {
-                    if !res.messages.is_empty() {
-                        return Err(Error::SubmessagesUnsupported);
-                    }
+        let result = serde_json::to_string(trace).map_err(io::Error::from).and_then(|line| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(trace_file)?
+                .write_all(format!("{line}\n").as_bytes())
+        });
+
+        if let Err(err) = result {
+            warn!(target: "Failed to write storage trace", reason = err.to_string());
+        }
+    }
+
+    /// Deliver all txs of the block at once, executing them in parallel where
+    /// they don't conflict. See `parallel::deliver_txs` for details.
+    ///
+    /// Results, state changes, gas consumption, storage traces and debug
+    /// messages are the same as calling `deliver_tx` on each of the txs in
+    /// order.
+    ///
+    /// Experimental: the node doesn't use this yet, as ABCI delivers the txs of
+    /// a block one at a time, and expects each one's result before the next.
+    pub fn deliver_txs(
+        &mut self,
+        txs: Vec<(Tx, HexBinary)>,
+    ) -> Vec<(Result<Vec<Event>>, Vec<DebugMessage>)> {
+        let (txs, tx_hashes): (Vec<_>, Vec<_>) = txs.into_iter().unzip();
+        let traces = txs.iter().map(|tx| self.start_trace(tx)).collect::<Vec<_>>();
+        let gas_meter = GasMeter::new();
+
+        let deliveries = parallel::deliver_txs(
+            &self.vm,
+            self.pending_block.as_ref().unwrap(),
+            || self.store.pending_wrap(),
+            txs,
+            &gas_meter,
+            self.trace_file.is_some(),
+        );
+
+        self.pending_results.gas_used += gas_meter.gas_used();
+
+        let mut outputs = vec![];
+        for ((delivery, tx_hash), trace) in deliveries.into_iter().zip(tx_hashes).zip(traces) {
+            if let (Some(ops), Some(mut trace)) = (delivery.traces, trace) {
+                trace.error = delivery.result.as_ref().err().map(ToString::to_string);
+                trace.traces = ops;
+                self.write_trace(&trace);
+            }
+
+            // debug messages are always captured when delivering txs in
+            // parallel. if capturing them is disabled, log them instead
+            let debug_messages = if self.capture_debug {
+                delivery.debug_messages
+            } else {
+                for debug_message in delivery.debug_messages {
+                    debug!(
+                        target: "Contract debug message",
+                        contract = debug_message.contract.to_string(),
+                        message = debug_message.message,
+                    );
                 }
+                vec![]
+            };
 
-                result.map(|res| res.events).map_err(Error::Contract)
-            },
+            self.pending_results.record(&delivery.result, tx_hash);
+            outputs.push((delivery.result, debug_messages));
         }
+
+        outputs
     }
 
     pub fn info(&self) -> Result<(i64, [u8; HASH_LENGTH])> {
@@ -265,54 +422,183 @@ impl StateMachine {
         ))
     }
 
+    /// Handle a query against the last committed state. See `QueryHandle`
+    /// for serving queries from other threads.
     pub fn query(&self, query: SdkQuery) -> Result<Binary> {
-        let store = self.store.wrap();
-        match query {
-            SdkQuery::Info {} => to_binary(&query::info(&store)?),
-            SdkQuery::Account {
-                address,
-            } => to_binary(&query::account(&store, address)?),
-            SdkQuery::Accounts {
-                start_after,
-                limit,
-            } => to_binary(&query::accounts(&store, start_after, limit)?),
-            SdkQuery::Contract {
-                label
-            } => to_binary(&query::contract(&store, label)?),
-            SdkQuery::Contracts {
-                start_after,
-                limit,
-            } => to_binary(&query::contracts(&store, start_after, limit)?),
-            SdkQuery::Code {
-                code_id,
-            } => to_binary(&query::code(&store, code_id)?),
-            SdkQuery::Codes {
-                start_after,
-                limit,
-            } => to_binary(&query::codes(&store, start_after, limit)?),
-            SdkQuery::WasmRaw {
-                contract,
-                key,
-            } => to_binary(&query::wasm_raw(store, &contract, &key)?),
-            SdkQuery::WasmSmart {
-                contract,
-                msg,
-            } => to_binary(&query::wasm_smart(store, &contract, &serde_json::to_vec(&msg)?)?),
-        }
-        .map_err(Error::from)
+        self.query_handle.query(query)
+    }
+
+    /// Return a handle serving queries against the last committed state, which
+    /// is kept up to date after each commit, and can be sent to other threads.
+    pub fn query_handle(&self) -> QueryHandle<S::Snapshot> {
+        self.query_handle.clone()
     }
 
     pub fn commit(&mut self) -> Result<(i64, [u8; HASH_LENGTH])> {
         // save the current pending block as the last committed block
-        BLOCK.save(&mut self.store.pending_wrap(), self.pending_block.as_ref().unwrap())?;
+        let block = self.pending_block.as_ref().unwrap();
+        BLOCK.save(&mut self.store.pending_wrap(), block)?;
+
+        // summarize the block's results, so that they can be queried later
+        let results = mem::take(&mut self.pending_results);
+        BLOCK_SUMMARIES.save(&mut self.store.pending_wrap(), block.height, &BlockSummary {
+            block: block.clone(),
+            tx_count: results.tx_count,
+            gas_used: results.gas_used,
+            failed_txs: results.failed_txs,
+            events_hash: sha256(&serde_json::to_vec(&results.events)?).into(),
+        })?;
 
         // clear the pending block
         self.pending_block = None;
 
-        // commit pending ops to the underlying store
+        // commit pending ops to the underlying store, and serve queries from
+        // the newly committed state
         self.store.commit()?;
+        self.query_handle.update(self.store.snapshot());
+
+        // load newly pinned codes into memory, and release unpinned ones
+        self.sync_pinned_codes()?;
 
         // return the block height and app hash that was just committed
         self.info()
     }
 }
+
+/// Results of the txs delivered in a block so far.
+#[derive(Default)]
+struct BlockResults {
+    tx_count: u32,
+    gas_used: u64,
+    failed_txs: Vec<HexBinary>,
+    /// Events emitted in BeginBlock and by the successful txs, in order
+    events: Vec<Event>,
+}
+
+impl BlockResults {
+    fn record(&mut self, result: &Result<Vec<Event>>, tx_hash: HexBinary) {
+        self.tx_count += 1;
+        match result {
+            Ok(events) => self.events.extend(events.iter().cloned()),
+            Err(_) => self.failed_txs.push(tx_hash),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, thread, time::SystemTime};
+
+    use cosmwasm_std::{from_binary, Addr};
+    use cw_sdk::{BlockResponse, InfoResponse, TxBody};
+    use cw_store::MemStore;
+
+    use super::*;
+
+    fn setup_test() -> (StateMachine<MemStore>, Addr) {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        let state_machine = StateMachine::new(MemStore::new(), Options {
+            vm_cache_dir: temp_dir().join(format!("cw-state-machine-test-{nanos}")),
+            query_gas_limit: DEFAULT_QUERY_GAS_LIMIT,
+            trace_file: None,
+            capture_debug: false,
+        })
+        .unwrap();
+
+        let deployer = address::derive_from_pubkey(b"deployer").unwrap();
+        state_machine
+            .init_chain("cw-test".into(), GenesisState {
+                deployer: deployer.to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        (state_machine, deployer)
+    }
+
+    #[test]
+    fn summarizing_blocks() {
+        let (mut state_machine, deployer) = setup_test();
+
+        let block = BlockInfo {
+            height: 1,
+            time: Timestamp::from_seconds(1),
+            chain_id: "cw-test".into(),
+        };
+        state_machine.begin_block(block.clone()).unwrap();
+
+        // the sender's account doesn't exist, and no pubkey is provided, so the
+        // tx fails
+        let tx_hash = HexBinary::from(sha256(b"tx"));
+        let (result, _) = state_machine.deliver_tx(
+            Tx {
+                body: TxBody {
+                    sender: deployer.into(),
+                    chain_id: "cw-test".into(),
+                    sequence: 1,
+                    nonce: None,
+                    timeout_height: None,
+                    memo: None,
+                    fee: vec![],
+                    fee_payer: None,
+                    msgs: vec![],
+                },
+                pubkey: None,
+                signature: Binary::default(),
+                multi_signature: None,
+                fee_payer_signature: None,
+            },
+            tx_hash.clone(),
+        );
+        assert!(result.is_err());
+
+        let (height, app_hash) = state_machine.commit().unwrap();
+        assert_eq!(height, 1);
+        assert_ne!(app_hash, [0; HASH_LENGTH]);
+
+        let query = SdkQuery::Block {
+            height: 1,
+        };
+        let response: BlockResponse = from_binary(&state_machine.query(query).unwrap()).unwrap();
+        assert_eq!(response.block, block);
+        assert_eq!(response.tx_count, 1);
+        assert_eq!(response.gas_used, 0);
+        assert_eq!(response.failed_txs, vec![tx_hash]);
+        assert_eq!(response.events_hash, HexBinary::from(sha256(b"[]")));
+    }
+
+    #[test]
+    fn querying_from_other_threads() {
+        let (mut state_machine, _) = setup_test();
+
+        let query_handle = state_machine.query_handle();
+        let query_height = || {
+            let query_handle = query_handle.clone();
+            thread::spawn(move || -> Result<u64> {
+                let response = query_handle.query(SdkQuery::Info {})?;
+                Ok(from_binary::<InfoResponse>(&response)?.last_committed_block.height)
+            })
+            .join()
+            .unwrap()
+        };
+
+        let mock_block = |height| BlockInfo {
+            height,
+            time: Timestamp::from_seconds(height),
+            chain_id: "cw-test".into(),
+        };
+
+        // queries are served from the committed state, which doesn't include
+        // the genesis state until the first block is committed
+        state_machine.begin_block(mock_block(1)).unwrap();
+        assert!(query_height().is_err());
+        state_machine.commit().unwrap();
+        assert_eq!(query_height().unwrap(), 1);
+
+        // nor the pending block
+        state_machine.begin_block(mock_block(2)).unwrap();
+        assert_eq!(query_height().unwrap(), 1);
+        state_machine.commit().unwrap();
+        assert_eq!(query_height().unwrap(), 2);
+    }
+}
@@ -0,0 +1,538 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    iter,
+    num::NonZeroUsize,
+    ops::Bound,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use cosmwasm_std::{BlockInfo, Event, Order, Record, Storage};
+use cw_sdk::{DebugMessage, StorageTrace, Tx};
+use cw_store::{Cached, Tracer};
+
+use crate::{
+    backend::{DebugLog, VmCache},
+    deliver::execute_tx,
+    error::Result,
+    execute::GasMeter,
+};
+
+/// The state changes made by a tx, where `None` means the key is deleted.
+pub type WriteSet = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Keys and key ranges a tx has read from the state as of before the block's
+/// txs. Reads of the tx's own writes are not included.
+#[derive(Debug, Default)]
+pub struct ReadSet {
+    keys: BTreeSet<Vec<u8>>,
+
+    /// Lower bounds are inclusive, upper bounds exclusive
+    ranges: Vec<(Option<Vec<u8>>, Option<Vec<u8>>)>,
+}
+
+impl ReadSet {
+    /// Return whether any of the reads is of a key in the given set, i.e.
+    /// whether the values read may have been changed by the txs that wrote
+    /// these keys.
+    pub fn conflicts_with(&self, written: &BTreeSet<Vec<u8>>) -> bool {
+        self.keys.iter().any(|key| written.contains(key))
+            || self.ranges.iter().any(|(start, end)| {
+                let start = start.as_deref().map_or(Bound::Unbounded, Bound::Included);
+                let end = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+                written.range::<[u8], _>((start, end)).next().is_some()
+            })
+    }
+}
+
+/// Deliver the txs of a block, producing the same results and state changes
+/// as delivering them one by one, in order.
+///
+/// This is a prototype of optimistic parallel execution:
+///
+/// - First, the txs are executed speculatively in worker threads, each on top
+///   of the state as of before the txs, recording the keys it reads and the
+///   state changes it makes.
+/// - Then, in order, the state changes of each tx are applied to the store,
+///   unless the tx has read a key written by a tx before it. In that case, its
+///   speculative outcome may differ from that of sequential execution, so the
+///   tx is executed again on top of the current state.
+///
+/// `new_store` is called each time a handle of the store is needed. The gas
+/// consumed by each tx is recorded on `gas_meter`.
+///
+/// Each execution records its own debug messages, gas consumption, and, if
+/// `trace` is true, storage operations. Only those of the executions whose
+/// outcomes are kept are returned or recorded, so they are the same as those
+/// of delivering the txs one by one. Debug messages are always captured, as
+/// printing them as they are made would include those of discarded executions.
+pub fn deliver_txs<S, F>(
+    vm: &VmCache,
+    block: &BlockInfo,
+    new_store: F,
+    txs: Vec<Tx>,
+    gas_meter: &GasMeter,
+    trace: bool,
+) -> Vec<Delivery>
+where
+    S: Storage + 'static,
+    F: Fn() -> S,
+{
+    let speculations = speculate(vm, block, &new_store(), &txs, trace);
+
+    // keys written by the txs whose state changes have been applied so far
+    let mut written = BTreeSet::new();
+
+    txs.into_iter()
+        .zip(speculations)
+        .map(|(tx, speculation)| {
+            let execution = if speculation.reads.conflicts_with(&written) {
+                execute(vm, block, new_store(), tx, trace)
+            } else {
+                speculation.execution
+            };
+
+            gas_meter.consume(execution.gas_used);
+
+            let mut store = new_store();
+            for (key, value) in &execution.writes {
+                match value {
+                    Some(value) => store.set(key, value),
+                    None => store.remove(key),
+                }
+            }
+            written.extend(execution.writes.into_keys());
+
+            execution.delivery
+        })
+        .collect()
+}
+
+/// The outcome of delivering a tx.
+pub struct Delivery {
+    pub result: Result<Vec<Event>>,
+
+    /// The storage operations made by the tx, if tracing is enabled
+    pub traces: Option<Vec<StorageTrace>>,
+
+    /// The debug messages printed by contracts during the tx
+    pub debug_messages: Vec<DebugMessage>,
+}
+
+/// The outcome of executing a tx, whose state changes are yet to be applied.
+struct Execution {
+    delivery: Delivery,
+    gas_used: u64,
+    writes: WriteSet,
+}
+
+/// Execute a tx on top of the given store without writing to it, recording
+/// its state changes, debug messages, gas consumption, and, if `trace` is
+/// true, storage operations.
+fn execute<S>(vm: &VmCache, block: &BlockInfo, store: S, tx: Tx, trace: bool) -> Execution
+where
+    S: Storage + 'static,
+{
+    let tracer = trace.then(Tracer::new);
+    let debug_log = DebugLog::new();
+    let gas_meter = GasMeter::new();
+
+    let (result, cache) = execute_tx(
+        vm,
+        block,
+        store,
+        tx,
+        tracer.clone(),
+        Some(debug_log.clone()),
+        Some(gas_meter.clone()),
+    );
+    let writes = cache.map(Cached::into_pending_ops).unwrap_or_default();
+
+    Execution {
+        delivery: Delivery {
+            result,
+            traces: tracer.map(|tracer| tracer.take()),
+            debug_messages: debug_log.take(),
+        },
+        gas_used: gas_meter.gas_used(),
+        writes,
+    }
+}
+
+/// The outcome of executing a tx speculatively.
+struct Speculation {
+    execution: Execution,
+    reads: ReadSet,
+}
+
+/// Execute the txs in worker threads, each on top of the given store, without
+/// writing to it. Return the outcomes in the same order as the txs.
+fn speculate(
+    vm: &VmCache,
+    block: &BlockInfo,
+    store: &dyn Storage,
+    txs: &[Tx],
+    trace: bool,
+) -> Vec<Speculation> {
+    let workers =
+        thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(1).min(txs.len());
+    let next_index = AtomicUsize::new(0);
+    let (requests_tx, requests_rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                let requests = requests_tx.clone();
+                let next_index = &next_index;
+                scope.spawn(move || {
+                    let mut speculations = vec![];
+                    loop {
+                        let index = next_index.fetch_add(1, Ordering::Relaxed);
+                        let Some(tx) = txs.get(index) else {
+                            break;
+                        };
+
+                        let reads = Rc::new(RefCell::new(ReadSet::default()));
+                        let snapshot = SnapshotReader {
+                            requests: requests.clone(),
+                            reads: Rc::clone(&reads),
+                        };
+                        let execution = execute(vm, block, snapshot, tx.clone(), trace);
+
+                        speculations.push((
+                            index,
+                            Speculation {
+                                execution,
+                                reads: reads.take(),
+                            },
+                        ));
+                    }
+                    speculations
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // the workers hold copies of the sender. drop the original one, so that
+        // the loop below ends once all workers are finished
+        drop(requests_tx);
+
+        for request in requests_rx {
+            serve(store, request);
+        }
+
+        let mut speculations = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("[parallel]: worker thread panicked"))
+            .collect::<Vec<_>>();
+        speculations.sort_by_key(|(index, _)| *index);
+        speculations.into_iter().map(|(_, speculation)| speculation).collect()
+    })
+}
+
+/// A request from a worker thread to read the state as of before the txs.
+enum ReadRequest {
+    Get {
+        key: Vec<u8>,
+        reply: mpsc::Sender<Option<Vec<u8>>>,
+    },
+    Range {
+        start: Option<Vec<u8>>,
+        end: Option<Vec<u8>>,
+        order: Order,
+        reply: mpsc::Sender<Vec<Record>>,
+    },
+}
+
+/// Serve a worker thread's read request from the store.
+fn serve(store: &dyn Storage, request: ReadRequest) {
+    // if the worker has panicked, the reply can't be received, which is fine;
+    // the panic is reported once the worker is joined
+    match request {
+        ReadRequest::Get {
+            key,
+            reply,
+        } => {
+            reply.send(store.get(&key)).ok();
+        },
+        ReadRequest::Range {
+            start,
+            end,
+            order,
+            reply,
+        } => {
+            reply.send(store.range(start.as_deref(), end.as_deref(), order).collect()).ok();
+        },
+    }
+}
+
+/// A read-only view of the state as of before the txs, on top of which worker
+/// threads execute txs speculatively, and which records the reads made.
+///
+/// The state machine's store can't be shared across threads, so the reads are
+/// sent to the thread that owns the store, which serves them until all workers
+/// are finished. Writes are held in the caches created on top of this.
+struct SnapshotReader {
+    requests: mpsc::Sender<ReadRequest>,
+    reads: Rc<RefCell<ReadSet>>,
+}
+
+impl Storage for SnapshotReader {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.reads.borrow_mut().keys.insert(key.to_vec());
+
+        let (reply, response) = mpsc::channel();
+        self.requests
+            .send(ReadRequest::Get {
+                key: key.to_vec(),
+                reply,
+            })
+            .expect("[parallel]: store thread hung up");
+        response.recv().expect("[parallel]: store thread hung up")
+    }
+
+    fn set(&mut self, _key: &[u8], _value: &[u8]) {
+        unreachable!("[parallel]: speculative execution must not write to the snapshot");
+    }
+
+    fn remove(&mut self, _key: &[u8]) {
+        unreachable!("[parallel]: speculative execution must not write to the snapshot");
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(iter::empty());
+            }
+        }
+
+        self.reads.borrow_mut().ranges.push((start.map(<[u8]>::to_vec), end.map(<[u8]>::to_vec)));
+
+        let (reply, response) = mpsc::channel();
+        self.requests
+            .send(ReadRequest::Range {
+                start: start.map(<[u8]>::to_vec),
+                end: end.map(<[u8]>::to_vec),
+                order,
+                reply,
+            })
+            .expect("[parallel]: store thread hung up");
+        Box::new(response.recv().expect("[parallel]: store thread hung up").into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs, path::Path, time::SystemTime};
+
+    use cosmwasm_std::{testing::MockStorage, HexBinary, Timestamp};
+    use cosmwasm_vm::{capabilities_from_csv, CacheOptions};
+    use cw_sdk::{address, hash::sha256, Config, GenesisState, SdkMsg, TxBody};
+    use cw_store::{MemStore, Shared};
+    use k256::ecdsa::{signature::Signer, Signature, SigningKey};
+
+    use super::*;
+    use crate::{
+        execute::AVAILABLE_CAPABILITIES, state::CONFIG, Options, StateMachine,
+        DEFAULT_QUERY_GAS_LIMIT, INSTANCE_MEMORY_LIMIT, MEMORY_CACHE_SIZE,
+    };
+
+    fn mock_vm() -> VmCache {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        unsafe {
+            VmCache::new(CacheOptions {
+                base_dir: temp_dir().join(format!("cw-state-machine-test-{nanos}")),
+                available_capabilities: capabilities_from_csv(AVAILABLE_CAPABILITIES),
+                memory_cache_size: MEMORY_CACHE_SIZE,
+                instance_memory_limit: INSTANCE_MEMORY_LIMIT,
+            })
+            .unwrap()
+        }
+    }
+
+    fn mock_store() -> Shared<MockStorage> {
+        let mut store = Shared::new(MockStorage::new());
+        CONFIG.save(&mut store, &Config::default()).unwrap();
+        store
+    }
+
+    /// Create a state machine that traces the txs it delivers to the given
+    /// file, and captures debug messages.
+    fn mock_state_machine(trace_file: &Path) -> StateMachine<MemStore> {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        let state_machine = StateMachine::new(MemStore::new(), Options {
+            vm_cache_dir: temp_dir().join(format!("cw-state-machine-test-{nanos}")),
+            query_gas_limit: DEFAULT_QUERY_GAS_LIMIT,
+            trace_file: Some(trace_file.to_path_buf()),
+            capture_debug: true,
+        })
+        .unwrap();
+
+        let deployer = address::derive_from_pubkey(b"deployer").unwrap();
+        state_machine
+            .init_chain("cw-test".into(), GenesisState {
+                deployer: deployer.to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        state_machine
+    }
+
+    fn mock_tx(sk_bytes: u8, sequence: u64, enabled: bool) -> Tx {
+        let sk = SigningKey::from_bytes(&[sk_bytes; 32].into()).unwrap();
+        let pubkey = sk.verifying_key().to_sec1_bytes().to_vec();
+        let body = TxBody {
+            sender: address::derive_from_pubkey(&pubkey).unwrap().into(),
+            chain_id: "cw-test".into(),
+            sequence,
+            nonce: None,
+            timeout_height: None,
+            memo: None,
+            fee: vec![],
+            fee_payer: None,
+            msgs: vec![SdkMsg::SetUnorderedTxs {
+                enabled,
+            }],
+        };
+        let signature: Signature = sk.sign(&serde_json::to_vec(&body).unwrap());
+        Tx {
+            body,
+            pubkey: Some(pubkey.into()),
+            signature: signature.to_vec().into(),
+            multi_signature: None,
+            fee_payer_signature: None,
+        }
+    }
+
+    #[test]
+    fn detecting_conflicts() {
+        let reads = ReadSet {
+            keys: [b"apple".to_vec()].into(),
+            ranges: vec![
+                (Some(b"cat".to_vec()), Some(b"dog".to_vec())),
+                (Some(b"pig".to_vec()), None),
+            ],
+        };
+
+        let conflicts = |key: &[u8]| reads.conflicts_with(&[key.to_vec()].into());
+        assert!(conflicts(b"apple"));
+        assert!(!conflicts(b"banana"));
+        assert!(conflicts(b"cat"));
+        assert!(conflicts(b"cow"));
+        assert!(!conflicts(b"dog"));
+        assert!(conflicts(b"zebra"));
+    }
+
+    #[test]
+    fn matching_sequential_execution() {
+        let vm = mock_vm();
+        let block = BlockInfo {
+            height: 1,
+            time: Timestamp::default(),
+            chain_id: "cw-test".into(),
+        };
+
+        // alice's second tx conflicts with her first one, which creates her
+        // account, and bob's tx has a wrong sequence
+        let txs = vec![
+            mock_tx(1, 1, true),
+            mock_tx(2, 2, true),
+            mock_tx(1, 2, false),
+            mock_tx(3, 1, true),
+        ];
+
+        let sequential_store = mock_store();
+        let sequential_outputs = txs
+            .iter()
+            .map(|tx| {
+                let debug_log = DebugLog::new();
+                let (result, cache) = execute_tx(
+                    &vm,
+                    &block,
+                    sequential_store.share(),
+                    tx.clone(),
+                    None,
+                    Some(debug_log.clone()),
+                    None,
+                );
+                if let Some(mut cache) = cache {
+                    cache.flush();
+                }
+                (result.ok(), debug_log.take())
+            })
+            .collect::<Vec<_>>();
+
+        let parallel_store = mock_store();
+        let parallel_deliveries = deliver_txs(
+            &vm,
+            &block,
+            || parallel_store.share(),
+            txs.clone(),
+            &GasMeter::new(),
+            false,
+        );
+
+        assert!(parallel_deliveries[0].result.is_ok());
+        assert!(parallel_deliveries[1].result.is_err());
+        assert!(parallel_deliveries[2].result.is_ok());
+        assert!(parallel_deliveries[3].result.is_ok());
+        assert!(parallel_deliveries.iter().all(|delivery| delivery.traces.is_none()));
+        assert_eq!(
+            parallel_deliveries
+                .into_iter()
+                .map(|delivery| (delivery.result.ok(), delivery.debug_messages))
+                .collect::<Vec<_>>(),
+            sequential_outputs,
+        );
+
+        let state = |store: &Shared<MockStorage>| {
+            store.range(None, None, Order::Ascending).collect::<Vec<Record>>()
+        };
+        assert_eq!(state(&parallel_store), state(&sequential_store));
+
+        // the block's summary, which includes the gas consumed by its txs, is
+        // part of the committed state, so the app hashes must match as well
+        let tx_hashes = txs
+            .iter()
+            .map(|tx| HexBinary::from(sha256(&serde_json::to_vec(tx).unwrap())))
+            .collect::<Vec<_>>();
+        let tempdir = tempfile::tempdir().unwrap();
+        let sequential_trace_file = tempdir.path().join("sequential.jsonl");
+        let parallel_trace_file = tempdir.path().join("parallel.jsonl");
+
+        let mut sequential = mock_state_machine(&sequential_trace_file);
+        sequential.begin_block(block.clone()).unwrap();
+        let sequential_outputs = txs
+            .iter()
+            .cloned()
+            .zip(tx_hashes.clone())
+            .map(|(tx, tx_hash)| sequential.deliver_tx(tx, tx_hash))
+            .collect::<Vec<_>>();
+
+        let mut parallel = mock_state_machine(&parallel_trace_file);
+        parallel.begin_block(block).unwrap();
+        let parallel_outputs = parallel.deliver_txs(txs.into_iter().zip(tx_hashes).collect());
+
+        let outputs = |outputs: Vec<(Result<Vec<Event>>, Vec<DebugMessage>)>| {
+            outputs.into_iter().map(|(result, debug)| (result.ok(), debug)).collect::<Vec<_>>()
+        };
+        assert_eq!(outputs(parallel_outputs), outputs(sequential_outputs));
+        assert_eq!(parallel.commit().unwrap(), sequential.commit().unwrap());
+
+        // the storage operations of discarded speculative executions are not
+        // traced
+        let sequential_traces = fs::read_to_string(sequential_trace_file).unwrap();
+        assert_eq!(sequential_traces.lines().count(), 4);
+        assert_eq!(fs::read_to_string(parallel_trace_file).unwrap(), sequential_traces);
+    }
+}
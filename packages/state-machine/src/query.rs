@@ -1,18 +1,155 @@
-use cosmwasm_std::{Binary, ContractInfo, Env, Order, Storage};
-use cosmwasm_vm::{call_query, Backend, Instance, InstanceOptions, Storage as VmStorage};
-use cw_paginate::{collect, paginate_indexed_map, paginate_map};
+use std::sync::{Arc, RwLock};
+
+use cosmwasm_std::{to_binary, Addr, Binary, ContractInfo, Env, Order, StdResult, Storage};
+use cosmwasm_vm::{call_query, Storage as VmStorage};
+use cw_paginate::{
+    collect, paginate_indexed_map, paginate_map, paginate_map_prefix, DEFAULT_LIMIT, MAX_LIMIT,
+};
 use cw_sdk::{
-    address, Account, AccountResponse, CodeResponse, ContractResponse, InfoResponse,
+    address, Account, AccountResponse, AllowanceResponse, BlockResponse, CodeInfoResponse,
+    CodeResponse, Config, ContractResponse, InfoResponse, JobResponse, SdkQuery,
     WasmRawResponse, WasmSmartResponse,
 };
 use cw_storage_plus::Bound;
+use cw_store::TreeStoreSnapshot;
 
 use crate::{
-    backend::{BackendApi, BackendQuerier, ContractSubstore},
-    error::Result,
-    state::{code_by_address, ACCOUNTS, BLOCK, CODES, CODE_COUNT},
+    backend::{create_instance, ContractSubstore, VmCache},
+    error::{Error, Result},
+    state::{
+        checksum_by_address, ACCOUNTS, ALLOWANCES, BLOCK, BLOCK_SUMMARIES, CODES, CODE_COUNT,
+        CODE_INFOS, CONFIG, JOBS, PINNED_CODES, STORAGE_USAGE,
+    },
 };
 
+/// Serves queries against a snapshot of the last committed state, which the
+/// state machine replaces after each commit.
+///
+/// Unlike the state machine, it can be sent to and shared between threads, and
+/// cloning it creates another handle to the same snapshot, so that queries can
+/// be served by a pool of threads, without waiting for blocks to be executed.
+pub struct QueryHandle<T = TreeStoreSnapshot> {
+    snapshot: Arc<RwLock<T>>,
+    vm: Arc<VmCache>,
+    gas_limit: u64,
+}
+
+impl<T> Clone for QueryHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            snapshot: Arc::clone(&self.snapshot),
+            vm: Arc::clone(&self.vm),
+            gas_limit: self.gas_limit,
+        }
+    }
+}
+
+impl<T> QueryHandle<T>
+where
+    T: Storage + Clone + 'static,
+{
+    pub(crate) fn new(snapshot: T, vm: Arc<VmCache>, gas_limit: u64) -> Self {
+        Self {
+            snapshot: Arc::new(RwLock::new(snapshot)),
+            vm,
+            gas_limit,
+        }
+    }
+
+    /// Serve subsequent queries from the given snapshot.
+    pub(crate) fn update(&self, snapshot: T) {
+        *self.snapshot.write().unwrap() = snapshot;
+    }
+
+    /// Handle a query against the snapshot of the last committed state.
+    pub fn query(&self, query: SdkQuery) -> Result<Binary> {
+        // clone the snapshot, which is cheap, so that the lock isn't held while
+        // the query is handled
+        let store = self.snapshot.read().unwrap().clone();
+        match query {
+            SdkQuery::Info {} => to_binary(&info(&store)?),
+            SdkQuery::Config {} => to_binary(&config(&store)?),
+            SdkQuery::Block {
+                height,
+            } => to_binary(&block(&store, height)?),
+            SdkQuery::Account {
+                address,
+            } => to_binary(&account(&store, address)?),
+            SdkQuery::Accounts {
+                start_after,
+                limit,
+            } => to_binary(&accounts(&store, start_after, limit)?),
+            SdkQuery::AccountsByPubkey {
+                pubkey,
+                start_after,
+                limit,
+            } => to_binary(&accounts_by_pubkey(&store, pubkey, start_after, limit)?),
+            SdkQuery::Contract {
+                label
+            } => to_binary(&contract(&store, label)?),
+            SdkQuery::Contracts {
+                start_after,
+                limit,
+            } => to_binary(&contracts(&store, start_after, limit)?),
+            SdkQuery::ContractsByCode {
+                code_id,
+                start_after,
+                limit,
+            } => to_binary(&contracts_by_code(&store, code_id, start_after, limit)?),
+            SdkQuery::ContractsByAdmin {
+                admin,
+                start_after,
+                limit,
+            } => to_binary(&contracts_by_admin(&store, admin, start_after, limit)?),
+            SdkQuery::Allowance {
+                granter,
+                grantee,
+            } => to_binary(&allowance(&store, granter, grantee)?),
+            SdkQuery::Allowances {
+                granter,
+                start_after,
+                limit,
+            } => to_binary(&allowances(&store, granter, start_after, limit)?),
+            SdkQuery::Code {
+                code_id,
+            } => to_binary(&code(&store, code_id)?),
+            SdkQuery::Codes {
+                start_after,
+                limit,
+            } => to_binary(&codes(&store, start_after, limit)?),
+            SdkQuery::PinnedCodes {
+                start_after,
+                limit,
+            } => to_binary(&pinned_codes(&store, start_after, limit)?),
+            SdkQuery::CodeInfo {
+                code_id,
+            } => to_binary(&code_info(&store, code_id)?),
+            SdkQuery::Job {
+                job_id,
+            } => to_binary(&job(&store, job_id)?),
+            SdkQuery::Jobs {
+                start_after,
+                limit,
+            } => to_binary(&jobs(&store, start_after, limit)?),
+            SdkQuery::WasmRaw {
+                contract,
+                key,
+            } => to_binary(&wasm_raw(store, &contract, &key)?),
+            SdkQuery::WasmSmart {
+                contract,
+                msg,
+            } => to_binary(&wasm_smart(
+                store,
+                &self.vm,
+                self.gas_limit,
+                &contract,
+                &serde_json::to_vec(&msg)?,
+            )?),
+        }
+        .map_err(Error::from)
+    }
+}
+
 pub fn info(store: &dyn Storage) -> Result<InfoResponse> {
     Ok(InfoResponse {
         last_committed_block: BLOCK.load(store)?,
@@ -20,13 +157,25 @@ pub fn info(store: &dyn Storage) -> Result<InfoResponse> {
     })
 }
 
+pub fn config(store: &dyn Storage) -> Result<Config<String>> {
+    Ok(CONFIG.load(store)?.into())
+}
+
+pub fn block(store: &dyn Storage, height: u64) -> Result<BlockResponse> {
+    let summary = BLOCK_SUMMARIES.load(store, height)?;
+    Ok(BlockResponse {
+        block: summary.block,
+        tx_count: summary.tx_count,
+        gas_used: summary.gas_used,
+        failed_txs: summary.failed_txs,
+        events_hash: summary.events_hash,
+    })
+}
+
 pub fn account(store: &dyn Storage, address: String) -> Result<AccountResponse> {
     let addr = address::resolve_raw(&address)?;
     let account = ACCOUNTS.load(store, &addr)?;
-    Ok(AccountResponse {
-        address,
-        account: account.into(),
-    })
+    account_response(store, addr, account)
 }
 
 pub fn accounts(
@@ -36,28 +185,45 @@ pub fn accounts(
 ) -> Result<Vec<AccountResponse>> {
     let start = start_after.map(|address| Bound::ExclusiveRaw(address.into_bytes()));
     paginate_indexed_map(&ACCOUNTS, store, start, limit, |address, account| {
-        Ok(AccountResponse {
-            address: address.into(),
-            account: account.into(),
-        })
+        account_response(store, address, account)
+    })
+}
+
+pub fn accounts_by_pubkey(
+    store: &dyn Storage,
+    pubkey: Binary,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Vec<AccountResponse>> {
+    let start = start_after.map(|address| Bound::ExclusiveRaw(address.into_bytes()));
+    let iter = ACCOUNTS.idx.pubkey.range(store, pubkey.into(), start, None, Order::Ascending);
+    collect(iter, limit, |address, account| account_response(store, address, account))
+}
+
+/// Convert an account to the response type, including its storage usage if
+/// it is a contract.
+fn account_response(
+    store: &dyn Storage,
+    address: Addr,
+    account: Account<Addr>,
+) -> Result<AccountResponse> {
+    let storage_usage = match &account {
+        Account::Contract {
+            ..
+        } => Some(STORAGE_USAGE.may_load(store, &address)?.unwrap_or_default()),
+        _ => None,
+    };
+
+    Ok(AccountResponse {
+        address: address.into(),
+        account: account.into(),
+        storage_usage,
     })
 }
 
 pub fn contract(store: &dyn Storage, label: String) -> Result<ContractResponse> {
     let (address, account) = ACCOUNTS.idx.label.load(store, label)?;
-    match account {
-        Account::Contract {
-            code_id,
-            label,
-            admin,
-        } => Ok(ContractResponse {
-            address: address.into(),
-            code_id,
-            label,
-            admin: admin.map(String::from),
-        }),
-        _ => unreachable!(),
-    }
+    contract_response(address, account)
 }
 
 pub fn contracts(
@@ -67,18 +233,80 @@ pub fn contracts(
 ) -> Result<Vec<ContractResponse>> {
     let start = start_after.map(Bound::exclusive);
     let iter = ACCOUNTS.idx.label.range(store, start, None, Order::Ascending);
-    collect(iter, limit, |address, account| match account {
+    collect(iter, limit, contract_response)
+}
+
+pub fn contracts_by_code(
+    store: &dyn Storage,
+    code_id: u64,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Vec<ContractResponse>> {
+    let start = start_after.map(|address| Bound::ExclusiveRaw(address.into_bytes()));
+    let iter = ACCOUNTS.idx.code_id.range(store, code_id, start, None, Order::Ascending);
+    collect(iter, limit, contract_response)
+}
+
+pub fn contracts_by_admin(
+    store: &dyn Storage,
+    admin: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Vec<ContractResponse>> {
+    let admin_addr = address::resolve_raw(&admin)?;
+    let start = start_after.map(|address| Bound::ExclusiveRaw(address.into_bytes()));
+    let iter = ACCOUNTS.idx.admin.range(store, admin_addr, start, None, Order::Ascending);
+    collect(iter, limit, contract_response)
+}
+
+/// Convert a contract account to the response type. The account must be a
+/// contract; only contracts are indexed by label, code id, or admin.
+fn contract_response(address: Addr, account: Account<Addr>) -> Result<ContractResponse> {
+    match account {
         Account::Contract {
             code_id,
             label,
             admin,
+            non_reentrant,
         } => Ok(ContractResponse {
             address: address.into(),
             code_id,
             label,
             admin: admin.map(String::from),
+            non_reentrant,
         }),
         _ => unreachable!(),
+    }
+}
+
+pub fn allowance(
+    store: &dyn Storage,
+    granter: String,
+    grantee: String,
+) -> Result<AllowanceResponse> {
+    let granter_addr = address::validate(&granter)?;
+    let grantee_addr = address::validate(&grantee)?;
+    Ok(AllowanceResponse {
+        allowance: ALLOWANCES.load(store, (&granter_addr, &grantee_addr))?,
+        granter,
+        grantee,
+    })
+}
+
+pub fn allowances(
+    store: &dyn Storage,
+    granter: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Vec<AllowanceResponse>> {
+    let granter_addr = address::validate(&granter)?;
+    let start = start_after.map(|grantee| Bound::ExclusiveRaw(grantee.into_bytes()));
+    paginate_map_prefix(&ALLOWANCES, store, &granter_addr, start, limit, |grantee, allowance| {
+        Ok(AllowanceResponse {
+            granter: granter.clone(),
+            grantee: grantee.into(),
+            allowance,
+        })
     })
 }
 
@@ -103,7 +331,54 @@ pub fn codes(
     })
 }
 
-pub fn wasm_raw(store: impl Storage, contract: &str, key: &[u8]) -> Result<WasmRawResponse> {
+pub fn pinned_codes(
+    store: &dyn Storage,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<Vec<u64>> {
+    let start = start_after.map(Bound::exclusive);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    PINNED_CODES
+        .items(store, start, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()
+        .map_err(Into::into)
+}
+
+pub fn code_info(store: &dyn Storage, code_id: u64) -> Result<CodeInfoResponse> {
+    let info = CODE_INFOS.load(store, code_id)?;
+    Ok(CodeInfoResponse {
+        code_id,
+        uploader: info.uploader.into(),
+        upload_height: info.upload_height,
+        checksum: info.checksum,
+        size: info.size,
+        required_capabilities: info.required_capabilities.into_iter().collect(),
+    })
+}
+
+pub fn job(store: &dyn Storage, job_id: u64) -> Result<JobResponse> {
+    Ok(JobResponse {
+        job_id,
+        job: JOBS.load(store, job_id)?.into(),
+    })
+}
+
+pub fn jobs(
+    store: &dyn Storage,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<Vec<JobResponse>> {
+    let start = start_after.map(Bound::exclusive);
+    paginate_map(&JOBS, store, start, limit, |job_id, job| {
+        Ok(JobResponse {
+            job_id,
+            job: job.into(),
+        })
+    })
+}
+
+pub fn wasm_raw(store: impl Storage + 'static, contract: &str, key: &[u8]) -> Result<WasmRawResponse> {
     let contract_addr = address::resolve_raw(contract)?;
     let substore = ContractSubstore::new(store, &contract_addr);
     let (value, _) = substore.get(key);
@@ -114,13 +389,15 @@ pub fn wasm_raw(store: impl Storage, contract: &str, key: &[u8]) -> Result<WasmR
 
 pub fn wasm_smart(
     store: impl Storage + 'static,
+    vm: &VmCache,
+    gas_limit: u64,
     contract: &str,
     msg: &[u8],
 ) -> Result<WasmSmartResponse> {
     let contract_addr = address::resolve_raw(contract)?;
 
-    // load contract binary code
-    let code = code_by_address(&store, &contract_addr)?;
+    // load the checksum of the contract binary code
+    let checksum = checksum_by_address(&store, &contract_addr)?;
 
     // load block info and prepare env
     //
@@ -138,17 +415,15 @@ pub fn wasm_smart(
         },
     };
 
-    let mut instance = Instance::from_code(
-        &code,
-        Backend {
-            api: BackendApi,
-            storage: ContractSubstore::new(store, &contract_addr),
-            querier: BackendQuerier,
-        },
-        InstanceOptions {
-            gas_limit: u64::MAX,
-            print_debug: true,
-        },
+    // `call_query` puts the instance's storage in read-only mode, so the VM
+    // rejects writes with an error before they reach the read-only store
+    // wrapper. the query is also limited in gas, so that a runaway contract
+    // can't hang the node
+    let mut instance = create_instance(
+        vm,
+        &checksum,
+        ContractSubstore::new(store, &contract_addr),
+        gas_limit,
         None,
     )?;
 
@@ -158,3 +433,122 @@ pub fn wasm_smart(
         result,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::MockStorage;
+
+    use super::*;
+
+    fn contract(code_id: u64, label: &str, admin: Option<&Addr>) -> (Addr, Account<Addr>) {
+        let address = address::derive_from_label(label).unwrap();
+        let account = Account::Contract {
+            code_id,
+            label: label.into(),
+            admin: admin.cloned(),
+            non_reentrant: false,
+        };
+        (address, account)
+    }
+
+    fn base(label: &str, pubkey: &[u8]) -> (Addr, Account<Addr>) {
+        let address = address::derive_from_label(label).unwrap();
+        let account = Account::Base {
+            pubkey: pubkey.into(),
+            sequence: 0,
+            unordered: false,
+        };
+        (address, account)
+    }
+
+    /// Save the given accounts, and return their addresses in ascending order.
+    fn save_accounts(
+        store: &mut dyn Storage,
+        accounts: Vec<(Addr, Account<Addr>)>,
+    ) -> Vec<String> {
+        let mut addresses = vec![];
+        for (address, account) in accounts {
+            ACCOUNTS.save(store, &address, &account).unwrap();
+            addresses.push(address.into_string());
+        }
+        addresses.sort();
+        addresses
+    }
+
+    #[test]
+    fn querying_contracts_by_code() {
+        let mut store = MockStorage::new();
+        let addresses = save_accounts(&mut store, vec![
+            contract(1, "a", None),
+            contract(1, "b", None),
+            contract(1, "c", None),
+        ]);
+        save_accounts(&mut store, vec![contract(2, "d", None), base("e", b"pubkey")]);
+
+        let contracts = |start_after: Option<&String>, limit| {
+            contracts_by_code(&store, 1, start_after.cloned(), limit)
+                .unwrap()
+                .into_iter()
+                .map(|contract| {
+                    assert_eq!(contract.code_id, 1);
+                    contract.address
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(contracts(None, None), addresses);
+        assert_eq!(contracts(None, Some(2)), addresses[..2]);
+        assert_eq!(contracts(Some(&addresses[0]), None), addresses[1..]);
+        assert!(contracts(Some(&addresses[2]), None).is_empty());
+        assert!(contracts_by_code(&store, 3, None, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn querying_contracts_by_admin() {
+        let mut store = MockStorage::new();
+        let admin = address::derive_from_pubkey(b"admin").unwrap();
+        let other = address::derive_from_pubkey(b"other").unwrap();
+        let addresses = save_accounts(&mut store, vec![
+            contract(1, "a", Some(&admin)),
+            contract(2, "b", Some(&admin)),
+            contract(2, "c", Some(&admin)),
+        ]);
+        save_accounts(&mut store, vec![contract(1, "d", Some(&other)), contract(1, "e", None)]);
+
+        let contracts = |start_after: Option<&String>| {
+            contracts_by_admin(&store, admin.to_string(), start_after.cloned(), None)
+                .unwrap()
+                .into_iter()
+                .map(|contract| {
+                    assert_eq!(contract.admin, Some(admin.to_string()));
+                    contract.address
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(contracts(None), addresses);
+        assert_eq!(contracts(Some(&addresses[1])), addresses[2..]);
+        assert!(contracts(Some(&addresses[2])).is_empty());
+    }
+
+    #[test]
+    fn querying_accounts_by_pubkey() {
+        let mut store = MockStorage::new();
+        let addresses = save_accounts(&mut store, vec![
+            base("a", b"pubkey"),
+            base("b", b"pubkey"),
+        ]);
+        save_accounts(&mut store, vec![base("c", b"other"), contract(1, "d", None)]);
+
+        let accounts = |start_after: Option<&String>| {
+            accounts_by_pubkey(&store, Binary::from(&b"pubkey"[..]), start_after.cloned(), None)
+                .unwrap()
+                .into_iter()
+                .map(|response| response.address)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(accounts(None), addresses);
+        assert_eq!(accounts(Some(&addresses[0])), addresses[1..]);
+        assert!(accounts(Some(&addresses[1])).is_empty());
+        let unknown = Binary::from(&b"unknown"[..]);
+        assert!(accounts_by_pubkey(&store, unknown, None, None).unwrap().is_empty());
+    }
+}
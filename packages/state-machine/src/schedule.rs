@@ -0,0 +1,465 @@
+use cosmwasm_std::{
+    Addr, BlockInfo, Coin, ContractResult, Event, Order, StdError, StdResult, Storage,
+};
+use cw_sdk::{address, Job, SdkMsg};
+use cw_storage_plus::PrefixBound;
+use cw_store::{Cached, Shared};
+use cw_utils::Scheduled;
+use tracing::{debug, error, info};
+
+use crate::{
+    backend::VmCache,
+    deliver,
+    error::{Error, Result},
+    execute::{bank_transfer, CallStack, GasMeter},
+    state::{CONFIG, JOBS, JOBS_BY_HEIGHT, JOBS_BY_TIME, JOB_COUNT},
+};
+
+/// Maximum number of due jobs to be run in a block. The remaining ones are run
+/// in the following blocks.
+pub const MAX_JOBS_PER_BLOCK: usize = 100;
+
+/// Label from which the address holding the gas deposits of scheduled jobs is
+/// derived.
+pub const JOB_DEPOSIT_ESCROW: &str = "job-deposits";
+
+/// Schedule a job, escrowing the deposit paying for its gas limit from the
+/// sender. Fails if the gas limit exceeds the maximum, or if the sender can't
+/// pay the deposit.
+#[allow(clippy::too_many_arguments)]
+pub fn schedule_job<S>(
+    store: &Shared<Cached<S>>,
+    vm: &VmCache,
+    call_stack: &mut CallStack,
+    block: &BlockInfo,
+    sender_addr: &Addr,
+    msg: SdkMsg,
+    at: Scheduled,
+    gas_limit: u64,
+) -> Result<Vec<Event>>
+where
+    S: Storage + 'static,
+{
+    // a job scheduling another one could keep itself alive forever
+    if let SdkMsg::Schedule {
+        ..
+    } = msg
+    {
+        return Err(Error::NestedSchedule);
+    }
+
+    // due jobs are run in BeginBlock, so their gas must be capped, or they
+    // could stall block production
+    let config = CONFIG.load(store)?;
+    if gas_limit > config.max_job_gas_limit {
+        return Err(Error::job_gas_limit_exceeded(gas_limit, config.max_job_gas_limit));
+    }
+
+    let deposit = job_deposit(config.job_gas_price, gas_limit)?;
+    let deposit_events = match &deposit {
+        Some(deposit) => {
+            let escrow_addr = address::derive_from_label(JOB_DEPOSIT_ESCROW)?;
+            transfer_deposit(store, vm, call_stack, block, sender_addr, &escrow_addr, deposit)?
+        },
+        None => vec![],
+    };
+
+    let job_id = JOB_COUNT.may_load(store)?.unwrap_or(0) + 1;
+    JOB_COUNT.save(&mut *store.borrow_mut(), &job_id)?;
+
+    let job = Job {
+        creator: sender_addr.clone(),
+        msg,
+        at,
+        gas_limit,
+        deposit,
+    };
+    JOBS.save(&mut *store.borrow_mut(), job_id, &job)?;
+    match at {
+        Scheduled::AtHeight(height) => {
+            JOBS_BY_HEIGHT.insert(&mut *store.borrow_mut(), (height, job_id))?
+        },
+        Scheduled::AtTime(time) => {
+            JOBS_BY_TIME.insert(&mut *store.borrow_mut(), (time.nanos(), job_id))?
+        },
+    };
+
+    info!(target: "Scheduled job", id = job_id, creator = sender_addr.to_string());
+
+    let mut events = vec![Event::new("schedule")
+        .add_attribute("sender", sender_addr)
+        .add_attribute("job_id", job_id.to_string())
+        .add_attribute("at", at.to_string())];
+    events.extend(deposit_events);
+
+    Ok(events)
+}
+
+/// Cancel a job that has not yet been run, refunding its deposit in full.
+pub fn cancel_job<S>(
+    store: &Shared<Cached<S>>,
+    vm: &VmCache,
+    call_stack: &mut CallStack,
+    block: &BlockInfo,
+    sender_addr: &Addr,
+    job_id: u64,
+) -> Result<Vec<Event>>
+where
+    S: Storage + 'static,
+{
+    let job = JOBS.may_load(store, job_id)?.ok_or_else(|| Error::job_not_found(job_id))?;
+
+    if job.creator != *sender_addr {
+        return Err(Error::not_job_creator(job_id));
+    }
+
+    remove_job(&mut *store.borrow_mut(), job_id, &job)?;
+
+    let deposit_events = match &job.deposit {
+        Some(deposit) => {
+            let escrow_addr = address::derive_from_label(JOB_DEPOSIT_ESCROW)?;
+            transfer_deposit(store, vm, call_stack, block, &escrow_addr, sender_addr, deposit)?
+        },
+        None => vec![],
+    };
+
+    info!(target: "Cancelled job", id = job_id);
+
+    let mut events = vec![Event::new("cancel_job")
+        .add_attribute("sender", sender_addr)
+        .add_attribute("job_id", job_id.to_string())];
+    events.extend(deposit_events);
+
+    Ok(events)
+}
+
+/// Run the jobs that are due as of the given block, on behalf of their
+/// creators, each with its own gas budget.
+///
+/// Each job is run once, and deleted regardless of whether it succeeds. If it
+/// fails, the state changes made by its message are discarded, and the error
+/// is reported in the `run_job` event. Either way, the part of its deposit
+/// paying for the gas it hasn't used is refunded to its creator.
+pub fn run_due_jobs<S>(vm: &VmCache, block: &BlockInfo, store: S) -> Result<Vec<Event>>
+where
+    S: Storage + 'static,
+{
+    let mut cache = Shared::new(Cached::new(store));
+    let mut events = vec![];
+
+    for job_id in due_jobs(&cache, block)? {
+        let job = JOBS.load(&cache, job_id)?;
+        remove_job(&mut cache, job_id, &job)?;
+
+        // execute the message under a checkpoint, which is only committed if
+        // it succeeds
+        let max_call_depth = CONFIG.load(&cache)?.max_call_depth;
+        let gas_meter = GasMeter::new();
+        let checkpoint = cache.borrow_mut().checkpoint();
+        let result = deliver::handle_msg(
+            vm,
+            cache.share(),
+            block.clone(),
+            None,
+            &job.creator,
+            job.msg,
+            &mut CallStack::new(max_call_depth)
+                .with_gas_limit(job.gas_limit)
+                .with_gas_meter(Some(gas_meter.clone())),
+        );
+
+        let event = Event::new("run_job")
+            .add_attribute("job_id", job_id.to_string())
+            .add_attribute("creator", &job.creator);
+        match result {
+            Ok(msg_events) => {
+                cache.borrow_mut().commit(checkpoint);
+                events.push(event.add_attribute("success", "true"));
+                events.extend(msg_events);
+            },
+            Err(err) => {
+                cache.borrow_mut().rollback(checkpoint);
+                debug!(target: "Failed to run job", id = job_id, reason = err.to_string());
+                events.push(
+                    event.add_attribute("success", "false").add_attribute("error", err.to_string()),
+                );
+            },
+        }
+
+        // refund the deposit paid for the gas the job hasn't used. the transfer
+        // is not counted towards the job's gas budget. it can only fail if the
+        // escrow is short of funds, in which case the block still goes on
+        let Some(deposit) = &job.deposit else {
+            continue;
+        };
+        let refund = unused_deposit(deposit, job.gas_limit, gas_meter.gas_used());
+        if refund.amount.is_zero() {
+            continue;
+        }
+
+        let escrow_addr = address::derive_from_label(JOB_DEPOSIT_ESCROW)?;
+        let result = transfer_deposit(
+            &cache,
+            vm,
+            &mut CallStack::new(max_call_depth),
+            block,
+            &escrow_addr,
+            &job.creator,
+            &refund,
+        );
+        match result {
+            Ok(refund_events) => events.extend(refund_events),
+            Err(err) => {
+                let reason = err.to_string();
+                error!(target: "Failed to refund job deposit", id = job_id, reason);
+            },
+        }
+    }
+
+    cache.borrow_mut().flush();
+
+    Ok(events)
+}
+
+/// Transfer a job's deposit, or a part of it, between its creator and the
+/// escrow address.
+fn transfer_deposit<S>(
+    store: &Shared<Cached<S>>,
+    vm: &VmCache,
+    call_stack: &mut CallStack,
+    block: &BlockInfo,
+    from: &Addr,
+    to: &Addr,
+    coin: &Coin,
+) -> Result<Vec<Event>>
+where
+    S: Storage + 'static,
+{
+    let result = bank_transfer(store, vm, call_stack, block, from, to, &[coin.clone()])?;
+
+    match result {
+        ContractResult::Ok(resp) => Ok(resp.events),
+        ContractResult::Err(err) => Err(Error::job_deposit_failed(err)),
+    }
+}
+
+/// Return the deposit paying for the given gas limit at the given price, or
+/// `None` if gas is not priced, or the deposit would be zero.
+fn job_deposit(gas_price: Option<Coin>, gas_limit: u64) -> StdResult<Option<Coin>> {
+    let Some(price) = gas_price else {
+        return Ok(None);
+    };
+
+    let amount = price.amount.checked_mul(gas_limit.into()).map_err(StdError::from)?;
+    if amount.is_zero() {
+        return Ok(None);
+    }
+
+    Ok(Some(Coin {
+        denom: price.denom,
+        amount,
+    }))
+}
+
+/// Return the part of a job's deposit paying for the gas it hasn't used.
+fn unused_deposit(deposit: &Coin, gas_limit: u64, gas_used: u64) -> Coin {
+    Coin {
+        denom: deposit.denom.clone(),
+        amount: deposit.amount.multiply_ratio(gas_limit.saturating_sub(gas_used), gas_limit),
+    }
+}
+
+/// Return the ids of the jobs that are due as of the given block, up to
+/// `MAX_JOBS_PER_BLOCK` of them: first the ones scheduled at block heights,
+/// then the ones scheduled at block times, each in the order they are due.
+fn due_jobs(store: &dyn Storage, block: &BlockInfo) -> StdResult<Vec<u64>> {
+    let mut job_ids = JOBS_BY_HEIGHT
+        .prefix_range(store, None, Some(PrefixBound::inclusive(block.height)), Order::Ascending)
+        .take(MAX_JOBS_PER_BLOCK)
+        .map(|res| res.map(|(_, job_id)| job_id))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let remaining = MAX_JOBS_PER_BLOCK - job_ids.len();
+    let max = Some(PrefixBound::inclusive(block.time.nanos()));
+    for res in JOBS_BY_TIME.prefix_range(store, None, max, Order::Ascending).take(remaining) {
+        let (_, job_id) = res?;
+        job_ids.push(job_id);
+    }
+
+    Ok(job_ids)
+}
+
+fn remove_job(store: &mut dyn Storage, job_id: u64, job: &Job<Addr>) -> StdResult<()> {
+    JOBS.remove(store, job_id);
+    match job.at {
+        Scheduled::AtHeight(height) => JOBS_BY_HEIGHT.remove(store, (height, job_id))?,
+        Scheduled::AtTime(time) => JOBS_BY_TIME.remove(store, (time.nanos(), job_id))?,
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, time::SystemTime};
+
+    use cosmwasm_std::{coin, testing::MockStorage, Timestamp};
+    use cosmwasm_vm::{capabilities_from_csv, CacheOptions};
+    use cw_sdk::Config;
+
+    use super::*;
+    use crate::{execute::AVAILABLE_CAPABILITIES, INSTANCE_MEMORY_LIMIT, MEMORY_CACHE_SIZE};
+
+    fn mock_vm() -> VmCache {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        unsafe {
+            VmCache::new(CacheOptions {
+                base_dir: temp_dir().join(format!("cw-state-machine-test-{nanos}")),
+                available_capabilities: capabilities_from_csv(AVAILABLE_CAPABILITIES),
+                memory_cache_size: MEMORY_CACHE_SIZE,
+                instance_memory_limit: INSTANCE_MEMORY_LIMIT,
+            })
+            .unwrap()
+        }
+    }
+
+    /// Create a store in which jobs can be given up to 1,000 gas, at the given
+    /// price. The bank contract is not deployed.
+    fn mock_store(job_gas_price: Option<Coin>) -> Shared<Cached<MockStorage>> {
+        let mut store = Shared::new(Cached::new(MockStorage::new()));
+        CONFIG
+            .save(&mut store, &Config {
+                max_job_gas_limit: 1_000,
+                job_gas_price,
+                ..Default::default()
+            })
+            .unwrap();
+        store
+    }
+
+    fn mock_block(height: u64, seconds: u64) -> BlockInfo {
+        BlockInfo {
+            height,
+            time: Timestamp::from_seconds(seconds),
+            chain_id: "cw-test".into(),
+        }
+    }
+
+    fn mock_msg() -> SdkMsg {
+        SdkMsg::SetUnorderedTxs {
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn scheduling_and_cancelling_jobs() {
+        let vm = mock_vm();
+        let store = mock_store(None);
+        let block = mock_block(1, 1);
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+
+        let schedule = |sender: &Addr, msg, at| {
+            schedule_job(&store, &vm, &mut CallStack::new(3), &block, sender, msg, at, 0)
+        };
+        let cancel = |sender: &Addr, job_id| {
+            cancel_job(&store, &vm, &mut CallStack::new(3), &block, sender, job_id)
+        };
+
+        let at_time = |seconds| Scheduled::AtTime(Timestamp::from_seconds(seconds));
+        schedule(&alice, mock_msg(), Scheduled::AtHeight(20)).unwrap();
+        schedule(&alice, mock_msg(), at_time(100)).unwrap();
+        schedule(&bob, mock_msg(), Scheduled::AtHeight(10)).unwrap();
+        schedule(&bob, mock_msg(), at_time(200)).unwrap();
+
+        // scheduled messages can't schedule others
+        let err = schedule(
+            &alice,
+            SdkMsg::Schedule {
+                msg: Box::new(mock_msg()),
+                at: Scheduled::AtHeight(30),
+                gas_limit: 0,
+            },
+            Scheduled::AtHeight(20),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::NestedSchedule));
+
+        // jobs are due once their height or time is reached
+        assert_eq!(due_jobs(&store, &mock_block(9, 99)).unwrap(), Vec::<u64>::new());
+        assert_eq!(due_jobs(&store, &mock_block(10, 99)).unwrap(), vec![3]);
+        assert_eq!(due_jobs(&store, &mock_block(20, 100)).unwrap(), vec![3, 1, 2]);
+        assert_eq!(due_jobs(&store, &mock_block(20, 200)).unwrap(), vec![3, 1, 2, 4]);
+
+        // only the creator can cancel a job
+        let err = cancel(&bob, 1).unwrap_err();
+        assert!(matches!(err, Error::NotJobCreator { job_id: 1 }));
+        cancel(&alice, 1).unwrap();
+        cancel(&bob, 4).unwrap();
+        assert_eq!(due_jobs(&store, &mock_block(20, 200)).unwrap(), vec![3, 2]);
+
+        // cancelled jobs no longer exist
+        let err = cancel(&alice, 1).unwrap_err();
+        assert!(matches!(err, Error::JobNotFound { job_id: 1 }));
+    }
+
+    #[test]
+    fn capping_gas_limits() {
+        let vm = mock_vm();
+        let store = mock_store(None);
+        let block = mock_block(1, 1);
+        let alice = Addr::unchecked("alice");
+
+        let schedule = |gas_limit| {
+            let at = Scheduled::AtHeight(10);
+            let mut call_stack = CallStack::new(3);
+            schedule_job(&store, &vm, &mut call_stack, &block, &alice, mock_msg(), at, gas_limit)
+        };
+
+        let err = schedule(1_001).unwrap_err();
+        assert!(matches!(err, Error::JobGasLimitExceeded { gas_limit: 1_001, max: 1_000 }));
+        assert!(JOB_COUNT.may_load(&store).unwrap().is_none());
+
+        schedule(1_000).unwrap();
+        assert_eq!(JOBS.load(&store, 1).unwrap().gas_limit, 1_000);
+    }
+
+    #[test]
+    fn rejecting_jobs_without_deposits() {
+        let vm = mock_vm();
+        let store = mock_store(Some(coin(2, "uatom")));
+        let block = mock_block(1, 1);
+        let alice = Addr::unchecked("alice");
+
+        let schedule = |gas_limit| {
+            let at = Scheduled::AtHeight(10);
+            let mut call_stack = CallStack::new(3);
+            schedule_job(&store, &vm, &mut call_stack, &block, &alice, mock_msg(), at, gas_limit)
+        };
+
+        // the bank contract isn't deployed, so the deposit can't be paid, same
+        // as if alice didn't have the funds. the job isn't scheduled
+        assert!(schedule(500).is_err());
+        assert!(JOB_COUNT.may_load(&store).unwrap().is_none());
+        assert!(!JOBS.has(&store, 1));
+
+        // a job with no gas needs no deposit
+        schedule(0).unwrap();
+        assert_eq!(JOBS.load(&store, 1).unwrap().deposit, None);
+    }
+
+    #[test]
+    fn computing_deposits_and_refunds() {
+        // gas is free unless priced
+        assert_eq!(job_deposit(None, 500).unwrap(), None);
+        assert_eq!(job_deposit(Some(coin(2, "uatom")), 0).unwrap(), None);
+        assert_eq!(job_deposit(Some(coin(2, "uatom")), 500).unwrap(), Some(coin(1_000, "uatom")));
+        assert!(job_deposit(Some(coin(u128::MAX, "uatom")), 2).is_err());
+
+        // the part paying for the unused gas is refunded
+        let deposit = coin(1_000, "uatom");
+        assert_eq!(unused_deposit(&deposit, 500, 0), coin(1_000, "uatom"));
+        assert_eq!(unused_deposit(&deposit, 500, 200), coin(600, "uatom"));
+        assert_eq!(unused_deposit(&deposit, 500, 500), coin(0, "uatom"));
+        assert_eq!(unused_deposit(&deposit, 500, 600), coin(0, "uatom"));
+    }
+}
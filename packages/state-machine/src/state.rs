@@ -1,6 +1,10 @@
-use cosmwasm_std::{Addr, Binary, Storage, BlockInfo};
-use cw_optional_indexes::OptionalUniqueIndex;
-use cw_sdk::Account;
+use std::collections::BTreeSet;
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, Binary, Storage, BlockInfo, Coin, HexBinary, StdResult};
+use cw_item_set::Set;
+use cw_optional_indexes::{OptionalMultiIndex, OptionalUniqueIndex};
+use cw_sdk::{Account, Allowance, Config, Job, StorageUsage};
 use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map};
 
 use crate::error::{Error, Result};
@@ -8,18 +12,133 @@ use crate::error::{Error, Result};
 /// Info of the last committed block.
 pub const BLOCK: Item<BlockInfo> = Item::new("block");
 
+/// Chain-level parameters, set during genesis and updatable by the owner.
+pub const CONFIG: Item<Config<Addr>> = Item::new("config");
+
 /// The total number of wasm byte codes stored on chain.
 pub const CODE_COUNT: Item<u64> = Item::new("code_count");
 
 /// The wasm byte codes, indexed by code ids.
 pub const CODES: Map<u64, Binary> = Map::new("codes");
 
+/// Metadata of the wasm byte codes, indexed by code ids.
+///
+/// Stored separately from the byte codes themselves, so that the metadata can
+/// be loaded without also loading the (potentially large) byte codes.
+pub const CODE_INFOS: Map<u64, CodeInfo> = Map::new("code_infos");
+
+/// Ids of the wasm byte codes whose compiled modules are to be kept in memory.
+pub const PINNED_CODES: Set<u64> = Set::new("pinned_codes");
+
+/// Nonces of unordered txs that have not yet timed out, indexed by sender
+/// addresses and nonces. The values are the txs' timeout heights.
+pub const UNORDERED_NONCES: Map<(&Addr, u64), u64> = Map::new("unordered_nonces");
+
+/// Nonces of unordered txs, indexed by timeout heights and sender addresses,
+/// so that the timed out ones can be efficiently pruned.
+pub const UNORDERED_NONCES_BY_TIMEOUT: Map<(u64, &Addr), BTreeSet<u64>> =
+    Map::new("unordered_nonces_by_timeout");
+
+/// Fee allowances, indexed by granter and grantee addresses.
+pub const ALLOWANCES: Map<(&Addr, &Addr), Allowance> = Map::new("allowances");
+
+/// Storage used by each contract, and the deposits held for it, indexed by
+/// contract addresses.
+pub const STORAGE_USAGE: Map<&Addr, StorageUsage> = Map::new("storage_usage");
+
+/// Deposits paid for the storage of contracts, indexed by contract and
+/// depositor addresses.
+pub const STORAGE_DEPOSITS: Map<(&Addr, &Addr), StorageDeposit> = Map::new("storage_deposits");
+
+/// Total number of jobs ever scheduled, used to assign job ids.
+pub const JOB_COUNT: Item<u64> = Item::new("job_count");
+
+/// Scheduled jobs that have not yet been run, indexed by job ids.
+pub const JOBS: Map<u64, Job<Addr>> = Map::new("jobs");
+
+/// Ids of the jobs scheduled at block heights, indexed by the heights, so that
+/// the due ones can be efficiently found.
+pub const JOBS_BY_HEIGHT: Set<(u64, u64)> = Set::new("jobs_by_height");
+
+/// Ids of the jobs scheduled at block times, indexed by the times in
+/// nanoseconds, so that the due ones can be efficiently found.
+pub const JOBS_BY_TIME: Set<(u64, u64)> = Set::new("jobs_by_time");
+
+/// Summaries of the committed blocks, indexed by block heights.
+pub const BLOCK_SUMMARIES: Map<u64, BlockSummary> = Map::new("block_summaries");
+
+/// Metadata of a wasm byte code, recorded at upload time.
+#[cw_serde]
+pub struct CodeInfo {
+    /// The account who uploaded the code
+    pub uploader: Addr,
+
+    /// The block height at which the code was uploaded
+    pub upload_height: u64,
+
+    /// SHA-256 hash of the wasm byte code
+    pub checksum: HexBinary,
+
+    /// Size of the wasm byte code in bytes
+    pub size: u64,
+
+    /// Capabilities that the code requires the host to support, as declared
+    /// by its `requires_*` exports
+    pub required_capabilities: BTreeSet<String>,
+}
+
+/// The deposit an account has paid for the bytes its calls have added to a
+/// contract's storage.
+#[cw_serde]
+#[derive(Default)]
+pub struct StorageDeposit {
+    /// Number of bytes the deposit has been paid for, less the ones refunded
+    pub bytes: u64,
+
+    /// Coins paid for these bytes, at the prices as of when they were added
+    pub coins: Vec<Coin>,
+}
+
+/// What happened in a block, recorded at commit time, so that the chain's
+/// history can be read through the app, not only Tendermint's tx index.
+#[cw_serde]
+pub struct BlockSummary {
+    /// Height, time and chain id of the block
+    pub block: BlockInfo,
+
+    /// Number of txs included in the block, including failed ones
+    pub tx_count: u32,
+
+    /// Total CosmWasm gas consumed by the contract calls made by the txs,
+    /// including failed ones
+    pub gas_used: u64,
+
+    /// Hashes of the txs that failed
+    pub failed_txs: Vec<HexBinary>,
+
+    /// SHA-256 hash of the events emitted in the block, i.e. in BeginBlock and
+    /// by the successful txs, serialized in JSON in order
+    pub events_hash: HexBinary,
+}
+
+/// Root hashes of the contracts' trees, written by the store on commit; see
+/// `AppLayout`.
+pub const CONTRACT_ROOTS: Map<&Addr, HexBinary> = Map::new("contract_roots");
+
 /// Accounts, either base (i.e. externally-owned) accounts or smart contract
 /// accounts, indexed by addresses.
-/// Contracts are additionally indexed by their labels, which must be unique.
+/// Contracts are additionally indexed by their labels, which must be unique,
+/// as well as by their code ids and admins. Base accounts are indexed by their
+/// public keys.
 pub const ACCOUNTS: IndexedMap<&Addr, Account<Addr>, AccountIndexes> = IndexedMap::new(
     "accounts",
-    AccountIndexes::new("accounts__label"),
+    AccountIndexes::new(
+        "accounts",
+        "accounts__label",
+        "accounts__code_id",
+        "accounts__admin",
+        "accounts__pubkey",
+    ),
 );
 
 /// The index types used to index accounts in cw-sdk
@@ -27,15 +146,37 @@ pub struct AccountIndexes<'a> {
     /// Index accounts by contract labels. If an account is a base account
     /// then it is not indexed.
     pub label: OptionalUniqueIndex<'a, String, Account<Addr>, &'a Addr>,
+
+    /// Index contracts by the ids of their wasm byte codes. Base accounts are
+    /// not indexed.
+    pub code_id: OptionalMultiIndex<'a, u64, Account<Addr>, &'a Addr>,
+
+    /// Index contracts by their admins. Base accounts and contracts without
+    /// an admin are not indexed.
+    pub admin: OptionalMultiIndex<'a, Addr, Account<Addr>, &'a Addr>,
+
+    /// Index base accounts by their public keys. Multisig accounts are not
+    /// indexed, as each of them has several public keys, whereas an index
+    /// function returns at most one key.
+    pub pubkey: OptionalMultiIndex<'a, Vec<u8>, Account<Addr>, &'a Addr>,
 }
 
 impl<'a> AccountIndexes<'a> {
-    pub const fn new(label_namespace: &'a str) -> Self {
+    pub const fn new(
+        pk_namespace: &'a str,
+        label_namespace: &'a str,
+        code_id_namespace: &'a str,
+        admin_namespace: &'a str,
+        pubkey_namespace: &'a str,
+    ) -> Self {
         Self {
             label: OptionalUniqueIndex::new(
                 |account| match account {
                     Account::Base {
                         ..
+                    }
+                    | Account::Multisig {
+                        ..
                     } => None,
                     Account::Contract {
                         label,
@@ -44,19 +185,87 @@ impl<'a> AccountIndexes<'a> {
                 },
                 label_namespace,
             ),
+            code_id: OptionalMultiIndex::new(
+                |account| match account {
+                    Account::Base {
+                        ..
+                    }
+                    | Account::Multisig {
+                        ..
+                    } => None,
+                    Account::Contract {
+                        code_id,
+                        ..
+                    } => Some(*code_id),
+                },
+                pk_namespace,
+                code_id_namespace,
+            ),
+            admin: OptionalMultiIndex::new(
+                |account| match account {
+                    Account::Base {
+                        ..
+                    }
+                    | Account::Multisig {
+                        ..
+                    } => None,
+                    Account::Contract {
+                        admin,
+                        ..
+                    } => admin.clone(),
+                },
+                pk_namespace,
+                admin_namespace,
+            ),
+            pubkey: OptionalMultiIndex::new(
+                |account| match account {
+                    Account::Base {
+                        pubkey,
+                        ..
+                    } => Some(pubkey.to_vec()),
+                    Account::Multisig {
+                        ..
+                    }
+                    | Account::Contract {
+                        ..
+                    } => None,
+                },
+                pk_namespace,
+                pubkey_namespace,
+            ),
         }
     }
 }
 
 impl<'a> IndexList<Account<Addr>> for AccountIndexes<'a> {
     fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Account<Addr>>> + '_> {
-        let v: Vec<&dyn Index<Account<Addr>>> = vec![&self.label];
+        let v: Vec<&dyn Index<Account<Addr>>> =
+            vec![&self.label, &self.code_id, &self.admin, &self.pubkey];
         Box::new(v.into_iter())
     }
 }
 
-/// Helper function for loading the wasm code of a given contract address.
-pub fn code_by_address(store: &dyn Storage, contract_addr: &Addr) -> Result<Binary> {
+/// Update a contract's storage usage after one of its entries is written or
+/// deleted. The sizes are those of the entry's key plus value, before and after
+/// the write, where zero means the entry doesn't exist.
+pub fn update_storage_usage(
+    store: &mut dyn Storage,
+    contract_addr: &Addr,
+    old_size: u64,
+    new_size: u64,
+) -> StdResult<()> {
+    if old_size == new_size {
+        return Ok(());
+    }
+
+    let mut usage = STORAGE_USAGE.may_load(store, contract_addr)?.unwrap_or_default();
+    usage.bytes = usage.bytes + new_size - old_size;
+    STORAGE_USAGE.save(store, contract_addr, &usage)
+}
+
+/// Helper function for loading the checksum of the wasm code of a given
+/// contract address.
+pub fn checksum_by_address(store: &dyn Storage, contract_addr: &Addr) -> Result<HexBinary> {
     let code_id = match ACCOUNTS.may_load(store, contract_addr)? {
         Some(Account::Contract {
             code_id,
@@ -64,6 +273,9 @@ pub fn code_by_address(store: &dyn Storage, contract_addr: &Addr) -> Result<Bina
         }) => code_id,
         Some(Account::Base {
             ..
+        })
+        | Some(Account::Multisig {
+            ..
         }) => {
             return Err(Error::account_is_not_contract(contract_addr));
         },
@@ -71,12 +283,16 @@ pub fn code_by_address(store: &dyn Storage, contract_addr: &Addr) -> Result<Bina
             return Err(Error::account_not_found(contract_addr));
         },
     };
-    CODES.load(store, code_id).map_err(Error::from)
+    CODE_INFOS
+        .load(store, code_id)
+        .map(|info| info.checksum)
+        .map_err(Error::from)
 }
 
 #[cfg(test)]
 mod tests {
     use cosmwasm_std::{testing::MockStorage, Order, StdError, StdResult};
+    use cw_storage_plus::Bound;
 
     use super::*;
 
@@ -93,20 +309,24 @@ mod tests {
             Account::Base {
                 pubkey: b"base1pubkey".into(),
                 sequence: 0,
+                unordered: false,
             },
             Account::Base {
                 pubkey: b"base2pubkey".into(),
                 sequence: 123,
+                unordered: false,
             },
             Account::Contract {
                 code_id: 234,
                 label: "bank".into(),
                 admin: None,
+                non_reentrant: false,
             },
             Account::Contract {
                 code_id: 345,
                 label: "token-factory".into(),
                 admin: Some(Addr::unchecked("larry")),
+                non_reentrant: false,
             },
         ];
 
@@ -147,6 +367,98 @@ mod tests {
         assert_eq!(items.len(), 2)
     }
 
+    #[test]
+    fn indexing_contracts_by_code_and_admin() {
+        let mut store = MockStorage::new();
+
+        let accounts = [
+            ("base", Account::Base {
+                pubkey: b"basepubkey".into(),
+                sequence: 0,
+                unordered: false,
+            }),
+            ("bank", Account::Contract {
+                code_id: 1,
+                label: "bank".into(),
+                admin: None,
+                non_reentrant: false,
+            }),
+            ("token-factory", Account::Contract {
+                code_id: 2,
+                label: "token-factory".into(),
+                admin: Some(Addr::unchecked("larry")),
+                non_reentrant: false,
+            }),
+            ("token-factory-2", Account::Contract {
+                code_id: 2,
+                label: "token-factory-2".into(),
+                admin: Some(Addr::unchecked("larry")),
+                non_reentrant: false,
+            }),
+        ];
+
+        for (addr, acct) in &accounts {
+            ACCOUNTS.save(&mut store, &Addr::unchecked(*addr), acct).unwrap();
+        }
+
+        let by_code = |store: &MockStorage, code_id: u64| {
+            ACCOUNTS
+                .idx
+                .code_id
+                .range(store, code_id, None, None, Order::Ascending)
+                .map(|res| res.map(|(addr, _)| addr))
+                .collect::<StdResult<Vec<_>>>()
+                .unwrap()
+        };
+        let by_admin = |store: &MockStorage, admin: &str| {
+            ACCOUNTS
+                .idx
+                .admin
+                .range(store, Addr::unchecked(admin), None, None, Order::Ascending)
+                .map(|res| res.map(|(addr, _)| addr))
+                .collect::<StdResult<Vec<_>>>()
+                .unwrap()
+        };
+
+        assert_eq!(by_code(&store, 1), vec![Addr::unchecked("bank")]);
+        assert_eq!(by_code(&store, 2), vec![
+            Addr::unchecked("token-factory"),
+            Addr::unchecked("token-factory-2"),
+        ]);
+        assert_eq!(by_admin(&store, "larry"), vec![
+            Addr::unchecked("token-factory"),
+            Addr::unchecked("token-factory-2"),
+        ]);
+
+        // change the admin of a contract. the old index entry should be removed
+        ACCOUNTS
+            .save(&mut store, &Addr::unchecked("token-factory"), &Account::Contract {
+                code_id: 2,
+                label: "token-factory".into(),
+                admin: Some(Addr::unchecked("jake")),
+                non_reentrant: false,
+            })
+            .unwrap();
+        assert_eq!(by_admin(&store, "larry"), vec![Addr::unchecked("token-factory-2")]);
+        assert_eq!(by_admin(&store, "jake"), vec![Addr::unchecked("token-factory")]);
+
+        // pagination by address
+        let addrs = ACCOUNTS
+            .idx
+            .code_id
+            .range(
+                &store,
+                2,
+                Some(Bound::ExclusiveRaw(b"token-factory".to_vec())),
+                None,
+                Order::Ascending,
+            )
+            .map(|res| res.map(|(addr, _)| addr))
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(addrs, vec![Addr::unchecked("token-factory-2")]);
+    }
+
     #[test]
     fn rejecting_duplicate_indexes() {
         let mut store = MockStorage::new();
@@ -157,6 +469,7 @@ mod tests {
             code_id: 234,
             label: "bank".into(),
             admin: None,
+            non_reentrant: false,
         };
         ACCOUNTS.save(&mut store, &addr, &acct).unwrap();
 
@@ -171,6 +484,7 @@ mod tests {
             code_id: 42069,
             label: "bank".into(), // same label but different code id and admin
             admin: Some(Addr::unchecked("jake")),
+            non_reentrant: false,
         };
         let res = ACCOUNTS.save(&mut store, &addr, &acct);
         assert!(res.is_ok());
@@ -181,6 +495,7 @@ mod tests {
             code_id: 345,
             label: "bank".into(), // pretend we type the wrong label by mistake; should be `token-factory`
             admin: None,
+            non_reentrant: false,
         };
         let err = ACCOUNTS.save(&mut store, &addr, &acct).unwrap_err();
         assert_eq!(err, StdError::generic_err("Violates unique constraint on index"));
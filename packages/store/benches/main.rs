@@ -0,0 +1,104 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use cosmwasm_std::{Order, Storage};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use cw_store::{Cached, CommitStore, MemStore, Shared, TreeStore};
+
+/// Number of keys in the committed state
+const KEY_COUNT: u32 = 1_000_000;
+
+/// Number of keys committed per block when setting up the state
+const KEYS_PER_BLOCK: u32 = 100_000;
+
+/// Number of pending writes, made on top of the committed state
+const PENDING_COUNT: u32 = 1_000;
+
+/// Number of records returned by each query, as in a typical paginated query
+const PAGE_SIZE: usize = 10;
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_be_bytes()
+}
+
+fn setup_store<S>(store: &S)
+where
+    S: CommitStore,
+    S::Error: Debug,
+{
+    for block in 0..KEY_COUNT / KEYS_PER_BLOCK {
+        let mut wrapper = store.pending_wrap();
+        for i in block * KEYS_PER_BLOCK..(block + 1) * KEYS_PER_BLOCK {
+            wrapper.set(&key(i), b"value");
+        }
+        store.commit().unwrap();
+    }
+
+    // overwrite and delete some keys around the pages being queried, without
+    // committing them
+    let mut wrapper = store.pending_wrap();
+    for i in 0..PENDING_COUNT {
+        let i = KEY_COUNT / 2 + i * 2;
+        if i % 4 == 0 {
+            wrapper.remove(&key(i));
+        } else {
+            wrapper.set(&key(i), b"new_value");
+        }
+    }
+}
+
+/// Query pages of records, starting from different positions in the state.
+fn paginate(store: &dyn Storage, order: Order) {
+    for i in 0..10 {
+        let start = key(KEY_COUNT / 2 + i * 100);
+        let (start, end) = match order {
+            Order::Ascending => (Some(start.as_slice()), None),
+            Order::Descending => (None, Some(start.as_slice())),
+        };
+        let count = store.range(start, end, order).take(PAGE_SIZE).count();
+        assert_eq!(black_box(count), PAGE_SIZE);
+    }
+}
+
+fn bench_paginated_queries(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let tree_store = TreeStore::open(dir.path()).unwrap();
+    setup_store(&tree_store);
+
+    let mem_store = MemStore::new();
+    setup_store(&mem_store);
+
+    let mut group = c.benchmark_group("Paginated queries over 1M keys");
+    group.measurement_time(Duration::from_secs(10));
+
+    for order in [Order::Ascending, Order::Descending] {
+        let name = |store: &str| format!("{store}, {order:?}");
+
+        group.bench_function(name("TreeStore, committed"), |b| {
+            let wrapper = tree_store.wrap();
+            b.iter(|| paginate(&wrapper, order))
+        });
+
+        group.bench_function(name("TreeStore, pending"), |b| {
+            let wrapper = tree_store.pending_wrap();
+            b.iter(|| paginate(&wrapper, order))
+        });
+
+        // as seen by a contract executing in a tx
+        group.bench_function(name("TreeStore, cached"), |b| {
+            let cached = Shared::new(Cached::new(tree_store.pending_wrap()));
+            b.iter(|| paginate(&cached, order))
+        });
+
+        group.bench_function(name("MemStore, pending"), |b| {
+            let wrapper = mem_store.pending_wrap();
+            b.iter(|| paginate(&wrapper, order))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_paginated_queries);
+criterion_main!(benches);
@@ -1,18 +1,19 @@
-use std::{collections::BTreeMap, iter};
+use std::{collections::BTreeMap, iter, mem};
 
 use cosmwasm_std::{Order, Record, Storage};
+use cw_sdk::StorageOp;
 use merk::Op;
 
 use crate::{
-    clone_op,
     iterators::{range_bounds, MergedIter},
+    Tracer,
 };
 
 /// Holds an immutable reference of any storage object that implements the
 /// `Storage` trait, and a temporary, in-memory cache of uncommitted ops.
 ///
-/// If the ops are to be committed to the underlying store, use the `prepare`
-/// method to consume the cache, and then `flush`:
+/// If the ops are to be committed to the underlying store, use the `flush`
+/// method, and then `recycle` the underlying store:
 ///
 /// ```rust
 /// use cosmwasm_std::{testing::MockStorage, Storage};
@@ -22,11 +23,52 @@ use crate::{
 /// let mut cache = Cached::new(store);
 ///
 /// cache.set(b"key1", b"value1");
-/// let store = cache.flush();
+/// cache.flush();
+/// let store = cache.recycle();
 /// ```
+///
+/// Nested calls, such as contract calls, don't need caches of their own: they
+/// can create a checkpoint, and either roll the cache back to it if they fail,
+/// or commit it if they succeed:
+///
+/// ```rust
+/// use cosmwasm_std::{testing::MockStorage, Storage};
+/// use cw_store::Cached;
+///
+/// let mut cache = Cached::new(MockStorage::new());
+/// cache.set(b"key1", b"value1");
+///
+/// let checkpoint = cache.checkpoint();
+/// cache.set(b"key1", b"value2");
+/// cache.rollback(checkpoint);
+///
+/// assert_eq!(cache.get(b"key1"), Some(b"value1".to_vec()));
+/// ```
+///
+/// If a tracer is attached, every operation made on the cache is recorded.
 pub struct Cached<T: Storage> {
     store: T,
     pending_ops: BTreeMap<Vec<u8>, Op>,
+    /// For each write made while there is a checkpoint, the key written, and
+    /// the pending op it replaced, if any, so that writes can be undone.
+    journal: Vec<(Vec<u8>, Option<Op>)>,
+    /// For each live checkpoint, its id, and the length of the journal at the
+    /// time it was created.
+    checkpoints: Vec<(u64, usize)>,
+    /// The id to be given to the next checkpoint. Ids are never reused, so
+    /// that a released checkpoint can't be mistaken for a later one created
+    /// at the same position in the stack.
+    next_checkpoint_id: u64,
+    tracer: Option<Tracer>,
+}
+
+/// A checkpoint in the pending ops of a `Cached`, created by
+/// `Cached::checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use = "a checkpoint must be either rolled back or committed"]
+pub struct Checkpoint {
+    index: usize,
+    id: u64,
 }
 
 impl<T: Storage> Cached<T> {
@@ -34,17 +76,95 @@ impl<T: Storage> Cached<T> {
         Self {
             store,
             pending_ops: BTreeMap::new(),
+            journal: vec![],
+            checkpoints: vec![],
+            next_checkpoint_id: 0,
+            tracer: None,
+        }
+    }
+
+    /// Attach a tracer, if provided, to record the operations made on the cache.
+    pub fn with_tracer(self, tracer: Option<Tracer>) -> Self {
+        Self {
+            tracer,
+            ..self
+        }
+    }
+
+    fn trace(&self, op: impl FnOnce() -> StorageOp) {
+        if let Some(tracer) = &self.tracer {
+            tracer.record(None, op());
         }
     }
 
-    /// Apply the pending ops to the underlying store.
+    /// Create a checkpoint, to which the pending ops can later be rolled back.
+    /// Checkpoints can be nested, e.g. one for each contract call in a call
+    /// stack.
+    ///
+    /// Takes constant time, as writes are only recorded in a journal as they
+    /// are made, rather than the pending ops being copied.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        let id = self.next_checkpoint_id;
+        self.next_checkpoint_id += 1;
+        self.checkpoints.push((id, self.journal.len()));
+        Checkpoint {
+            index: self.checkpoints.len() - 1,
+            id,
+        }
+    }
+
+    /// Release the checkpoint, along with the ones created after it, and
+    /// return the length of the journal at the time it was created.
+    ///
+    /// Panics if the checkpoint has already been released.
+    fn release(&mut self, checkpoint: Checkpoint, action: &str) -> usize {
+        let len = match self.checkpoints.get(checkpoint.index) {
+            Some((id, len)) if *id == checkpoint.id => *len,
+            _ => panic!("[cw-store]: {action} a checkpoint that has been released"),
+        };
+        self.checkpoints.truncate(checkpoint.index);
+        len
+    }
+
+    /// Undo the writes made since the checkpoint was created, and release it,
+    /// along with the checkpoints created after it.
+    ///
+    /// Takes time proportional to the number of writes undone, which has
+    /// already been spent making them.
+    ///
+    /// Panics if the checkpoint has already been released.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        let len = self.release(checkpoint, "rolling back to");
+
+        for (key, op) in self.journal.drain(len..).rev() {
+            match op {
+                Some(op) => self.pending_ops.insert(key, op),
+                None => self.pending_ops.remove(&key),
+            };
+        }
+    }
+
+    /// Keep the writes made since the checkpoint was created, and release it,
+    /// along with the checkpoints created after it.
+    ///
+    /// Takes constant time: the writes stay where they are, and are only
+    /// undone if an earlier checkpoint is rolled back to.
+    ///
+    /// Panics if the checkpoint has already been released.
+    pub fn commit(&mut self, checkpoint: Checkpoint) {
+        self.release(checkpoint, "committing");
+        if self.checkpoints.is_empty() {
+            self.journal.clear();
+        }
+    }
+
+    /// Apply the pending ops to the underlying store, moving rather than
+    /// cloning them. Checkpoints are released.
     pub fn flush(&mut self) {
-        // unlike hashmap, btreemap doesn't have a handy `drain` method
-        // so we have to do this, which is very inefficient:
-        let batch: Vec<_> = self.pending_ops.iter().map(clone_op).collect();
-        self.pending_ops = BTreeMap::new();
+        self.journal.clear();
+        self.checkpoints.clear();
 
-        for (key, op) in batch {
+        for (key, op) in mem::take(&mut self.pending_ops) {
             match op {
                 Op::Put(value) => self.store.set(&key, &value),
                 Op::Delete => self.store.remove(&key),
@@ -52,31 +172,57 @@ impl<T: Storage> Cached<T> {
         }
     }
 
+    /// Record a pending op, journaling the one it replaces if there is a
+    /// checkpoint.
+    fn write(&mut self, key: &[u8], op: Op) {
+        let replaced = self.pending_ops.insert(key.to_vec(), op);
+        if !self.checkpoints.is_empty() {
+            self.journal.push((key.to_vec(), replaced));
+        }
+    }
+
     /// Consume self, discard the pending ops, return the underlying store.
     pub fn recycle(self) -> T {
         self.store
     }
+
+    /// Consume self, discard the underlying store, return the pending ops,
+    /// where `None` means the key is to be deleted.
+    ///
+    /// Used when the ops are to be applied to a store other than the one the
+    /// cache was created on.
+    pub fn into_pending_ops(self) -> BTreeMap<Vec<u8>, Option<Vec<u8>>> {
+        self.pending_ops
+            .into_iter()
+            .map(|(key, op)| match op {
+                Op::Put(value) => (key, Some(value)),
+                Op::Delete => (key, None),
+            })
+            .collect()
+    }
 }
 
 // this block of code is basically duplicate from PendingStoreWrapper
 // it'd be better if we can avoid duplication
 impl<T: Storage> Storage for Cached<T> {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        let Some(op) = self.pending_ops.get(key) else {
-            return self.store.get(key);
+        let value = match self.pending_ops.get(key) {
+            Some(Op::Put(value)) => Some(value.clone()),
+            Some(Op::Delete) => None,
+            None => self.store.get(key),
         };
-        match op {
-            Op::Put(value) => Some(value.clone()),
-            Op::Delete => None,
-        }
+        self.trace(|| StorageOp::get(key, value.as_deref()));
+        value
     }
 
     fn set(&mut self, key: &[u8], value: &[u8]) {
-        self.pending_ops.insert(key.to_vec(), Op::Put(value.to_vec()));
+        self.trace(|| StorageOp::set(key, value));
+        self.write(key, Op::Put(value.to_vec()));
     }
 
     fn remove(&mut self, key: &[u8]) {
-        self.pending_ops.insert(key.to_vec(), Op::Delete);
+        self.trace(|| StorageOp::remove(key));
+        self.write(key, Op::Delete);
     }
 
     fn range<'b>(
@@ -85,6 +231,8 @@ impl<T: Storage> Storage for Cached<T> {
         end: Option<&[u8]>,
         order: Order,
     ) -> Box<dyn Iterator<Item = Record> + 'b> {
+        self.trace(|| StorageOp::scan(start, end, order == Order::Descending));
+
         if let (Some(start), Some(end)) = (start, end) {
             if start > end {
                 return Box::new(iter::empty());
@@ -153,6 +301,146 @@ mod tests {
         assert_eq!(items, kv());
     }
 
+    #[test]
+    fn taking_pending_ops() {
+        let mut store = MockStorage::default();
+        setup_store(&mut store);
+
+        let mut cache = Cached::new(store);
+        setup_cache(&mut cache);
+
+        assert_eq!(
+            cache.into_pending_ops(),
+            BTreeMap::from([
+                (b"key2".to_vec(), Some(b"value23456".to_vec())),
+                (b"key3".to_vec(), None),
+                (b"key3333".to_vec(), Some(b"value3333".to_vec())),
+            ])
+        );
+    }
+
+    #[test]
+    fn checkpointing() {
+        let mut store = MockStorage::default();
+        setup_store(&mut store);
+
+        let mut cache = Cached::new(store);
+        setup_cache(&mut cache);
+
+        // nested checkpoints, the inner one of which is rolled back
+        let outer = cache.checkpoint();
+        cache.set(b"key1", b"value11");
+        cache.remove(b"key2");
+
+        let inner = cache.checkpoint();
+        cache.set(b"key1", b"value111");
+        cache.set(b"key3", b"value33");
+        cache.set(b"key5", b"value5");
+        cache.rollback(inner);
+
+        assert_eq!(cache.get(b"key1"), Some(b"value11".to_vec()));
+        assert_eq!(cache.get(b"key2"), None);
+        assert_eq!(cache.get(b"key3"), None);
+        assert_eq!(cache.get(b"key5"), None);
+
+        // committing the inner checkpoint keeps its writes, until the outer one
+        // is rolled back
+        let inner = cache.checkpoint();
+        cache.set(b"key5", b"value5");
+        cache.commit(inner);
+        assert_eq!(cache.get(b"key5"), Some(b"value5".to_vec()));
+
+        cache.rollback(outer);
+        assert_eq!(cache.into_pending_ops(), BTreeMap::from([
+            (b"key2".to_vec(), Some(b"value23456".to_vec())),
+            (b"key3".to_vec(), None),
+            (b"key3333".to_vec(), Some(b"value3333".to_vec())),
+        ]));
+    }
+
+    #[test]
+    fn rolling_back_released_checkpoints() {
+        let mut cache = Cached::new(MockStorage::default());
+
+        // rolling back to a checkpoint releases the ones created after it,
+        // even if they haven't been rolled back or committed themselves
+        let outer = cache.checkpoint();
+        cache.set(b"key1", b"value1");
+        let _inner = cache.checkpoint();
+        cache.set(b"key2", b"value2");
+        cache.rollback(outer);
+        assert!(cache.into_pending_ops().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "rolling back to a checkpoint that has been released")]
+    fn rolling_back_twice() {
+        let mut cache = Cached::new(MockStorage::default());
+        let checkpoint = cache.checkpoint();
+        cache.rollback(checkpoint);
+        cache.rollback(checkpoint);
+    }
+
+    #[test]
+    #[should_panic(expected = "committing a checkpoint that has been released")]
+    fn committing_twice() {
+        let mut cache = Cached::new(MockStorage::default());
+        let checkpoint = cache.checkpoint();
+        cache.commit(checkpoint);
+        cache.commit(checkpoint);
+    }
+
+    #[test]
+    #[should_panic(expected = "rolling back to a checkpoint that has been released")]
+    fn rolling_back_to_checkpoint_taking_the_place_of_a_released_one() {
+        let mut cache = Cached::new(MockStorage::default());
+        let released = cache.checkpoint();
+        cache.commit(released);
+
+        // the new checkpoint is at the same position in the stack as the
+        // released one, but the released one's handle mustn't refer to it
+        let _checkpoint = cache.checkpoint();
+        cache.set(b"key1", b"value1");
+        cache.rollback(released);
+    }
+
+    #[test]
+    #[should_panic(expected = "committing a checkpoint that has been released")]
+    fn committing_after_flushing() {
+        let mut cache = Cached::new(MockStorage::default());
+        let checkpoint = cache.checkpoint();
+        cache.flush();
+        cache.commit(checkpoint);
+    }
+
+    #[test]
+    fn tracing_ops() {
+        let mut store = MockStorage::default();
+        setup_store(&mut store);
+
+        let tracer = Tracer::new();
+        let mut cache = Cached::new(store).with_tracer(Some(tracer.clone()));
+
+        cache.get(b"key1");
+        cache.set(b"key1", b"value12");
+        cache.remove(b"key2");
+        cache.get(b"key2");
+        cache.range(Some(b"key1"), None, Order::Descending);
+
+        let ops = tracer.take().into_iter().map(|trace| trace.op).collect::<Vec<_>>();
+        assert_eq!(ops, vec![
+            StorageOp::get(b"key1", Some(b"value1")),
+            StorageOp::set(b"key1", b"value12"),
+            StorageOp::remove(b"key2"),
+            StorageOp::get(b"key2", None),
+            StorageOp::scan(Some(b"key1"), None, true),
+        ]);
+
+        // ops made after taking the traces are recorded anew
+        cache.get(b"key3");
+        assert_eq!(tracer.take().len(), 1);
+    }
+
     #[cfg(feature = "iterator")]
     #[test]
     fn iterating() {
@@ -0,0 +1,41 @@
+use cosmwasm_std::Storage;
+use cw_sdk::hash::HASH_LENGTH;
+
+/// A store holding the blockchain state, whose changes are kept pending during
+/// a block, and committed at the end of it.
+///
+/// Implemented by the Merk-backed `Store` used by nodes, and by the in-memory
+/// `MemStore`, which can be used in tests without creating a database on disk.
+pub trait CommitStore {
+    /// Read-only view of the committed state, disregarding pending changes.
+    type Wrapper: Storage + 'static;
+
+    /// Read-and-write view of the state, including pending changes.
+    type PendingWrapper: Storage + 'static;
+
+    /// Read-only view of the committed state as of when it's taken, which later
+    /// commits don't affect. Unlike the wrappers, it can be sent to and shared
+    /// between threads, and cloning it is cheap.
+    type Snapshot: Storage + Clone + Send + Sync + 'static;
+
+    /// Error that may occur when committing.
+    type Error;
+
+    /// Wrap the store for reading the committed state, e.g. in the "Query"
+    /// ABCI request.
+    fn wrap(&self) -> Self::Wrapper;
+
+    /// Wrap the store for reading and writing the pending state, e.g. in the
+    /// BeginBlock and DeliverTx ABCI requests.
+    fn pending_wrap(&self) -> Self::PendingWrapper;
+
+    /// Take a snapshot of the committed state, e.g. after each commit, so that
+    /// queries can be served from other threads in the meantime.
+    fn snapshot(&self) -> Self::Snapshot;
+
+    /// Commit the pending changes, during the "Commit" ABCI request.
+    fn commit(&self) -> Result<(), Self::Error>;
+
+    /// Derive the root hash of the committed state.
+    fn root_hash(&self) -> [u8; HASH_LENGTH];
+}
@@ -0,0 +1,127 @@
+use std::{mem, path::Path, sync::Arc};
+
+use cosmwasm_std::{Order, Record, Storage};
+use rocksdb::{Snapshot, DB};
+
+use crate::{iterators::RawIter, snapshot::SnapshotDb};
+
+/// A RocksDB database with the `cosmwasm_std::Storage` trait implemented, in
+/// which `TreeStore` persists the nodes of its tree.
+///
+/// Similar to `must_get` for Merk, a failed read or write is considered fatal,
+/// and panics.
+///
+/// Cloning it is cheap, and the clones share the same database, so that it can
+/// be written to from another thread, e.g. for pruning.
+#[derive(Clone)]
+pub struct Db {
+    inner: Arc<DB>,
+}
+
+impl Db {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, rocksdb::Error> {
+        Ok(Self {
+            inner: Arc::new(DB::open_default(path)?),
+        })
+    }
+}
+
+impl Storage for Db {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get(key).unwrap_or_else(|err| {
+            panic!("[cw-store]: failed to read key {} from database: {err}", hex::encode(key));
+        })
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.inner.put(key, value).unwrap_or_else(|err| {
+            panic!("[cw-store]: failed to write key {} to database: {err}", hex::encode(key));
+        })
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.inner.delete(key).unwrap_or_else(|err| {
+            panic!("[cw-store]: failed to delete key {} from database: {err}", hex::encode(key));
+        })
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(std::iter::empty());
+            }
+        }
+
+        let mut raw = RawIter::new(self.inner.raw_iterator(), start, end, order);
+        Box::new(std::iter::from_fn(move || {
+            raw.next().map(|(key, value)| (key.to_vec(), value.to_vec()))
+        }))
+    }
+}
+
+impl SnapshotDb for Db {
+    type Snapshot = DbSnapshot;
+
+    fn snapshot(&self) -> DbSnapshot {
+        let snapshot = self.inner.snapshot();
+
+        // SAFETY: the snapshot borrows the database, which the `Arc` stored next
+        // to it keeps alive for as long as the snapshot exists. the snapshot is
+        // declared first, so it is dropped before the `Arc`.
+        let snapshot = unsafe { mem::transmute::<Snapshot<'_>, Snapshot<'static>>(snapshot) };
+
+        DbSnapshot {
+            snapshot,
+            _db: Arc::clone(&self.inner),
+        }
+    }
+}
+
+/// A point-in-time, read-only view of a `Db`, backed by a RocksDB snapshot, so
+/// that writes made to the database afterwards aren't seen. It can be sent to,
+/// and read from, other threads.
+///
+/// Being a raw view of the database, writing to it is a bug, and panics.
+pub struct DbSnapshot {
+    snapshot: Snapshot<'static>,
+    _db: Arc<DB>,
+}
+
+impl Storage for DbSnapshot {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.snapshot.get(key).unwrap_or_else(|err| {
+            panic!("[cw-store]: failed to read key {} from snapshot: {err}", hex::encode(key));
+        })
+    }
+
+    fn set(&mut self, key: &[u8], _value: &[u8]) {
+        panic!("[cw-store]: attempted to write key {} to a snapshot", hex::encode(key));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        panic!("[cw-store]: attempted to delete key {} from a snapshot", hex::encode(key));
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(std::iter::empty());
+            }
+        }
+
+        let mut raw = RawIter::new(self.snapshot.raw_iterator(), start, end, order);
+        Box::new(std::iter::from_fn(move || {
+            raw.next().map(|(key, value)| (key.to_vec(), value.to_vec()))
+        }))
+    }
+}
@@ -0,0 +1,35 @@
+use cw_tree::TreeError;
+
+/// Errors that can occur when opening or rolling back a `TreeStore`.
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error(transparent)]
+    Db(#[from] rocksdb::Error),
+
+    #[error(transparent)]
+    Tree(#[from] TreeError),
+
+    #[error("no commit to roll back at version {version}; only the last commit can be rolled back")]
+    NothingToRollBack {
+        version: u64,
+    },
+
+    #[error("cannot roll back to version {version}, which has been pruned")]
+    VersionPruned {
+        version: u64,
+    },
+}
+
+impl StoreError {
+    pub fn nothing_to_roll_back(version: u64) -> Self {
+        Self::NothingToRollBack {
+            version,
+        }
+    }
+
+    pub fn version_pruned(version: u64) -> Self {
+        Self::VersionPruned {
+            version,
+        }
+    }
+}
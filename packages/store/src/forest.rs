@@ -0,0 +1,151 @@
+use cosmwasm_std::{Binary, Storage};
+use cw_sdk::hash::HASH_LENGTH;
+use cw_tree::{Batch, Tree, TreeError};
+
+use crate::pruning::record_tree_version;
+
+/// The trees making up the app state, as described in `docs/5-app-state.md`.
+///
+/// Variants are declared in this order on purpose: contract trees sort after
+/// the top-level ones, so they can be split off a sorted map of batches.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TreeId {
+    /// The chain's global state, such as the config and the last block.
+    Global,
+    /// Stored Wasm bytecodes and their metadata.
+    Code,
+    /// Metadata of instantiated contracts, including the root of each
+    /// contract's tree.
+    Account,
+    /// IBC connection and channel states.
+    Ibc,
+    /// Internal state of the contract of the given address.
+    Contract(String),
+}
+
+/// The trees whose roots are hashed together into the app hash, in order.
+pub const TOP_LEVEL_TREES: [TreeId; 4] =
+    [TreeId::Global, TreeId::Code, TreeId::Account, TreeId::Ibc];
+
+impl TreeId {
+    /// Prefix of the tree's namespaces in the underlying database.
+    pub fn prefix(&self) -> String {
+        match self {
+            TreeId::Global => "g".into(),
+            TreeId::Code => "w".into(),
+            TreeId::Account => "a".into(),
+            TreeId::Ibc => "i".into(),
+            TreeId::Contract(contract) => format!("c{contract}"),
+        }
+    }
+}
+
+/// Decides which tree of the forest each key of the app state belongs to.
+///
+/// The store doesn't know anything about how the state machine organizes its
+/// data, so the state machine provides its layout when opening the store.
+pub trait Layout {
+    /// Find the tree that the given key belongs to. The key is inserted into
+    /// that tree as is.
+    fn locate(&self, key: &[u8]) -> TreeId;
+
+    /// The key under which a contract's root hash is recorded on commit. It
+    /// must be located in one of the top-level trees, typically the Account
+    /// tree, so that the app hash commits to every contract's state.
+    fn contract_root_key(&self, contract: &str) -> Vec<u8>;
+
+    /// Encode a contract's root hash into the value recorded under its key.
+    /// Defaults to the raw bytes.
+    fn encode_contract_root(&self, root_hash: &[u8; HASH_LENGTH]) -> Vec<u8> {
+        root_hash.to_vec()
+    }
+}
+
+/// A layout putting every key in the Global tree, used unless the store is
+/// given another one.
+pub struct FlatLayout;
+
+impl Layout for FlatLayout {
+    fn locate(&self, _key: &[u8]) -> TreeId {
+        TreeId::Global
+    }
+
+    fn contract_root_key(&self, _contract: &str) -> Vec<u8> {
+        unreachable!("flat layout has no contract trees");
+    }
+}
+
+/// Run a function on the tree of the given ID. The namespaces of a contract's
+/// tree depend on its address, so trees can't be declared as constants.
+pub(crate) fn with_tree<T>(id: &TreeId, f: impl FnOnce(&Tree<Binary, Binary>) -> T) -> T {
+    with_tree_prefix(&id.prefix(), f)
+}
+
+/// Run a function on the tree of the given prefix.
+pub(crate) fn with_tree_prefix<T>(prefix: &str, f: impl FnOnce(&Tree<Binary, Binary>) -> T) -> T {
+    let version_namespace = format!("{prefix}v");
+    let node_namespace = format!("{prefix}n");
+    let orphan_namespace = format!("{prefix}o");
+    f(&Tree::new(&version_namespace, &node_namespace, &orphan_namespace))
+}
+
+/// Apply a batch to the given tree, as part of the given version of the store.
+pub(crate) fn apply(
+    db: &mut dyn Storage,
+    version: u64,
+    id: &TreeId,
+    batch: Batch<Binary, Binary>,
+) -> Result<(), TreeError> {
+    with_tree(id, |tree| {
+        tree.apply(db, batch)?;
+        if let Some(tree_version) = tree.latest_version(db)? {
+            record_tree_version(db, version, &id.prefix(), tree_version);
+        }
+        Ok(())
+    })
+}
+
+/// Latest version of the given tree, or zero if it has never been written to.
+pub(crate) fn tree_version(db: &dyn Storage, id: &TreeId) -> Result<u64, TreeError> {
+    with_tree(id, |tree| Ok(tree.latest_version(db)?.unwrap_or(0)))
+}
+
+/// Root hash of the latest version of the given tree. A tree that is empty, or
+/// has never been written to, is hashed to all zeros.
+pub(crate) fn tree_root(db: &dyn Storage, id: &TreeId) -> Result<[u8; HASH_LENGTH], TreeError> {
+    with_tree(id, |tree| {
+        if tree.latest_version(db)?.is_none() {
+            return Ok([0; HASH_LENGTH]);
+        }
+        match tree.root(db, None) {
+            Ok(res) => Ok(res.root_hash.into_bytes()),
+            // the latest version's root node doesn't exist if all keys have
+            // been deleted
+            Err(TreeError::RootNodeNotFound {
+                ..
+            }) => Ok([0; HASH_LENGTH]),
+            Err(err) => Err(err),
+        }
+    })
+}
+
+/// Query a key in the given tree along with a Merkle proof. The proof is `None`
+/// if the tree is empty.
+pub(crate) fn prove(
+    db: &dyn Storage,
+    id: &TreeId,
+    key: &[u8],
+) -> Result<(Option<Binary>, Option<Binary>), TreeError> {
+    with_tree(id, |tree| {
+        if tree.latest_version(db)?.is_none() {
+            return Ok((None, None));
+        }
+        match tree.get(db, &key.into(), true, None) {
+            Ok(res) => Ok((res.value, res.proof)),
+            Err(TreeError::RootNodeNotFound {
+                ..
+            }) => Ok((None, None)),
+            Err(err) => Err(err),
+        }
+    })
+}
@@ -1,4 +1,5 @@
 use merk::Merk;
+use tracing::error;
 
 /// Read a value from the Merk store; panicks if fails.
 pub(crate) fn must_get(merk: &Merk, key: &[u8]) -> Option<Vec<u8>> {
@@ -10,3 +11,23 @@ pub(crate) fn must_get(merk: &Merk, key: &[u8]) -> Option<Vec<u8>> {
         );
     })
 }
+
+/// Handle a write invoked on a read-only wrapper or snapshot of a store, which
+/// is a logic error in the caller.
+///
+/// The `Storage` trait doesn't allow `set` and `remove` to return errors. In
+/// debug builds the write panics, so that such bugs fail loudly in tests. In
+/// release builds, panicking would crash the node, so the write is discarded
+/// and an error is logged instead.
+pub(crate) fn reject_write(method: &str, kind: &str, key: &[u8]) {
+    error!(
+        target: "[cw-store]: write method invoked on read-only store; write discarded",
+        method,
+        kind,
+        key = hex::encode(key),
+    );
+
+    if cfg!(debug_assertions) {
+        panic!("[cw-store]: `{method}` method invoked on read-only store {kind}");
+    }
+}
@@ -5,41 +5,50 @@ use std::{
     ops::{Bound, RangeBounds},
 };
 
-use cosmwasm_std::{Order, Record};
+use cosmwasm_std::{Order, Record, Storage};
 use merk::{Merk, Op};
+use rocksdb::DBRawIterator;
 
-use crate::helpers::must_get;
+use crate::{helpers::must_get, prefix::concat};
 
-/// Iterator over a Merk store.
+/// Number of records read in the first page of a `RangeCursor`. Each page is
+/// twice as large as the previous one, up to `MAX_PAGE_SIZE`, so that a short
+/// iteration (e.g. a paginated query) only reads a few records, whereas a long
+/// one doesn't seek too often.
+const MIN_PAGE_SIZE: usize = 16;
+
+/// Maximum number of records read in a page of a `RangeCursor`.
+const MAX_PAGE_SIZE: usize = 1024;
+
+/// Iterator over a range of keys in a RocksDB database, yielding raw keys and
+/// values without copying them.
 ///
-/// Different from IAVL tree, the Merk tree stores raw keys as database keys.
-/// To iterate keys in the tree, we simply iterate keys in the underlying RocksDB.
-pub struct MerkIter<'a> {
-    merk: &'a Merk,
-    iter: rocksdb::DBRawIterator<'a>,
+/// Not an `Iterator`, as the items borrow the iterator itself.
+pub(crate) struct RawIter<'a> {
+    iter: DBRawIterator<'a>,
     start: Option<Vec<u8>>,
     end: Option<Vec<u8>>,
     order: Order,
     started: bool,
 }
 
-impl<'a> MerkIter<'a> {
-    pub fn new(merk: &'a Merk, start: Option<&[u8]>, end: Option<&[u8]>, order: Order) -> Self {
+impl<'a> RawIter<'a> {
+    pub fn new(
+        iter: DBRawIterator<'a>,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Self {
         Self {
-            merk,
-            iter: merk.raw_iter(),
+            iter,
             start: start.map(|bytes| bytes.to_vec()),
             end: end.map(|bytes| bytes.to_vec()),
             order,
             started: false,
         }
     }
-}
-
-impl<'a> Iterator for MerkIter<'a> {
-    type Item = Record;
 
-    fn next(&mut self) -> Option<Self::Item> {
+    pub fn next(&mut self) -> Option<(&[u8], &[u8])> {
         if self.started {
             match self.order {
                 Order::Ascending => self.iter.next(),
@@ -68,7 +77,7 @@ impl<'a> Iterator for MerkIter<'a> {
             self.started = true;
         }
 
-        let Some(key) = self.iter.key() else {
+        let (Some(key), Some(value)) = (self.iter.key(), self.iter.value()) else {
             return None;
         };
 
@@ -92,6 +101,35 @@ impl<'a> Iterator for MerkIter<'a> {
             },
         }
 
+        Some((key, value))
+    }
+}
+
+/// Iterator over a Merk store.
+///
+/// Different from IAVL tree, the Merk tree stores raw keys as database keys.
+/// To iterate keys in the tree, we simply iterate keys in the underlying RocksDB.
+pub struct MerkIter<'a> {
+    merk: &'a Merk,
+    raw: RawIter<'a>,
+}
+
+impl<'a> MerkIter<'a> {
+    pub fn new(merk: &'a Merk, start: Option<&[u8]>, end: Option<&[u8]>, order: Order) -> Self {
+        Self {
+            merk,
+            raw: RawIter::new(merk.raw_iter(), start, end, order),
+        }
+    }
+}
+
+impl<'a> Iterator for MerkIter<'a> {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the database value is an encoded tree node, not the raw value
+        let (key, _) = self.raw.next()?;
+
         // two invariants:
         // - the read from Merk store must be successful
         // - if the key exists, the value must also exist (must not be a `None`)
@@ -136,14 +174,15 @@ where
     }
 
     /// If the pending op is to add a new KV, then return this KV.
-    /// Otherwise, this key does not exist, advance to the next iteration step.
+    /// Otherwise, this key does not exist, return `None` so that the caller
+    /// advances to the next iteration step.
     fn take_pending(&mut self) -> Option<Record> {
         // `take_pending` is only called if we have peeked that `self.pending.next()`
         // will return `Some`, so we can safely unwrap here
         let (key, op) = self.pending.next().unwrap();
         match op {
             Op::Put(value) => Some((key.clone(), value.clone())),
-            Op::Delete => self.next(),
+            Op::Delete => None,
         }
     }
 }
@@ -156,70 +195,156 @@ where
     type Item = Record;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match (self.base.peek(), self.pending.peek()) {
-            (Some(base_item), Some(pending_item)) => {
-                let (base_key, _) = base_item;
-                let (pending_key, _) = pending_item;
-
-                // compare the keys of the base item and the pending op item
-                // Ordering::Less means base precedes pending op,
-                // Ordering::Greater means pending op precedes base
-                let order_raw = base_key.cmp(pending_key);
-                let order = match self.order {
-                    Order::Ascending => order_raw,
-                    Order::Descending => order_raw.reverse(),
-                };
-
-                match order {
-                    Ordering::Less => self.base.next(),
-                    Ordering::Equal => {
-                        self.base.next();
-                        self.take_pending()
-                    },
-                    Ordering::Greater => self.take_pending(),
-                }
-            },
+        // loop rather than recurse over deleted keys, as there can be many of
+        // them in a row
+        loop {
+            let record = match (self.base.peek(), self.pending.peek()) {
+                (Some(base_item), Some(pending_item)) => {
+                    let (base_key, _) = base_item;
+                    let (pending_key, _) = pending_item;
 
-            // base has reached end, pending has not
-            (None, Some(_)) => self.take_pending(),
+                    // compare the keys of the base item and the pending op item
+                    // Ordering::Less means base precedes pending op,
+                    // Ordering::Greater means pending op precedes base
+                    let order_raw = base_key.cmp(pending_key);
+                    let order = match self.order {
+                        Order::Ascending => order_raw,
+                        Order::Descending => order_raw.reverse(),
+                    };
 
-            // pending has reached end, base has not => simply return next base
-            (Some(_), None) => self.base.next(),
+                    match order {
+                        Ordering::Less => self.base.next(),
+                        Ordering::Equal => {
+                            self.base.next();
+                            self.take_pending()
+                        },
+                        Ordering::Greater => self.take_pending(),
+                    }
+                },
+
+                // base has reached end, pending has not
+                (None, Some(_)) => self.take_pending(),
 
-            // both base and pending have reached end => simply return None
-            (None, None) => None,
+                // pending has reached end, base has not => simply return next base
+                (Some(_), None) => return self.base.next(),
+
+                // both base and pending have reached end => simply return None
+                (None, None) => return None,
+            };
+
+            if record.is_some() {
+                return record;
+            }
         }
     }
 }
 
-/// An iterator that holds a collected VecDeque of records in memory.
+/// The position of an iteration over a range of a store, which reads records a
+/// page at a time.
 ///
-/// Have to do this because of an incompatibility between the Storage trait and
-/// Rust smart pointers.
+/// It doesn't borrow the store, so that it can be kept across borrows of a
+/// store behind an `Rc<RefCell>`, or in a struct owning the store, such as the
+/// contract substore. Each page is read with a new iterator over the store,
+/// starting right after the last record read, so only one page is held in
+/// memory at a time.
 ///
-/// Not very optimized as all items need to be collected in memory, but should
-/// be fine for our use case as CosmWasm contracts typically don't iterate more
-/// than a few tens or at most a few hundreds records at a time.
+/// As a consequence, writes made to the store during the iteration are visible
+/// in the pages read afterwards, rather than the iteration being over a
+/// snapshot of the store.
+pub struct RangeCursor {
+    start: Option<Vec<u8>>,
+    end: Option<Vec<u8>>,
+    order: Order,
+    page: VecDeque<Record>,
+    page_size: usize,
+    exhausted: bool,
+}
+
+impl RangeCursor {
+    pub fn new(start: Option<&[u8]>, end: Option<&[u8]>, order: Order) -> Self {
+        Self {
+            start: start.map(|bytes| bytes.to_vec()),
+            end: end.map(|bytes| bytes.to_vec()),
+            order,
+            page: VecDeque::new(),
+            page_size: MIN_PAGE_SIZE,
+            exhausted: false,
+        }
+    }
+
+    /// Return the next record. If the current page has been consumed, the next
+    /// one is read by `fetch`, which takes the remaining range, the order, and
+    /// the maximum number of records to return.
+    pub fn next_with<F>(&mut self, fetch: F) -> Option<Record>
+    where
+        F: FnOnce(Option<&[u8]>, Option<&[u8]>, Order, usize) -> Vec<Record>,
+    {
+        if self.page.is_empty() && !self.exhausted {
+            let start = self.start.as_deref();
+            let end = self.end.as_deref();
+            let page = fetch(start, end, self.order, self.page_size);
+
+            // a page shorter than requested means the range has been exhausted.
+            // otherwise, narrow the range to after the page's last record
+            if page.len() < self.page_size {
+                self.exhausted = true;
+            } else if let Some((key, _)) = page.last() {
+                match self.order {
+                    // the smallest key greater than the last one
+                    Order::Ascending => self.start = Some(concat(key, &[0])),
+                    // end is exclusive
+                    Order::Descending => self.end = Some(key.clone()),
+                }
+            }
+
+            self.page = page.into();
+            self.page_size = (self.page_size * 2).min(MAX_PAGE_SIZE);
+        }
+
+        self.page.pop_front()
+    }
+
+    /// Return the next record, reading the next page from the given store if
+    /// needed.
+    pub fn next_from(&mut self, store: &dyn Storage) -> Option<Record> {
+        self.next_with(|start, end, order, limit| {
+            store.range(start, end, order).take(limit).collect()
+        })
+    }
+}
+
+/// A `RangeCursor` along with the function reading its pages, so that it can be
+/// used as an `Iterator`.
 ///
-/// This being said, we need to think about malicious contracts intentially
-/// iterate a huge amount of data to cause memory issues.
-pub struct MemIter {
-    items: VecDeque<Record>,
+/// The function typically borrows a store behind an `Rc<RefCell>` only for as
+/// long as it takes to read a page. This way, the iterator doesn't hold a
+/// borrow in between, and records are streamed from the store rather than all
+/// collected in memory upfront.
+pub struct PagedIter<F> {
+    cursor: RangeCursor,
+    fetch: F,
 }
 
-impl MemIter {
-    pub fn new(iter: impl Iterator<Item = Record>) -> Self {
+impl<F> PagedIter<F>
+where
+    F: FnMut(Option<&[u8]>, Option<&[u8]>, Order, usize) -> Vec<Record>,
+{
+    pub fn new(start: Option<&[u8]>, end: Option<&[u8]>, order: Order, fetch: F) -> Self {
         Self {
-            items: iter.collect(),
+            cursor: RangeCursor::new(start, end, order),
+            fetch,
         }
     }
 }
 
-impl Iterator for MemIter {
+impl<F> Iterator for PagedIter<F>
+where
+    F: FnMut(Option<&[u8]>, Option<&[u8]>, Order, usize) -> Vec<Record>,
+{
     type Item = Record;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.items.pop_front()
+        self.cursor.next_with(&mut self.fetch)
     }
 }
 
@@ -229,3 +354,77 @@ pub(crate) fn range_bounds(start: Option<&[u8]>, end: Option<&[u8]>) -> impl Ran
         end.map_or(Bound::Unbounded, |x| Bound::Excluded(x.to_vec())),
     )
 }
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use cosmwasm_std::testing::MockStorage;
+
+    use super::*;
+
+    fn setup_test(count: u32) -> MockStorage {
+        let mut store = MockStorage::new();
+        for i in 0..count {
+            store.set(&i.to_be_bytes(), &i.to_le_bytes());
+        }
+        store
+    }
+
+    #[test]
+    fn reading_pages_lazily() {
+        let store = setup_test(1000);
+        let pages = Cell::new(0);
+        let fetch = |start: Option<&[u8]>, end: Option<&[u8]>, order, limit| -> Vec<Record> {
+            pages.set(pages.get() + 1);
+            store.range(start, end, order).take(limit).collect()
+        };
+
+        // a short iteration only reads one page
+        let items = PagedIter::new(None, None, Order::Ascending, fetch).take(10).count();
+        assert_eq!(items, 10);
+        assert_eq!(pages.get(), 1);
+
+        // pages grow in size, and don't skip or repeat records across their
+        // boundaries
+        pages.set(0);
+        let start = 100u32.to_be_bytes();
+        let end = 900u32.to_be_bytes();
+        for order in [Order::Ascending, Order::Descending] {
+            let keys = PagedIter::new(Some(&start), Some(&end), order, fetch)
+                .map(|(key, _)| u32::from_be_bytes(key.try_into().unwrap()))
+                .collect::<Vec<_>>();
+            let mut expected = (100..900).collect::<Vec<_>>();
+            if order == Order::Descending {
+                expected.reverse();
+            }
+            assert_eq!(keys, expected);
+        }
+        // 16 + 32 + 64 + 128 + 256 + 512 >= 800
+        assert_eq!(pages.get(), 12);
+    }
+
+    #[test]
+    fn seeing_writes_in_later_pages() {
+        let store = RefCell::new(setup_test(100));
+        let mut cursor = RangeCursor::new(None, None, Order::Ascending);
+
+        let first = cursor.next_from(&*store.borrow()).unwrap();
+        assert_eq!(first.0, 0u32.to_be_bytes());
+
+        // the rest of the first page has been read already, whereas the next
+        // pages are read after the write
+        store.borrow_mut().remove(&1u32.to_be_bytes());
+        store.borrow_mut().remove(&99u32.to_be_bytes());
+
+        let mut count = 1;
+        while cursor.next_from(&*store.borrow()).is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 99);
+    }
+}
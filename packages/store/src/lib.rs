@@ -1,24 +1,40 @@
 mod cache;
+mod commit;
+mod db;
+mod error;
+mod forest;
 mod helpers;
 pub mod iterators;
+mod listen;
+mod mem;
 pub mod prefix;
+mod pruning;
 mod share;
+mod snapshot;
 mod store;
+mod trace;
+mod tree;
 
-pub use crate::cache::Cached;
+pub use crate::cache::{Cached, Checkpoint};
+pub use crate::commit::CommitStore;
+pub use crate::db::{Db, DbSnapshot};
+pub use crate::error::StoreError;
+pub use crate::forest::{FlatLayout, Layout, TreeId, TOP_LEVEL_TREES};
+pub use crate::listen::CommitListener;
+pub use crate::mem::{
+    MemStore, MemStoreBase, MemStoreSnapshot, MemStoreWrapper, PendingMemStoreWrapper,
+};
+pub use crate::pruning::Pruning;
 pub use crate::share::Shared;
+pub use crate::snapshot::SnapshotDb;
 pub use crate::store::{PendingStoreWrapper, Store, StoreBase, StoreWrapper};
+pub use crate::trace::Tracer;
+pub use crate::tree::{
+    ContractRootProof, ForestProof, PendingTreeStoreWrapper, TreeStore, TreeStoreBase,
+    TreeStoreSnapshot, TreeStoreWrapper,
+};
 
-pub use merk::Error as MerkError;
+pub use cw_tree::TreeError;
+pub use rocksdb::Error as DbError;
 
-// merk::Op doesn't implement Clone, so we have to do this:
-use merk::Op;
-
-fn clone_op((key, op): (&Vec<u8>, &Op)) -> (Vec<u8>, Op) {
-    let cloned_op = match op {
-        Op::Put(value) => Op::Put(value.clone()),
-        Op::Delete => Op::Delete,
-    };
-
-    (key.clone(), cloned_op)
-}
+pub use merk::{Error as MerkError, Op};
@@ -0,0 +1,16 @@
+use std::collections::BTreeMap;
+
+use merk::Op;
+
+/// Receives the changes made to the state in each commit of a `TreeStore`, e.g.
+/// to stream them to an indexer, similar to the Cosmos SDK's state listening
+/// (ADR-038).
+pub trait CommitListener {
+    /// Called with the pending ops being committed as the given version of the
+    /// store, sorted by key.
+    ///
+    /// This is done before the ops are written. If the commit is interrupted,
+    /// the same version is committed, and listened to, again once the block is
+    /// replayed, so listeners should overwrite what they got for a version.
+    fn on_commit(&mut self, version: u64, changeset: &BTreeMap<Vec<u8>, Op>);
+}
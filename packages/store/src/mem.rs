@@ -0,0 +1,412 @@
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    collections::BTreeMap,
+    convert::Infallible,
+    iter,
+    rc::Rc,
+    sync::Arc,
+};
+
+use cosmwasm_std::{Order, Record, Storage};
+use cw_sdk::hash::{sha256, HASH_LENGTH};
+use merk::Op;
+
+use crate::{
+    helpers::reject_write,
+    iterators::{range_bounds, MergedIter, PagedIter},
+    CommitStore,
+};
+
+pub struct MemStoreBase {
+    /// The committed key-value data, shared with the snapshots taken since the
+    /// last commit, if any. Committing copies it only if it's still shared.
+    committed: Arc<BTreeMap<Vec<u8>, Vec<u8>>>,
+
+    /// Database operations not yet committed, same as in `StoreBase`.
+    pending_ops: BTreeMap<Vec<u8>, Op>,
+
+    /// Hash of the committed data, updated on each commit.
+    root_hash: [u8; HASH_LENGTH],
+}
+
+/// A pure in-memory counterpart of `Store`, intended for tests, so that they
+/// don't need to create temporary databases on disk.
+///
+/// Its root hash is a SHA-256 hash of all committed key-value pairs, so it is
+/// deterministic, but doesn't support Merkle proofs, nor match the root hash of
+/// a Merk-backed `Store` holding the same data.
+pub struct MemStore(Rc<RefCell<MemStoreBase>>);
+
+impl MemStore {
+    pub fn new() -> Self {
+        let base = MemStoreBase {
+            committed: Arc::new(BTreeMap::new()),
+            pending_ops: BTreeMap::new(),
+            root_hash: [0; HASH_LENGTH],
+        };
+        Self(Rc::new(RefCell::new(base)))
+    }
+
+    pub fn share(&self) -> Self {
+        Self(Rc::clone(&self.0))
+    }
+
+    fn borrow(&self) -> Ref<MemStoreBase> {
+        self.0.borrow()
+    }
+
+    fn borrow_mut(&self) -> RefMut<MemStoreBase> {
+        self.0.borrow_mut()
+    }
+}
+
+impl Default for MemStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommitStore for MemStore {
+    type Wrapper = MemStoreWrapper;
+    type PendingWrapper = PendingMemStoreWrapper;
+    type Snapshot = MemStoreSnapshot;
+    type Error = Infallible;
+
+    fn wrap(&self) -> MemStoreWrapper {
+        MemStoreWrapper {
+            inner: self.share(),
+        }
+    }
+
+    fn pending_wrap(&self) -> PendingMemStoreWrapper {
+        PendingMemStoreWrapper {
+            inner: self.share(),
+        }
+    }
+
+    fn snapshot(&self) -> MemStoreSnapshot {
+        MemStoreSnapshot {
+            committed: Arc::clone(&self.borrow().committed),
+        }
+    }
+
+    fn commit(&self) -> Result<(), Infallible> {
+        let mut ref_mut = self.borrow_mut();
+
+        let pending_ops = std::mem::take(&mut ref_mut.pending_ops);
+        let committed = Arc::make_mut(&mut ref_mut.committed);
+        for (key, op) in pending_ops {
+            match op {
+                Op::Put(value) => committed.insert(key, value),
+                Op::Delete => committed.remove(&key),
+            };
+        }
+
+        let root_hash = hash_state(&ref_mut.committed);
+        ref_mut.root_hash = root_hash;
+
+        Ok(())
+    }
+
+    fn root_hash(&self) -> [u8; HASH_LENGTH] {
+        self.borrow().root_hash
+    }
+}
+
+/// Hash the length-prefixed keys and values in order. An empty state is hashed
+/// to all zeros, same as an empty Merk tree.
+fn hash_state(committed: &BTreeMap<Vec<u8>, Vec<u8>>) -> [u8; HASH_LENGTH] {
+    if committed.is_empty() {
+        return [0; HASH_LENGTH];
+    }
+
+    let mut bytes = vec![];
+    for (key, value) in committed {
+        bytes.extend((key.len() as u32).to_be_bytes());
+        bytes.extend(key);
+        bytes.extend((value.len() as u32).to_be_bytes());
+        bytes.extend(value);
+    }
+
+    sha256(&bytes).try_into().unwrap()
+}
+
+/// Read-only wrapper of a `MemStore`, the counterpart of `StoreWrapper`.
+/// Writes are rejected, same as in `StoreWrapper`.
+pub struct MemStoreWrapper {
+    inner: MemStore,
+}
+
+impl Storage for MemStoreWrapper {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.borrow().committed.get(key).cloned()
+    }
+
+    fn set(&mut self, key: &[u8], _value: &[u8]) {
+        reject_write("set", "wrapper", key);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        reject_write("remove", "wrapper", key);
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(iter::empty());
+            }
+        }
+        let inner = &self.inner;
+        Box::new(PagedIter::new(start, end, order, move |start, end, order, limit| {
+            committed_range(&inner.borrow().committed, start, end, order).take(limit).collect()
+        }))
+    }
+}
+
+/// Thread-safe, read-only snapshot of a `MemStore`'s committed state, the
+/// counterpart of `TreeStoreSnapshot`. Writes are rejected, same as in
+/// `StoreWrapper`.
+#[derive(Clone)]
+pub struct MemStoreSnapshot {
+    pub(crate) committed: Arc<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl Storage for MemStoreSnapshot {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.committed.get(key).cloned()
+    }
+
+    fn set(&mut self, key: &[u8], _value: &[u8]) {
+        reject_write("set", "snapshot", key);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        reject_write("remove", "snapshot", key);
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(iter::empty());
+            }
+        }
+        committed_range(&self.committed, start, end, order)
+    }
+}
+
+/// Read-and-write wrapper of a `MemStore`, the counterpart of
+/// `PendingStoreWrapper`.
+pub struct PendingMemStoreWrapper {
+    inner: MemStore,
+}
+
+impl Storage for PendingMemStoreWrapper {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let store = self.inner.borrow();
+        let Some(op) = store.pending_ops.get(key) else {
+            return store.committed.get(key).cloned();
+        };
+        match op {
+            Op::Put(value) => Some(value.clone()),
+            Op::Delete => None,
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.inner.borrow_mut().pending_ops.insert(key.to_vec(), Op::Put(value.to_vec()));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.inner.borrow_mut().pending_ops.insert(key.to_vec(), Op::Delete);
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(iter::empty());
+            }
+        }
+
+        let inner = &self.inner;
+        Box::new(PagedIter::new(start, end, order, move |start, end, order, limit| {
+            let store = inner.borrow();
+
+            let base = committed_range(&store.committed, start, end, order);
+
+            let pending_raw = store.pending_ops.range(range_bounds(start, end));
+            let pending: Box<dyn Iterator<Item = (&Vec<u8>, &Op)>> = match order {
+                Order::Ascending => Box::new(pending_raw),
+                Order::Descending => Box::new(pending_raw.rev()),
+            };
+
+            // bound to a variable, so that the iterator borrowing `store` is
+            // dropped before it
+            let records = MergedIter::new(base, pending, order).take(limit).collect();
+            records
+        }))
+    }
+}
+
+fn committed_range<'a>(
+    committed: &'a BTreeMap<Vec<u8>, Vec<u8>>,
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+    order: Order,
+) -> Box<dyn Iterator<Item = Record> + 'a> {
+    let iter = committed
+        .range(range_bounds(start, end))
+        .map(|(key, value)| (key.clone(), value.clone()));
+    match order {
+        Order::Ascending => Box::new(iter),
+        Order::Descending => Box::new(iter.rev()),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn setup_test() -> MemStore {
+        let store = MemStore::new();
+
+        // add some committed key-values for testing
+        let mut wrapper = store.pending_wrap();
+        wrapper.set(b"key1", b"value1");
+        wrapper.set(b"key2", b"value2");
+        wrapper.set(b"key3", b"value3");
+        wrapper.set(b"key4", b"value4");
+        store.commit().unwrap();
+
+        // add some pending ops as well
+        wrapper.set(b"key2", b"value23456");
+        wrapper.set(b"key3333", b"value3333");
+        wrapper.remove(b"key3");
+
+        store
+    }
+
+    #[test]
+    fn getting() {
+        let store = setup_test();
+
+        let wrapper = store.wrap();
+        assert_eq!(wrapper.get(b"key2"), Some(b"value2".to_vec()));
+        assert_eq!(wrapper.get(b"key3"), Some(b"value3".to_vec()));
+        assert_eq!(wrapper.get(b"key3333"), None);
+
+        let wrapper = store.pending_wrap();
+        assert_eq!(wrapper.get(b"key2"), Some(b"value23456".to_vec()));
+        assert_eq!(wrapper.get(b"key3"), None);
+        assert_eq!(wrapper.get(b"key3333"), Some(b"value3333".to_vec()));
+    }
+
+    #[test]
+    fn committing() {
+        let store = setup_test();
+        let hash_before = store.root_hash();
+
+        store.commit().unwrap();
+
+        let wrapper = store.wrap();
+        assert_eq!(wrapper.get(b"key2"), Some(b"value23456".to_vec()));
+        assert_eq!(wrapper.get(b"key3"), None);
+        assert_eq!(wrapper.get(b"key3333"), Some(b"value3333".to_vec()));
+        assert!(store.borrow().pending_ops.is_empty());
+
+        // the root hash changes with the committed state, and is the same for
+        // the same state regardless of how it was reached
+        assert_ne!(store.root_hash(), hash_before);
+        let other = MemStore::new();
+        let mut wrapper = other.pending_wrap();
+        for (key, value) in store.wrap().range(None, None, Order::Ascending) {
+            wrapper.set(&key, &value);
+        }
+        other.commit().unwrap();
+        assert_eq!(other.root_hash(), store.root_hash());
+    }
+
+    #[test]
+    fn snapshotting() {
+        let store = setup_test();
+        let snapshot = store.snapshot();
+
+        // neither pending ops, nor later commits, are seen by the snapshot
+        store.commit().unwrap();
+        assert_eq!(snapshot.get(b"key2"), Some(b"value2".to_vec()));
+        assert_eq!(snapshot.get(b"key3333"), None);
+        assert_eq!(store.wrap().get(b"key2"), Some(b"value23456".to_vec()));
+
+        // it can be read from another thread
+        let count = thread::spawn(move || snapshot.range(None, None, Order::Ascending).count());
+        assert_eq!(count.join().unwrap(), 4);
+    }
+
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic = "[cw-store]: `set` method invoked on read-only store wrapper"
+    )]
+    fn illegal_set() {
+        let store = setup_test();
+
+        // the write panics in debug builds, and is discarded in release builds
+        let mut wrapper = store.wrap();
+        wrapper.set(b"should", b"discard");
+        assert_eq!(wrapper.get(b"should"), None);
+        assert_eq!(store.pending_wrap().get(b"should"), None);
+    }
+
+    #[cfg(feature = "iterator")]
+    #[test]
+    fn iterating_pending() {
+        let store = setup_test();
+
+        let mut kv = vec![
+            (b"key1".to_vec(), b"value1".to_vec()),
+            (b"key2".to_vec(), b"value23456".to_vec()),
+            (b"key3333".to_vec(), b"value3333".to_vec()),
+            (b"key4".to_vec(), b"value4".to_vec()),
+        ];
+
+        let items = store.pending_wrap().range(None, None, Order::Ascending).collect::<Vec<_>>();
+        assert_eq!(items, kv);
+
+        let items = store
+            .pending_wrap()
+            .range(Some(b"key1234"), Some(b"key4"), Order::Ascending)
+            .collect::<Vec<_>>();
+        assert_eq!(items, &kv[1..3]);
+
+        kv.reverse();
+
+        let items = store.pending_wrap().range(None, None, Order::Descending).collect::<Vec<_>>();
+        assert_eq!(items, kv);
+
+        let items = store
+            .pending_wrap()
+            .range(Some(b"key1234"), Some(b"key4"), Order::Descending)
+            .collect::<Vec<_>>();
+        assert_eq!(items, &kv[1..3]);
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::BTreeMap,
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use cosmwasm_std::{Order, Storage};
+use cw_tree::TreeError;
+use tracing::{error, info, warn};
+
+use crate::{
+    forest::with_tree_prefix,
+    prefix::{concat, namespace_upper_bound},
+    Db,
+};
+
+/// Prefix of the index from each version of the store, i.e. each commit, to the
+/// versions of the trees updated in it. Trees have their own versions, which
+/// only increase when they are updated, so this index is needed to find which
+/// version of each tree to prune up to.
+const TREE_VERSIONS_PREFIX: &[u8] = b"mtree_versions";
+
+/// Key of the latest version of the store that has been pruned up to, i.e.
+/// the oldest one that can still be rolled back to.
+const PRUNED_KEY: &[u8] = b"mpruned";
+
+/// Log the progress of a pruning round every this many trees.
+const PROGRESS_INTERVAL: usize = 1000;
+
+/// Which historical versions of the state to delete from disk.
+///
+/// Archive nodes keep every version, so that historical states can be queried
+/// and proven, whereas validators typically only need the latest one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pruning {
+    /// Keep all versions.
+    Nothing,
+    /// Keep only the latest version, pruning after every commit.
+    Everything,
+    /// Keep the given number of most recent versions, pruning every `interval`
+    /// commits.
+    KeepRecent {
+        keep_recent: u64,
+        interval: u64,
+    },
+}
+
+impl Pruning {
+    /// After committing the given version, return the oldest version to keep,
+    /// if pruning is due.
+    pub fn prune_up_to(&self, version: u64) -> Option<u64> {
+        match *self {
+            Pruning::Nothing => None,
+            Pruning::Everything => Some(version),
+            Pruning::KeepRecent {
+                keep_recent,
+                interval,
+            } => {
+                // the latest version is always kept
+                let keep_recent = keep_recent.max(1);
+                if interval == 0 || version % interval != 0 || version <= keep_recent {
+                    return None;
+                }
+                Some(version - keep_recent + 1)
+            },
+        }
+    }
+}
+
+/// Record that the tree of the given prefix is at the given version, as of the
+/// given version of the store.
+pub(crate) fn record_tree_version(
+    db: &mut dyn Storage,
+    version: u64,
+    tree_prefix: &str,
+    tree_version: u64,
+) {
+    let key = [TREE_VERSIONS_PREFIX, &version.to_be_bytes(), tree_prefix.as_bytes()].concat();
+    db.set(&key, &tree_version.to_be_bytes());
+}
+
+/// Remove the records of the versions of the trees updated in the given
+/// version of the store, when that version is undone.
+pub(crate) fn remove_tree_versions(db: &mut dyn Storage, version: u64) {
+    let prefix = concat(TREE_VERSIONS_PREFIX, &version.to_be_bytes());
+    let keys = db
+        .range(Some(&prefix), Some(&namespace_upper_bound(&prefix)), Order::Ascending)
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+    for key in keys {
+        db.remove(&key);
+    }
+}
+
+/// The latest version of the store that has been pruned up to, or zero if it
+/// has never been pruned. Versions older than it can no longer be restored.
+pub(crate) fn pruned_up_to(db: &dyn Storage) -> u64 {
+    db.get(PRUNED_KEY).map_or(0, |bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Delete the nodes of all trees that are not part of the given version of the
+/// store or any later one, so that older versions can no longer be queried.
+/// Returns the number of trees pruned.
+pub(crate) fn prune(db: &mut dyn Storage, up_to_version: u64) -> Result<usize, TreeError> {
+    let end = concat(TREE_VERSIONS_PREFIX, &(up_to_version + 1).to_be_bytes());
+    let records = db
+        .range(Some(TREE_VERSIONS_PREFIX), Some(&end), Order::Ascending)
+        .collect::<Vec<_>>();
+
+    // the version of each tree as of the given version of the store, i.e. the
+    // last one recorded
+    let mut trees = BTreeMap::new();
+    for (key, value) in &records {
+        let tree_prefix = String::from_utf8_lossy(&key[TREE_VERSIONS_PREFIX.len() + 8..]);
+        let tree_version = u64::from_be_bytes(value.as_slice().try_into().unwrap());
+        trees.insert(tree_prefix.into_owned(), tree_version);
+    }
+
+    for (i, (tree_prefix, tree_version)) in trees.iter().enumerate() {
+        with_tree_prefix(tree_prefix, |tree| tree.prune(db, Some(*tree_version)))?;
+
+        if (i + 1) % PROGRESS_INTERVAL == 0 {
+            info!(
+                target: "Pruning in progress",
+                up_to_version,
+                pruned = i + 1,
+                total = trees.len(),
+            );
+        }
+    }
+
+    // the records have been consumed. a tree that isn't updated afterwards
+    // has nothing more to prune, and a tree that is gets new records
+    for (key, _) in records {
+        db.remove(&key);
+    }
+
+    if up_to_version > pruned_up_to(db) {
+        db.set(PRUNED_KEY, &up_to_version.to_be_bytes());
+    }
+
+    Ok(trees.len())
+}
+
+/// Prunes a RocksDB-backed `TreeStore` in a background thread, so that commits
+/// don't wait for it.
+///
+/// This is safe because pruning only deletes nodes that are no longer part of
+/// the latest version of any tree, which commits never read.
+pub(crate) struct Pruner {
+    db: Db,
+    pruning: Pruning,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Pruner {
+    pub fn new(db: Db, pruning: Pruning) -> Self {
+        Self {
+            db,
+            pruning,
+            handle: None,
+        }
+    }
+
+    /// Start a pruning round, if one is due after committing the given version.
+    pub fn after_commit(&mut self, version: u64) {
+        let Some(up_to_version) = self.pruning.prune_up_to(version) else {
+            return;
+        };
+
+        // if the previous round is still running, skip this one. the next round
+        // prunes the skipped versions as well
+        if self.handle.as_ref().map_or(false, |handle| !handle.is_finished()) {
+            warn!(target: "Previous pruning still in progress; skipped", version);
+            return;
+        }
+
+        let mut db = self.db.clone();
+        self.handle = Some(thread::spawn(move || {
+            info!(target: "Pruning started", up_to_version);
+            let start = Instant::now();
+            match prune(&mut db, up_to_version) {
+                Ok(trees) => info!(
+                    target: "Pruning finished",
+                    up_to_version,
+                    trees,
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                ),
+                Err(err) => error!(
+                    target: "Pruning failed",
+                    up_to_version,
+                    reason = err.to_string(),
+                ),
+            }
+        }));
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::MockStorage;
+
+    use super::*;
+    use crate::{forest::with_tree, CommitStore, TreeId, TreeStore};
+
+    #[test]
+    fn pruning_strategies() {
+        assert_eq!(Pruning::Nothing.prune_up_to(100), None);
+        assert_eq!(Pruning::Everything.prune_up_to(100), Some(100));
+
+        let pruning = Pruning::KeepRecent {
+            keep_recent: 10,
+            interval: 5,
+        };
+        assert_eq!(pruning.prune_up_to(5), None);
+        assert_eq!(pruning.prune_up_to(10), None);
+        assert_eq!(pruning.prune_up_to(14), None);
+        assert_eq!(pruning.prune_up_to(15), Some(6));
+        assert_eq!(pruning.prune_up_to(100), Some(91));
+    }
+
+    #[test]
+    fn pruning_old_versions() {
+        let store = TreeStore::new(MockStorage::new());
+
+        for value in [b"value1", b"value2", b"value3"] {
+            store.pending_wrap().set(b"key1", value);
+            store.commit().unwrap();
+        }
+        assert_eq!(store.version(), 3);
+
+        let root = |version| {
+            let base = store.borrow();
+            with_tree(&TreeId::Global, |tree| tree.root(&base.db, Some(version)).is_ok())
+        };
+        assert!(root(1) && root(2) && root(3));
+
+        let trees = prune(&mut store.borrow_mut().db, 2).unwrap();
+        assert_eq!(trees, 1);
+        assert!(!root(1));
+        assert!(root(2) && root(3));
+
+        // the latest state is unaffected
+        let hash_before = store.root_hash();
+        let trees = prune(&mut store.borrow_mut().db, 3).unwrap();
+        assert_eq!(trees, 1);
+        assert!(!root(2));
+        assert_eq!(store.root_hash(), hash_before);
+        assert_eq!(store.wrap().get(b"key1"), Some(b"value3".to_vec()));
+
+        // nothing left to prune
+        assert_eq!(prune(&mut store.borrow_mut().db, 3).unwrap(), 0);
+    }
+}
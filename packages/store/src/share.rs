@@ -2,7 +2,7 @@ use std::{cell::{RefCell, Ref, RefMut}, rc::Rc};
 
 use cosmwasm_std::{Order, Record, Storage};
 
-use crate::iterators::MemIter;
+use crate::iterators::PagedIter;
 
 /// Wrap a store in a smart pointer, so that it can be shared as an owned value.
 /// Note that this struct can only be used in single threads.
@@ -20,11 +20,23 @@ impl<T> Shared<T> {
         Self(Rc::clone(&self.0))
     }
 
+    /// Consume self, return the inner value.
+    ///
+    /// Panics if the value is still being shared, i.e. if other copies of this
+    /// `Shared` object, created by the `share` method, have not been dropped.
+    pub fn into_inner(self) -> T {
+        Rc::try_unwrap(self.0)
+            .unwrap_or_else(|_| {
+                panic!("[cw-store]: `into_inner` invoked on a store that is still being shared");
+            })
+            .into_inner()
+    }
+
     pub fn borrow(&self) -> Ref<T> {
         self.0.borrow()
     }
 
-    pub fn borrow_mut(&mut self) -> RefMut<T> {
+    pub fn borrow_mut(&self) -> RefMut<T> {
         self.0.borrow_mut()
     }
 }
@@ -48,6 +60,9 @@ impl<T: Storage> Storage for Shared<T> {
         end: Option<&[u8]>,
         order: Order,
     ) -> Box<dyn Iterator<Item = Record> + 'a> {
-        Box::new(MemIter::new(self.0.borrow().range(start, end, order)))
+        let inner = &self.0;
+        Box::new(PagedIter::new(start, end, order, move |start, end, order, limit| {
+            inner.borrow().range(start, end, order).take(limit).collect()
+        }))
     }
 }
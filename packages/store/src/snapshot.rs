@@ -0,0 +1,24 @@
+use cosmwasm_std::{MemoryStorage, Order, Storage};
+
+/// A database of which point-in-time, read-only snapshots can be taken, so that
+/// a `TreeStore` persisted in it can serve reads from other threads while it
+/// keeps being written to.
+pub trait SnapshotDb: Storage {
+    type Snapshot: Storage + Send + Sync + 'static;
+
+    /// Take a snapshot of the database's current content.
+    fn snapshot(&self) -> Self::Snapshot;
+}
+
+/// In-memory databases, used in tests, are snapshotted by copying them.
+impl SnapshotDb for MemoryStorage {
+    type Snapshot = MemoryStorage;
+
+    fn snapshot(&self) -> MemoryStorage {
+        let mut snapshot = MemoryStorage::new();
+        for (key, value) in self.range(None, None, Order::Ascending) {
+            snapshot.set(&key, &value);
+        }
+        snapshot
+    }
+}
@@ -1,9 +1,10 @@
 use std::{
     cell::{RefCell, Ref, RefMut},
     collections::BTreeMap,
-    iter,
+    iter, mem,
     path::Path,
     rc::Rc,
+    sync::Arc,
 };
 
 use cosmwasm_std::{Order, Record, Storage};
@@ -11,9 +12,9 @@ use cw_sdk::hash::HASH_LENGTH;
 use merk::{Merk, Op};
 
 use crate::{
-    helpers::must_get,
-    iterators::{range_bounds, MemIter, MergedIter, MerkIter},
-    MerkError, clone_op,
+    helpers::{must_get, reject_write},
+    iterators::{range_bounds, MergedIter, MerkIter, PagedIter},
+    CommitStore, MemStoreSnapshot, MerkError,
 };
 
 pub struct StoreBase {
@@ -70,10 +71,8 @@ impl Store {
     pub fn commit(&self) -> Result<(), MerkError> {
         let mut ref_mut = self.borrow_mut();
 
-        // unlike hashmap, btreemap doesn't have a handy `drain` method
-        // so we have to do this, which is very inefficient:
-        let batch: Vec<_> = ref_mut.pending_ops.iter().map(clone_op).collect();
-        ref_mut.pending_ops = BTreeMap::new();
+        // move the ops out of the map rather than cloning them
+        let batch: Vec<_> = mem::take(&mut ref_mut.pending_ops).into_iter().collect();
 
         // we know the ops are sorted by keys (as they are collected from a
         // btreemap), so we skip the checking step
@@ -85,8 +84,8 @@ impl Store {
     /// StoreWrapper implements the Storage trait, and reads directly from the
     /// underlying Merk tree, disregarding the pending ops.
     ///
-    /// StoreWrapper only supports read, and panics if a write method is invoked.
-    /// It intended to be used for the "Query" ABCI request.
+    /// StoreWrapper only supports read, and panics in debug builds if a write
+    /// method is invoked. It intended to be used for the "Query" ABCI request.
     pub fn wrap(&self) -> StoreWrapper {
         StoreWrapper {
             inner: self.share(),
@@ -107,12 +106,50 @@ impl Store {
     }
 }
 
+impl CommitStore for Store {
+    type Wrapper = StoreWrapper;
+    type PendingWrapper = PendingStoreWrapper;
+    type Snapshot = MemStoreSnapshot;
+    type Error = MerkError;
+
+    fn wrap(&self) -> StoreWrapper {
+        Store::wrap(self)
+    }
+
+    fn pending_wrap(&self) -> PendingStoreWrapper {
+        Store::pending_wrap(self)
+    }
+
+    /// Merk's snapshots borrow its database, so they can't be sent to other
+    /// threads. Instead, the committed state is copied into memory, which is
+    /// only viable for small states.
+    fn snapshot(&self) -> MemStoreSnapshot {
+        let committed = self.wrap().range(None, None, Order::Ascending).collect();
+        MemStoreSnapshot {
+            committed: Arc::new(committed),
+        }
+    }
+
+    fn commit(&self) -> Result<(), MerkError> {
+        Store::commit(self)
+    }
+
+    fn root_hash(&self) -> [u8; HASH_LENGTH] {
+        Store::root_hash(self)
+    }
+}
+
 /// A read-only wrapper of the `Store` object, with the `cosmwasm_std::Storage`
 /// trait implemented. When reading from this object, the underlying Merk store
 /// is accessed, while the pending ops are ignored.
 ///
 /// This struct is intended to be used in the ABCI "Query" request, so an
 /// _immutable_ reference to the `Store` is used.
+///
+/// Writes are logic errors in the caller: they panic in debug builds, and are
+/// discarded with an error logged in release builds, so as not to crash the
+/// node. Contract queries never reach this point, as the VM rejects writes
+/// made by the `query` entry point.
 pub struct StoreWrapper {
     pub(super) inner: Store,
 }
@@ -122,12 +159,12 @@ impl Storage for StoreWrapper {
         must_get(&self.inner.borrow().merk, key)
     }
 
-    fn set(&mut self, _key: &[u8], _value: &[u8]) {
-        panic!("[cw-store]: `set` method invoked on read-only store wrapper");
+    fn set(&mut self, key: &[u8], _value: &[u8]) {
+        reject_write("set", "wrapper", key);
     }
 
-    fn remove(&mut self, _key: &[u8]) {
-        panic!("[cw-store]: `remove` method invoked on read-only store wrapper");
+    fn remove(&mut self, key: &[u8]) {
+        reject_write("remove", "wrapper", key);
     }
 
     fn range<'a>(
@@ -141,7 +178,10 @@ impl Storage for StoreWrapper {
                 return Box::new(iter::empty());
             }
         }
-        Box::new(MemIter::new(MerkIter::new(&self.inner.borrow().merk, start, end, order)))
+        let inner = &self.inner;
+        Box::new(PagedIter::new(start, end, order, move |start, end, order, limit| {
+            MerkIter::new(&inner.borrow().merk, start, end, order).take(limit).collect()
+        }))
     }
 }
 
@@ -192,17 +232,23 @@ impl Storage for PendingStoreWrapper {
             }
         }
 
-        let store = self.inner.borrow();
+        let inner = &self.inner;
+        Box::new(PagedIter::new(start, end, order, move |start, end, order, limit| {
+            let store = inner.borrow();
 
-        let base = MerkIter::new(&store.merk, start, end, order);
+            let base = MerkIter::new(&store.merk, start, end, order);
 
-        let pending_raw = store.pending_ops.range(range_bounds(start, end));
-        let pending: Box<dyn Iterator<Item = (&Vec<u8>, &Op)>> = match order {
-            Order::Ascending => Box::new(pending_raw),
-            Order::Descending => Box::new(pending_raw.rev()),
-        };
+            let pending_raw = store.pending_ops.range(range_bounds(start, end));
+            let pending: Box<dyn Iterator<Item = (&Vec<u8>, &Op)>> = match order {
+                Order::Ascending => Box::new(pending_raw),
+                Order::Descending => Box::new(pending_raw.rev()),
+            };
 
-        Box::new(MemIter::new(MergedIter::new(base, pending, order)))
+            // bound to a variable, so that the iterator borrowing `store` is
+            // dropped before it
+            let records = MergedIter::new(base, pending, order).take(limit).collect();
+            records
+        }))
     }
 }
 
@@ -283,21 +329,33 @@ mod tests {
     }
 
     #[test]
-    #[should_panic = "[cw-store]: `set` method invoked on read-only store wrapper"]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic = "[cw-store]: `set` method invoked on read-only store wrapper"
+    )]
     fn illegal_set() {
         let store = setup_test();
 
+        // the write panics in debug builds, and is discarded in release builds
         let mut wrapper = store.wrap();
-        wrapper.set(b"should", b"panic");
+        wrapper.set(b"should", b"discard");
+        assert_eq!(wrapper.get(b"should"), None);
+        assert_eq!(store.pending_wrap().get(b"should"), None);
     }
 
     #[test]
-    #[should_panic = "[cw-store]: `remove` method invoked on read-only store wrapper"]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic = "[cw-store]: `remove` method invoked on read-only store wrapper"
+    )]
     fn illegal_remove() {
         let store = setup_test();
 
+        // the write panics in debug builds, and is discarded in release builds
         let mut wrapper = store.wrap();
-        wrapper.remove(b"key2");
+        wrapper.remove(b"key1");
+        assert_eq!(wrapper.get(b"key1"), Some(b"value1".to_vec()));
+        assert_eq!(store.pending_wrap().get(b"key1"), Some(b"value1".to_vec()));
     }
 
     #[cfg(feature = "iterator")]
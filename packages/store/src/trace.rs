@@ -0,0 +1,33 @@
+use std::{cell::RefCell, rc::Rc};
+
+use cosmwasm_std::Addr;
+use cw_sdk::{StorageOp, StorageTrace};
+
+/// A sink for the storage operations recorded by the tracing layer, shared by
+/// all the stores being traced during the execution of a tx.
+///
+/// Cloning a tracer gives another handle to the same sink.
+#[derive(Clone, Debug, Default)]
+pub struct Tracer {
+    traces: Rc<RefCell<Vec<StorageTrace>>>,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an operation, made on the given contract's substore, or on the
+    /// store as a whole if no contract is given.
+    pub fn record(&self, contract: Option<&Addr>, op: StorageOp) {
+        self.traces.borrow_mut().push(StorageTrace {
+            contract: contract.cloned(),
+            op,
+        });
+    }
+
+    /// Return the operations recorded so far, in order, leaving the sink empty.
+    pub fn take(&self) -> Vec<StorageTrace> {
+        self.traces.take()
+    }
+}
@@ -0,0 +1,992 @@
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    collections::{BTreeMap, BTreeSet},
+    iter, mem,
+    path::Path,
+    rc::Rc,
+    sync::Arc,
+};
+
+use cosmwasm_std::{from_slice, to_vec, Binary, Order, Record, Storage};
+use cw_sdk::hash::{sha256, HASH_LENGTH};
+use cw_tree::{Batch, Op as TreeOp, TreeError};
+use merk::Op;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    forest::{
+        apply, prove, tree_root, tree_version, with_tree_prefix, FlatLayout, Layout, TreeId,
+        TOP_LEVEL_TREES,
+    },
+    helpers::reject_write,
+    iterators::{range_bounds, MergedIter, PagedIter},
+    listen::CommitListener,
+    prefix::{concat, namespace_upper_bound, trim},
+    pruning::{pruned_up_to, remove_tree_versions, Pruner},
+    snapshot::SnapshotDb,
+    CommitStore, Db, DbSnapshot, Pruning, StoreError,
+};
+
+/// Prefix of the flat copy of the latest committed state in the database. It
+/// doesn't collide with the namespaces of the trees, which start with one of
+/// the prefixes listed in `TreeId::prefix`, or with a zero byte.
+const STATE_PREFIX: &[u8] = b"s";
+
+/// Key of the version of the store, i.e. the number of commits so far. Keys of
+/// the store's own metadata start with `m`, so they don't collide either.
+const VERSION_KEY: &[u8] = b"mversion";
+
+/// Key of the record of the last commit. It is written before anything else in
+/// the commit, whereas the version is written last, so that the commit is only
+/// complete once the version is saved.
+const COMMIT_KEY: &[u8] = b"mcommit";
+
+/// What a commit is about to overwrite, so that it can be undone: on open, if
+/// it was interrupted halfway, e.g. by a crash; or on rollback, once complete.
+#[derive(Serialize, Deserialize)]
+struct CommitRecord {
+    /// The version of the store being committed.
+    version: u64,
+    /// Prefixes of the trees updated in the commit, and their versions before
+    /// it.
+    trees: Vec<(String, u64)>,
+    /// Keys written to the flat state in the commit, and their values before
+    /// it.
+    old_values: Vec<(Binary, Option<Binary>)>,
+}
+
+pub struct TreeStoreBase<D> {
+    /// The database in which the trees' nodes, as well as a flat copy of the
+    /// latest committed state, are persisted.
+    pub(crate) db: D,
+
+    /// Decides which tree each key belongs to.
+    pub(crate) layout: Box<dyn Layout>,
+
+    /// Database operations not yet committed to the tree, same as in
+    /// `StoreBase`.
+    pub(crate) pending_ops: BTreeMap<Vec<u8>, Op>,
+
+    /// Prunes old versions after commits, if the store is RocksDB-backed and
+    /// pruning is enabled.
+    pub(crate) pruner: Option<Pruner>,
+
+    /// Receive the changes made in each commit.
+    pub(crate) listeners: Vec<Box<dyn CommitListener>>,
+}
+
+/// A store backed by cw-tree, the repo's own versioned and Merklized radix
+/// tree, which persists its nodes in any `cosmwasm_std::Storage`, typically a
+/// RocksDB database.
+///
+/// Works the same as the Merk-backed `Store`: changes made during a block are
+/// kept as pending ops, which are applied on commit.
+///
+/// The state is a Merkle forest as described in `docs/5-app-state.md`: the
+/// store's `Layout` routes each key into the Global, Code, Account, IBC, or a
+/// contract's tree. On commit, the root of each updated contract tree is
+/// recorded in a top-level tree, and the root hash is the hash of the roots of
+/// the four top-level trees.
+///
+/// The trees are only used for commitments and proofs. Reads are served from a
+/// flat copy of the latest committed state kept next to them in the database,
+/// so that a range doesn't need to walk several trees.
+pub struct TreeStore<D = Db>(Rc<RefCell<TreeStoreBase<D>>>);
+
+impl TreeStore<Db> {
+    /// Open a RocksDB-backed tree store at the given path. If the last commit
+    /// was interrupted halfway, it is undone.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let store = Self::new(Db::open(path)?);
+        store.recover()?;
+        Ok(store)
+    }
+
+    /// Prune old versions of the trees with the given strategy, in a
+    /// background thread after commits. By default, nothing is pruned.
+    pub fn with_pruning(self, pruning: Pruning) -> Self {
+        let db = self.borrow().db.clone();
+        self.borrow_mut().pruner = Some(Pruner::new(db, pruning));
+        self
+    }
+}
+
+impl<D> TreeStore<D> {
+    /// Create a store with a flat layout, i.e. all keys in the Global tree.
+    pub fn new(db: D) -> Self {
+        let base = TreeStoreBase {
+            db,
+            layout: Box::new(FlatLayout),
+            pending_ops: BTreeMap::new(),
+            pruner: None,
+            listeners: vec![],
+        };
+        Self(Rc::new(RefCell::new(base)))
+    }
+
+    /// Route keys into the trees of the forest with the given layout. It must
+    /// not change over the lifetime of a database.
+    pub fn with_layout(self, layout: impl Layout + 'static) -> Self {
+        self.borrow_mut().layout = Box::new(layout);
+        self
+    }
+
+    /// Pass the changes made in each commit to the given listener, in addition
+    /// to the ones already added.
+    pub fn with_listener(self, listener: impl CommitListener + 'static) -> Self {
+        self.borrow_mut().listeners.push(Box::new(listener));
+        self
+    }
+
+    pub fn share(&self) -> Self {
+        Self(Rc::clone(&self.0))
+    }
+
+    pub(crate) fn borrow(&self) -> Ref<TreeStoreBase<D>> {
+        self.0.borrow()
+    }
+
+    pub(crate) fn borrow_mut(&self) -> RefMut<TreeStoreBase<D>> {
+        self.0.borrow_mut()
+    }
+}
+
+impl<D: Storage> TreeStore<D> {
+    /// The version of the store, i.e. the number of commits so far.
+    pub fn version(&self) -> u64 {
+        load_version(&self.borrow().db)
+    }
+
+    /// Undo the last commit if it was interrupted halfway, so that the store is
+    /// back at the version before it. Returns whether there was one to undo.
+    pub fn recover(&self) -> Result<bool, TreeError> {
+        let mut store = self.borrow_mut();
+        let version = load_version(&store.db);
+        let Some(record) = load_commit_record(&store.db)? else {
+            return Ok(false);
+        };
+        if record.version != version + 1 {
+            return Ok(false);
+        }
+
+        warn!(target: "Undoing incomplete commit", version = record.version);
+        undo_commit(&mut store.db, &record)?;
+        store.db.remove(COMMIT_KEY);
+
+        Ok(true)
+    }
+
+    /// Roll back the last commit, so that the store is back at the version
+    /// before it, which is returned. Pending ops are kept.
+    ///
+    /// Only the last commit is recorded, so rolling back can't be repeated to
+    /// go back further. The version before it must not have been pruned.
+    pub fn rollback(&self) -> Result<u64, StoreError> {
+        let mut store = self.borrow_mut();
+        let version = load_version(&store.db);
+        let record = match load_commit_record(&store.db)? {
+            Some(record) if record.version == version => record,
+            _ => return Err(StoreError::nothing_to_roll_back(version)),
+        };
+        if pruned_up_to(&store.db) >= version {
+            return Err(StoreError::version_pruned(version - 1));
+        }
+
+        undo_commit(&mut store.db, &record)?;
+
+        // same as in commits, the version is saved before the record is
+        // removed. if interrupted in between, the rollback is completed on open
+        if version > 1 {
+            store.db.set(VERSION_KEY, &(version - 1).to_be_bytes());
+        } else {
+            store.db.remove(VERSION_KEY);
+        }
+        store.db.remove(COMMIT_KEY);
+
+        Ok(version - 1)
+    }
+
+    /// Query a committed key along with Merkle proofs linking it to the root
+    /// hash.
+    pub fn get_with_proof(&self, key: &[u8]) -> Result<ForestProof, TreeError> {
+        let store = self.borrow();
+        let db = &store.db;
+
+        let tree = store.layout.locate(key);
+        let (value, proof) = prove(db, &tree, key)?;
+
+        let contract_root = match &tree {
+            TreeId::Contract(contract) => {
+                let root_key = store.layout.contract_root_key(contract);
+                let root_tree = store.layout.locate(&root_key);
+                let (root, root_proof) = prove(db, &root_tree, &root_key)?;
+                Some(ContractRootProof {
+                    tree: root_tree,
+                    key: root_key,
+                    value: root.map(Binary::into),
+                    proof: root_proof,
+                })
+            },
+            _ => None,
+        };
+
+        Ok(ForestProof {
+            tree,
+            value: value.map(Binary::into),
+            proof,
+            contract_root,
+            roots: top_level_roots(db)?,
+        })
+    }
+}
+
+impl<D> CommitStore for TreeStore<D>
+where
+    D: SnapshotDb + 'static,
+{
+    type Wrapper = TreeStoreWrapper<D>;
+    type PendingWrapper = PendingTreeStoreWrapper<D>;
+    type Snapshot = TreeStoreSnapshot<D::Snapshot>;
+    type Error = TreeError;
+
+    fn wrap(&self) -> TreeStoreWrapper<D> {
+        TreeStoreWrapper {
+            inner: self.share(),
+        }
+    }
+
+    fn pending_wrap(&self) -> PendingTreeStoreWrapper<D> {
+        PendingTreeStoreWrapper {
+            inner: self.share(),
+        }
+    }
+
+    /// Snapshot the database, reads from which are served from the flat state,
+    /// same as those from `wrap`.
+    fn snapshot(&self) -> TreeStoreSnapshot<D::Snapshot> {
+        TreeStoreSnapshot {
+            db: Arc::new(self.borrow().db.snapshot()),
+        }
+    }
+
+    /// Write the pending ops to the flat state, and apply them to the trees
+    /// they belong to, each as a new version of that tree.
+    ///
+    /// Contract trees are applied first, so that their new roots can be
+    /// recorded in the top-level trees in the same commit.
+    ///
+    /// The writes are not atomic. Before any of them, what they overwrite is
+    /// recorded, so that an interrupted commit can be undone on open; and the
+    /// version is saved last, marking the commit as complete. Listeners are
+    /// also called before the writes.
+    ///
+    /// Afterwards, old versions are pruned in the background, if due.
+    fn commit(&self) -> Result<(), TreeError> {
+        let mut ref_mut = self.borrow_mut();
+        let store = &mut *ref_mut;
+        let version = load_version(&store.db) + 1;
+
+        let pending_ops = mem::take(&mut store.pending_ops);
+        let record = record_commit(&store.db, &*store.layout, version, &pending_ops)?;
+        store.db.set(COMMIT_KEY, &to_vec(&record)?);
+
+        for listener in &mut store.listeners {
+            listener.on_commit(version, &pending_ops);
+        }
+
+        let mut batches: BTreeMap<TreeId, Batch<Binary, Binary>> = BTreeMap::new();
+        for (key, op) in pending_ops {
+            let op = match op {
+                Op::Put(value) => {
+                    store.db.set(&concat(STATE_PREFIX, &key), &value);
+                    TreeOp::Insert(value.into())
+                },
+                Op::Delete => {
+                    store.db.remove(&concat(STATE_PREFIX, &key));
+                    TreeOp::Delete
+                },
+            };
+            batches.entry(store.layout.locate(&key)).or_default().insert(key.into(), op);
+        }
+
+        let contract_batches = batches.split_off(&TreeId::Contract(String::new()));
+        for (tree, batch) in contract_batches {
+            let TreeId::Contract(contract) = &tree else {
+                unreachable!("only contract trees sort after the IBC tree");
+            };
+
+            apply(&mut store.db, version, &tree, batch)?;
+
+            // an emptied contract tree has its root record removed
+            let root_hash = tree_root(&store.db, &tree)?;
+            let root_key = store.layout.contract_root_key(contract);
+            let op = if root_hash == [0; HASH_LENGTH] {
+                store.db.remove(&concat(STATE_PREFIX, &root_key));
+                TreeOp::Delete
+            } else {
+                let value = store.layout.encode_contract_root(&root_hash);
+                store.db.set(&concat(STATE_PREFIX, &root_key), &value);
+                TreeOp::Insert(value.into())
+            };
+            batches.entry(store.layout.locate(&root_key)).or_default().insert(root_key.into(), op);
+        }
+
+        for (tree, batch) in batches {
+            apply(&mut store.db, version, &tree, batch)?;
+        }
+
+        store.db.set(VERSION_KEY, &version.to_be_bytes());
+
+        if let Some(pruner) = &mut store.pruner {
+            pruner.after_commit(version);
+        }
+
+        Ok(())
+    }
+
+    /// The hash of the roots of the Global, Code, Account, and IBC trees, in
+    /// this order. If all four are empty, the hash is all zeros, same as an
+    /// empty Merk tree.
+    fn root_hash(&self) -> [u8; HASH_LENGTH] {
+        let roots = must_load(top_level_roots(&self.borrow().db));
+        if roots.iter().all(|root| *root == [0; HASH_LENGTH]) {
+            return [0; HASH_LENGTH];
+        }
+        sha256(&roots.concat()).try_into().unwrap()
+    }
+}
+
+/// A committed value in a `TreeStore`, along with Merkle proofs linking it to
+/// the store's root hash:
+///
+/// - `proof` proves the key-value pair, or the key's absence, against the root
+///   of the tree the key belongs to;
+/// - for keys in a contract's tree, `contract_root` proves the contract's root
+///   against the root of the top-level tree it's recorded in;
+/// - `roots` hash to the root hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForestProof {
+    pub tree: TreeId,
+    pub value: Option<Vec<u8>>,
+    pub proof: Option<Binary>,
+    pub contract_root: Option<ContractRootProof>,
+    pub roots: [[u8; HASH_LENGTH]; 4],
+}
+
+/// The record of a contract's root in a top-level tree, along with its proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractRootProof {
+    pub tree: TreeId,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+    pub proof: Option<Binary>,
+}
+
+fn top_level_roots(db: &dyn Storage) -> Result<[[u8; HASH_LENGTH]; 4], TreeError> {
+    let mut roots = [[0; HASH_LENGTH]; 4];
+    for (root, tree) in roots.iter_mut().zip(&TOP_LEVEL_TREES) {
+        *root = tree_root(db, tree)?;
+    }
+    Ok(roots)
+}
+
+fn load_version(db: &dyn Storage) -> u64 {
+    db.get(VERSION_KEY).map_or(0, |bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Record what committing the given pending ops as the given version is about
+/// to overwrite: the versions of the trees to be updated, and the values in the
+/// flat state of the keys to be written, including the roots of the contracts.
+fn record_commit(
+    db: &dyn Storage,
+    layout: &dyn Layout,
+    version: u64,
+    pending_ops: &BTreeMap<Vec<u8>, Op>,
+) -> Result<CommitRecord, TreeError> {
+    let mut trees = BTreeSet::new();
+    let mut keys = BTreeSet::new();
+    for key in pending_ops.keys() {
+        let tree = layout.locate(key);
+        if let TreeId::Contract(contract) = &tree {
+            let root_key = layout.contract_root_key(contract);
+            trees.insert(layout.locate(&root_key));
+            keys.insert(root_key);
+        }
+        trees.insert(tree);
+        keys.insert(key.clone());
+    }
+
+    Ok(CommitRecord {
+        version,
+        trees: trees
+            .iter()
+            .map(|tree| Ok((tree.prefix(), tree_version(db, tree)?)))
+            .collect::<Result<_, TreeError>>()?,
+        old_values: keys
+            .into_iter()
+            .map(|key| {
+                let value = get_committed(db, &key).map(Binary);
+                (Binary(key), value)
+            })
+            .collect(),
+    })
+}
+
+fn load_commit_record(db: &dyn Storage) -> Result<Option<CommitRecord>, TreeError> {
+    let Some(bytes) = db.get(COMMIT_KEY) else {
+        return Ok(None);
+    };
+    Ok(Some(from_slice(&bytes)?))
+}
+
+/// Undo the writes made by the recorded commit, other than saving the version.
+/// Each step can be redone, so an undo that is itself interrupted can be run
+/// again.
+fn undo_commit(db: &mut dyn Storage, record: &CommitRecord) -> Result<(), TreeError> {
+    for (tree_prefix, tree_version) in &record.trees {
+        with_tree_prefix(tree_prefix, |tree| tree.revert(db, tree_version + 1))?;
+    }
+
+    for (key, value) in &record.old_values {
+        let key = concat(STATE_PREFIX, key);
+        match value {
+            Some(value) => db.set(&key, value),
+            None => db.remove(&key),
+        }
+    }
+
+    remove_tree_versions(db, record.version);
+
+    Ok(())
+}
+
+/// Read the committed value of a key from the flat state.
+fn get_committed(db: &dyn Storage, key: &[u8]) -> Option<Vec<u8>> {
+    db.get(&concat(STATE_PREFIX, key))
+}
+
+/// Iterate the committed key-value pairs in the flat state.
+fn range_committed<'a, D: Storage>(
+    db: &'a D,
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+    order: Order,
+) -> Box<dyn Iterator<Item = Record> + 'a> {
+    let start = match start {
+        Some(s) => concat(STATE_PREFIX, s),
+        None => STATE_PREFIX.to_vec(),
+    };
+    let end = match end {
+        Some(e) => concat(STATE_PREFIX, e),
+        None => namespace_upper_bound(STATE_PREFIX),
+    };
+    Box::new(
+        db.range(Some(&start), Some(&end), order)
+            .map(|(key, value)| (trim(STATE_PREFIX, &key), value)),
+    )
+}
+
+/// Reading from the trees must be successful; otherwise the database is
+/// corrupted, which we consider a fatal error.
+fn must_load<T>(result: Result<T, TreeError>) -> T {
+    result.unwrap_or_else(|err| {
+        panic!("[cw-store]: failed to read from tree: {err}");
+    })
+}
+
+/// Read-only wrapper of a `TreeStore`, the counterpart of `StoreWrapper`.
+/// Writes are rejected, same as in `StoreWrapper`.
+pub struct TreeStoreWrapper<D> {
+    inner: TreeStore<D>,
+}
+
+impl<D: Storage> Storage for TreeStoreWrapper<D> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        get_committed(&self.inner.borrow().db, key)
+    }
+
+    fn set(&mut self, key: &[u8], _value: &[u8]) {
+        reject_write("set", "wrapper", key);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        reject_write("remove", "wrapper", key);
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(iter::empty());
+            }
+        }
+        let inner = &self.inner;
+        Box::new(PagedIter::new(start, end, order, move |start, end, order, limit| {
+            range_committed(&inner.borrow().db, start, end, order).take(limit).collect()
+        }))
+    }
+}
+
+/// Thread-safe, read-only snapshot of a `TreeStore`'s committed state, backed
+/// by a snapshot of its database. Writes are rejected, same as in
+/// `StoreWrapper`.
+pub struct TreeStoreSnapshot<S = DbSnapshot> {
+    db: Arc<S>,
+}
+
+// derived `Clone` would require `S: Clone`, which database snapshots aren't
+impl<S> Clone for TreeStoreSnapshot<S> {
+    fn clone(&self) -> Self {
+        Self {
+            db: Arc::clone(&self.db),
+        }
+    }
+}
+
+impl<S: Storage> Storage for TreeStoreSnapshot<S> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        get_committed(&*self.db, key)
+    }
+
+    fn set(&mut self, key: &[u8], _value: &[u8]) {
+        reject_write("set", "snapshot", key);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        reject_write("remove", "snapshot", key);
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(iter::empty());
+            }
+        }
+        range_committed(&*self.db, start, end, order)
+    }
+}
+
+/// Read-and-write wrapper of a `TreeStore`, the counterpart of
+/// `PendingStoreWrapper`.
+pub struct PendingTreeStoreWrapper<D> {
+    inner: TreeStore<D>,
+}
+
+impl<D: Storage> Storage for PendingTreeStoreWrapper<D> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let store = self.inner.borrow();
+        let Some(op) = store.pending_ops.get(key) else {
+            return get_committed(&store.db, key);
+        };
+        match op {
+            Op::Put(value) => Some(value.clone()),
+            Op::Delete => None,
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.inner.borrow_mut().pending_ops.insert(key.to_vec(), Op::Put(value.to_vec()));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.inner.borrow_mut().pending_ops.insert(key.to_vec(), Op::Delete);
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(iter::empty());
+            }
+        }
+
+        let inner = &self.inner;
+        Box::new(PagedIter::new(start, end, order, move |start, end, order, limit| {
+            let store = inner.borrow();
+
+            let base = range_committed(&store.db, start, end, order);
+
+            let pending_raw = store.pending_ops.range(range_bounds(start, end));
+            let pending: Box<dyn Iterator<Item = (&Vec<u8>, &Op)>> = match order {
+                Order::Ascending => Box::new(pending_raw),
+                Order::Descending => Box::new(pending_raw.rev()),
+            };
+
+            // bound to a variable, so that the iterator borrowing `store` is
+            // dropped before it
+            let records = MergedIter::new(base, pending, order).take(limit).collect();
+            records
+        }))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use cosmwasm_std::testing::MockStorage;
+
+    use super::*;
+    use crate::pruning::prune;
+
+    fn setup_test() -> TreeStore<MockStorage> {
+        let store = TreeStore::new(MockStorage::new());
+
+        // add some committed key-values for testing
+        let mut wrapper = store.pending_wrap();
+        wrapper.set(b"key1", b"value1");
+        wrapper.set(b"key2", b"value2");
+        wrapper.set(b"key3", b"value3");
+        wrapper.set(b"key4", b"value4");
+        store.commit().unwrap();
+
+        // add some pending ops as well
+        wrapper.set(b"key2", b"value23456");
+        wrapper.set(b"key3333", b"value3333");
+        wrapper.remove(b"key3");
+
+        store
+    }
+
+    #[test]
+    fn getting() {
+        let store = setup_test();
+
+        let wrapper = store.wrap();
+        assert_eq!(wrapper.get(b"key2"), Some(b"value2".to_vec()));
+        assert_eq!(wrapper.get(b"key3"), Some(b"value3".to_vec()));
+        assert_eq!(wrapper.get(b"key3333"), None);
+
+        let wrapper = store.pending_wrap();
+        assert_eq!(wrapper.get(b"key2"), Some(b"value23456".to_vec()));
+        assert_eq!(wrapper.get(b"key3"), None);
+        assert_eq!(wrapper.get(b"key3333"), Some(b"value3333".to_vec()));
+    }
+
+    #[test]
+    fn committing() {
+        let store = setup_test();
+        let hash_before = store.root_hash();
+        assert_ne!(hash_before, [0; HASH_LENGTH]);
+
+        store.commit().unwrap();
+
+        let wrapper = store.wrap();
+        assert_eq!(wrapper.get(b"key2"), Some(b"value23456".to_vec()));
+        assert_eq!(wrapper.get(b"key3"), None);
+        assert_eq!(wrapper.get(b"key3333"), Some(b"value3333".to_vec()));
+        assert!(store.borrow().pending_ops.is_empty());
+        assert_ne!(store.root_hash(), hash_before);
+
+        // deleting every key empties the tree
+        let mut wrapper = store.pending_wrap();
+        for key in [b"key1".as_slice(), b"key2", b"key3333", b"key4"] {
+            wrapper.remove(key);
+        }
+        store.commit().unwrap();
+        assert_eq!(store.root_hash(), [0; HASH_LENGTH]);
+        assert_eq!(store.wrap().get(b"key1"), None);
+        assert_eq!(store.wrap().range(None, None, Order::Ascending).count(), 0);
+    }
+
+    #[test]
+    fn snapshotting() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TreeStore::open(dir.path()).unwrap();
+
+        let mut wrapper = store.pending_wrap();
+        wrapper.set(b"key1", b"value1");
+        wrapper.set(b"key2", b"value2");
+        store.commit().unwrap();
+
+        let snapshot = store.snapshot();
+
+        // neither pending ops, nor later commits, are seen by the snapshot
+        wrapper.set(b"key1", b"value11");
+        wrapper.remove(b"key2");
+        wrapper.set(b"key3", b"value3");
+        assert_eq!(snapshot.get(b"key1"), Some(b"value1".to_vec()));
+        store.commit().unwrap();
+        assert_eq!(snapshot.get(b"key1"), Some(b"value1".to_vec()));
+        assert_eq!(store.wrap().get(b"key1"), Some(b"value11".to_vec()));
+
+        // it can be read from another thread, even after the store is dropped
+        drop(store);
+        let records = thread::spawn(move || {
+            snapshot.range(None, None, Order::Descending).collect::<Vec<_>>()
+        });
+        assert_eq!(records.join().unwrap(), [
+            (b"key2".to_vec(), b"value2".to_vec()),
+            (b"key1".to_vec(), b"value1".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn empty_store() {
+        let store = TreeStore::new(MockStorage::new());
+        assert_eq!(store.root_hash(), [0; HASH_LENGTH]);
+        assert_eq!(store.wrap().get(b"key1"), None);
+        assert_eq!(store.pending_wrap().range(None, None, Order::Ascending).count(), 0);
+    }
+
+    /// Keys starting with `c` followed by a 4-byte contract address go to that
+    /// contract's tree, keys starting with `a` to the Account tree, where the
+    /// contracts' roots are recorded under `a` + address, and other keys to
+    /// the Global tree.
+    struct TestLayout;
+
+    impl Layout for TestLayout {
+        fn locate(&self, key: &[u8]) -> TreeId {
+            match key {
+                [b'c', contract @ ..] if contract.len() > 4 => {
+                    TreeId::Contract(String::from_utf8(contract[..4].to_vec()).unwrap())
+                },
+                [b'a', ..] => TreeId::Account,
+                _ => TreeId::Global,
+            }
+        }
+
+        fn contract_root_key(&self, contract: &str) -> Vec<u8> {
+            concat(b"a", contract.as_bytes())
+        }
+    }
+
+    #[test]
+    fn committing_to_forest() {
+        let store = TreeStore::new(MockStorage::new()).with_layout(TestLayout);
+
+        let mut wrapper = store.pending_wrap();
+        wrapper.set(b"key1", b"value1");
+        wrapper.set(b"cabcdkey1", b"value2");
+        wrapper.set(b"cabcdkey2", b"value3");
+        wrapper.set(b"cwxyzkey1", b"value4");
+        store.commit().unwrap();
+
+        let base = store.borrow();
+        let contract_root = tree_root(&base.db, &TreeId::Contract("abcd".into())).unwrap();
+        assert_ne!(contract_root, [0; HASH_LENGTH]);
+
+        // contract roots are readable like any other key
+        let wrapper = store.wrap();
+        assert_eq!(wrapper.get(b"aabcd"), Some(contract_root.to_vec()));
+        assert!(wrapper.get(b"awxyz").is_some());
+        assert_eq!(wrapper.get(b"cabcdkey2"), Some(b"value3".to_vec()));
+
+        // the root hash commits to the top-level trees
+        let roots = top_level_roots(&base.db).unwrap();
+        assert_ne!(roots[0], [0; HASH_LENGTH]);
+        assert_eq!(roots[1], [0; HASH_LENGTH]);
+        assert_ne!(roots[2], [0; HASH_LENGTH]);
+        assert_eq!(roots[3], [0; HASH_LENGTH]);
+        assert_eq!(store.root_hash().to_vec(), sha256(&roots.concat()));
+        drop(base);
+
+        // updating a contract's state updates the root hash, via its root
+        let hash_before = store.root_hash();
+        store.pending_wrap().set(b"cabcdkey1", b"value5");
+        store.commit().unwrap();
+        assert_ne!(store.wrap().get(b"aabcd"), Some(contract_root.to_vec()));
+        assert_ne!(store.root_hash(), hash_before);
+
+        // emptying a contract's tree removes its root
+        let mut wrapper = store.pending_wrap();
+        wrapper.remove(b"cabcdkey1");
+        wrapper.remove(b"cabcdkey2");
+        store.commit().unwrap();
+        assert_eq!(store.wrap().get(b"aabcd"), None);
+        assert!(store.wrap().get(b"awxyz").is_some());
+    }
+
+    #[test]
+    fn proving() {
+        let store = TreeStore::new(MockStorage::new()).with_layout(TestLayout);
+
+        let mut wrapper = store.pending_wrap();
+        wrapper.set(b"key1", b"value1");
+        wrapper.set(b"cabcdkey1", b"value2");
+        store.commit().unwrap();
+
+        let res = store.get_with_proof(b"key1").unwrap();
+        assert_eq!(res.tree, TreeId::Global);
+        assert_eq!(res.value, Some(b"value1".to_vec()));
+        assert!(res.proof.is_some());
+        assert!(res.contract_root.is_none());
+        assert_eq!(store.root_hash().to_vec(), sha256(&res.roots.concat()));
+
+        let res = store.get_with_proof(b"cabcdkey1").unwrap();
+        assert_eq!(res.tree, TreeId::Contract("abcd".into()));
+        assert_eq!(res.value, Some(b"value2".to_vec()));
+        let contract_root = res.contract_root.unwrap();
+        assert_eq!(contract_root.tree, TreeId::Account);
+        assert_eq!(contract_root.key, b"aabcd".to_vec());
+        assert_eq!(contract_root.value, store.wrap().get(b"aabcd"));
+        assert!(contract_root.proof.is_some());
+
+        // keys in an empty tree have neither a value nor a proof
+        let res = store.get_with_proof(b"cwxyzkey1").unwrap();
+        assert_eq!(res.value, None);
+        assert_eq!(res.proof, None);
+    }
+
+    #[test]
+    fn recovering_incomplete_commits() {
+        let store = TreeStore::new(MockStorage::new()).with_layout(TestLayout);
+
+        let mut wrapper = store.pending_wrap();
+        wrapper.set(b"key1", b"value1");
+        wrapper.set(b"cabcdkey1", b"value2");
+        store.commit().unwrap();
+        let hash_before = store.root_hash();
+
+        wrapper.set(b"key1", b"value3");
+        wrapper.remove(b"cabcdkey1");
+        wrapper.set(b"cwxyzkey1", b"value4");
+        store.commit().unwrap();
+
+        // interrupt the commit right before the version is saved, i.e. after
+        // everything else has been written
+        store.borrow_mut().db.set(VERSION_KEY, &1u64.to_be_bytes());
+        assert!(store.recover().unwrap());
+        assert_eq!(store.version(), 1);
+        assert_eq!(store.root_hash(), hash_before);
+
+        let wrapper = store.wrap();
+        assert_eq!(wrapper.get(b"key1"), Some(b"value1".to_vec()));
+        assert_eq!(wrapper.get(b"cabcdkey1"), Some(b"value2".to_vec()));
+        assert_eq!(wrapper.get(b"cwxyzkey1"), None);
+        assert_eq!(wrapper.get(b"awxyz"), None);
+
+        // a complete commit is left as is
+        assert!(!store.recover().unwrap());
+
+        // the version can be committed again
+        store.pending_wrap().set(b"key1", b"value5");
+        store.commit().unwrap();
+        assert_eq!(store.version(), 2);
+        assert_eq!(store.wrap().get(b"key1"), Some(b"value5".to_vec()));
+        assert_ne!(store.root_hash(), hash_before);
+    }
+
+    #[test]
+    fn rolling_back() {
+        let store = TreeStore::new(MockStorage::new()).with_layout(TestLayout);
+
+        let err = store.rollback().unwrap_err();
+        assert!(matches!(err, StoreError::NothingToRollBack { version: 0 }));
+
+        let mut wrapper = store.pending_wrap();
+        wrapper.set(b"key1", b"value1");
+        wrapper.set(b"cabcdkey1", b"value2");
+        store.commit().unwrap();
+        let hash_before = store.root_hash();
+
+        wrapper.set(b"key1", b"value3");
+        wrapper.set(b"cabcdkey2", b"value4");
+        store.commit().unwrap();
+
+        assert_eq!(store.rollback().unwrap(), 1);
+        assert_eq!(store.version(), 1);
+        assert_eq!(store.root_hash(), hash_before);
+        assert_eq!(store.wrap().get(b"key1"), Some(b"value1".to_vec()));
+        assert_eq!(store.wrap().get(b"cabcdkey2"), None);
+
+        // only the last commit can be rolled back
+        let err = store.rollback().unwrap_err();
+        assert!(matches!(err, StoreError::NothingToRollBack { version: 1 }));
+
+        // nor can it be once the version before it is pruned
+        store.pending_wrap().set(b"key1", b"value5");
+        store.commit().unwrap();
+        prune(&mut store.borrow_mut().db, 2).unwrap();
+        let err = store.rollback().unwrap_err();
+        assert!(matches!(err, StoreError::VersionPruned { version: 1 }));
+    }
+
+    /// Records the changesets it receives, with deletions as `None` values.
+    struct TestListener(Rc<RefCell<Vec<(u64, Vec<Record<Option<Vec<u8>>>>)>>>);
+
+    impl CommitListener for TestListener {
+        fn on_commit(&mut self, version: u64, changeset: &BTreeMap<Vec<u8>, Op>) {
+            let changes = changeset
+                .iter()
+                .map(|(key, op)| match op {
+                    Op::Put(value) => (key.clone(), Some(value.clone())),
+                    Op::Delete => (key.clone(), None),
+                })
+                .collect();
+            self.0.borrow_mut().push((version, changes));
+        }
+    }
+
+    #[test]
+    fn listening() {
+        let changesets = Rc::new(RefCell::new(vec![]));
+        let store =
+            TreeStore::new(MockStorage::new()).with_listener(TestListener(changesets.clone()));
+
+        let mut wrapper = store.pending_wrap();
+        wrapper.set(b"key2", b"value2");
+        wrapper.set(b"key1", b"value1");
+        store.commit().unwrap();
+        wrapper.remove(b"key1");
+        store.commit().unwrap();
+
+        // changes are sorted by key
+        let change = |key: &[u8], value: Option<&[u8]>| (key.to_vec(), value.map(<[u8]>::to_vec));
+        assert_eq!(*changesets.borrow(), [
+            (1, vec![change(b"key1", Some(b"value1")), change(b"key2", Some(b"value2"))]),
+            (2, vec![change(b"key1", None)]),
+        ]);
+    }
+
+    #[cfg(feature = "iterator")]
+    #[test]
+    fn iterating_pending() {
+        let store = setup_test();
+
+        let mut kv = vec![
+            (b"key1".to_vec(), b"value1".to_vec()),
+            (b"key2".to_vec(), b"value23456".to_vec()),
+            (b"key3333".to_vec(), b"value3333".to_vec()),
+            (b"key4".to_vec(), b"value4".to_vec()),
+        ];
+
+        let items = store.pending_wrap().range(None, None, Order::Ascending).collect::<Vec<_>>();
+        assert_eq!(items, kv);
+
+        let items = store
+            .pending_wrap()
+            .range(Some(b"key1234"), Some(b"key4"), Order::Ascending)
+            .collect::<Vec<_>>();
+        assert_eq!(items, &kv[1..3]);
+
+        kv.reverse();
+
+        let items = store.pending_wrap().range(None, None, Order::Descending).collect::<Vec<_>>();
+        assert_eq!(items, kv);
+
+        let items = store
+            .pending_wrap()
+            .range(Some(b"key1234"), Some(b"key4"), Order::Descending)
+            .collect::<Vec<_>>();
+        assert_eq!(items, &kv[1..3]);
+    }
+}
@@ -7,5 +7,7 @@ pub use crate::{
     // set::Set,
     tree::{Tree, TreeError, TreeIterator},
     types::*,
-    verify::{verify_membership, verify_non_membership, VerificationError},
+    verify::{
+        verify_membership, verify_multi, verify_non_membership, verify_range, VerificationError,
+    },
 };
@@ -1,19 +1,18 @@
 use {
     crate::{
-        Batch, Child, GetResponse, Nibble, NibbleIterator, NibblePath, NibbleRange,
-        NibbleRangeIterator, Node, NodeKey, Op, OpResponse, Proof, ProofNode, Record, RootResponse,
+        verify::{in_range, subtree_overlaps_range},
+        Batch, Child, GetResponse, MultiProofChild, MultiProofNode, Nibble, NibbleIterator,
+        NibblePath, NibbleRange, NibbleRangeIterator, Node, NodeKey, Op, OpResponse, Proof,
+        ProofChild, ProofNode, RangeResponse, Record, RootResponse,
     },
     cosmwasm_std::{to_binary, Order, StdResult, Storage},
     cw_item_set::Set,
-    cw_storage_plus::{Item, Map, PrefixBound},
+    cw_storage_plus::{Bound, Item, Map, PrefixBound},
     serde::{de::DeserializeOwned, ser::Serialize},
     std::{cmp::Ordering, collections::HashMap},
 };
 #[cfg(feature = "debug")]
-use {
-    crate::{NodeResponse, OrphanResponse},
-    cw_storage_plus::Bound,
-};
+use crate::{NodeResponse, OrphanResponse};
 
 const PRUNE_BATCH_SIZE: usize = 10;
 #[cfg(feature = "debug")]
@@ -38,13 +37,15 @@ const DEFAULT_QUERY_BATCH_SIZE: usize = 10;
 ///
 /// `Tree` offers a minimal API:
 ///
-/// | method    | description                                                                   |
-/// | --------- | ----------------------------------------------------------------------------- |
-/// | `apply`   | perform a batch insertion or deletion operations                              |
-/// | `prune`   | delete nodes that are not longer part of the tree since a given version       |
-/// | `root`    | query the root node hash                                                      |
-/// | `get`     | query the value associated with the given key, optionally with a Merkle proof |
-/// | `iterate` | enumerate key-value pairs stored in the tree                                  |
+/// | method                 | description                                                                   |
+/// | ---------------------- | ----------------------------------------------------------------------------- |
+/// | `apply`                | perform a batch insertion or deletion operations                              |
+/// | `prune`                | delete nodes that are not longer part of the tree since a given version       |
+/// | `revert`               | undo the latest version, restoring the previous one                           |
+/// | `root`                 | query the root node hash                                                      |
+/// | `get`                  | query the value associated with the given key, optionally with a Merkle proof |
+/// | `iterate`              | enumerate key-value pairs stored in the tree                                  |
+/// | `get_range_with_proof` | query the key-value pairs in a range, with a proof that none is left out      |
 pub struct Tree<'a, K, V> {
     version: Item<'a, u64>,
     nodes: Map<'a, &'a NodeKey, Node<K, V>>,
@@ -351,6 +352,53 @@ where
         Ok(())
     }
 
+    /// Undo the given version of the tree, which must be the latest one: delete
+    /// the nodes created in it, and unmark the ones orphaned in it, so that the
+    /// previous version becomes the latest again.
+    ///
+    /// This also cleans up after an `apply` that was interrupted halfway, in
+    /// which case the version may have not been saved yet. Reverting a version
+    /// that doesn't exist does nothing.
+    ///
+    /// The previous version must not have been pruned, as the nodes orphaned
+    /// in the reverted version are needed to restore it.
+    pub fn revert(&self, store: &mut dyn Storage, version: u64) -> Result<()> {
+        let min = Some(Bound::inclusive(&NodeKey::root(version)));
+        let max = Some(Bound::exclusive(&NodeKey::root(version + 1)));
+        let node_keys = self
+            .nodes
+            .keys(store, min, max, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        for node_key in &node_keys {
+            self.nodes.remove(store, node_key);
+        }
+
+        let bound = Some(PrefixBound::inclusive(version));
+        let orphans = self
+            .orphans
+            .prefix_range(store, bound.clone(), bound, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        for (orphaned_since_version, node_key) in &orphans {
+            self.orphans.remove(store, (*orphaned_since_version, node_key))?;
+        }
+
+        if self.version.may_load(store)? == Some(version) {
+            if version > 1 {
+                self.set_version(store, version - 1)?;
+            } else {
+                self.version.remove(store);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the latest version of the tree, or `None` if no batch has ever
+    /// been applied to it.
+    pub fn latest_version(&self, store: &dyn Storage) -> StdResult<Option<u64>> {
+        self.version.may_load(store)
+    }
+
     fn version_or_default(&self, store: &dyn Storage, version: Option<u64>) -> StdResult<u64> {
         if let Some(version) = version {
             Ok(version)
@@ -540,6 +588,106 @@ where
         Ok(TreeIterator::new(self, store, order, min, max, root_node))
     }
 
+    /// Query the key-value pairs within the range `[min, max)` in ascending
+    /// order, with a `MultiProof` that they are all the pairs in the range.
+    ///
+    /// If a limit is given and the range holds more pairs than it, only the
+    /// first `limit` of them are returned, and the range is narrowed to end at
+    /// the first one left out. The narrowed bound is returned as the response's
+    /// `max`, which is what the proof is to be verified against, and from which
+    /// the next page can be queried.
+    pub fn get_range_with_proof(
+        &self,
+        store: &dyn Storage,
+        min: Option<&K>,
+        max: Option<&K>,
+        limit: Option<usize>,
+        version: Option<u64>,
+    ) -> Result<RangeResponse<K, V>> {
+        let version = self.version_or_default(store, version)?;
+        let root_node = self.root_node(store, version)?;
+
+        let mut records = vec![];
+        let mut next = None;
+        let proof = self.prove_range_at(
+            store,
+            &NodeKey::root(version),
+            root_node,
+            min.map(|min| min.as_ref()),
+            max.map(|max| max.as_ref()),
+            limit,
+            &mut records,
+            &mut next,
+        )?;
+
+        Ok(RangeResponse {
+            records,
+            max: next.or_else(|| max.cloned()),
+            proof: to_binary(&proof)?,
+        })
+    }
+
+    /// Depth-first search for the records in the range, which finds them in
+    /// ascending order. Subtrees that can't contain any of them are pruned from
+    /// the proof.
+    ///
+    /// Once the limit is reached, the key of the next record found is taken as
+    /// the new max bound, and every subtree that comes after it is pruned.
+    #[allow(clippy::too_many_arguments)]
+    fn prove_range_at(
+        &self,
+        store: &dyn Storage,
+        current_node_key: &NodeKey,
+        current_node: Node<K, V>,
+        min: Option<&[u8]>,
+        max: Option<&[u8]>,
+        limit: Option<usize>,
+        records: &mut Vec<Record<K, V>>,
+        next: &mut Option<K>,
+    ) -> Result<MultiProofNode<K, V>> {
+        if let Some(data) = &current_node.data {
+            if next.is_none() && in_range(data.key.as_ref(), min, max) {
+                if limit.map_or(false, |limit| records.len() >= limit) {
+                    *next = Some(data.key.clone());
+                } else {
+                    records.push(data.clone());
+                }
+            }
+        }
+
+        let mut children = vec![];
+        for child in current_node.children {
+            let child_node_key = current_node_key.child(child.version, child.index);
+            if next.is_some() || !subtree_overlaps_range(&child_node_key.nibble_path, min, max) {
+                children.push(MultiProofChild::Pruned(ProofChild {
+                    index: child.index,
+                    hash: child.hash,
+                }));
+                continue;
+            }
+
+            let child_node = self.nodes.load(store, &child_node_key)?;
+            children.push(MultiProofChild::Expanded {
+                index: child.index,
+                node: self.prove_range_at(
+                    store,
+                    &child_node_key,
+                    child_node,
+                    min,
+                    max,
+                    limit,
+                    records,
+                    next,
+                )?,
+            });
+        }
+
+        Ok(MultiProofNode {
+            children,
+            data: current_node.data,
+        })
+    }
+
     #[cfg(feature = "debug")]
     pub fn node(
         &self,
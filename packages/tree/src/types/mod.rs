@@ -20,8 +20,8 @@ pub use {
     node::{Child, Node, Record},
     node_key::NodeKey,
     op::{Batch, Op, OpResponse},
-    proof::{Proof, ProofChild, ProofNode},
-    query::{GetResponse, RootResponse},
+    proof::{MultiProof, MultiProofChild, MultiProofNode, Proof, ProofChild, ProofNode},
+    query::{GetResponse, RangeResponse, RootResponse},
 };
 #[cfg(feature = "debug")]
 pub use query::{NodeResponse, OrphanResponse};
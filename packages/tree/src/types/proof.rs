@@ -110,3 +110,74 @@ where
         hasher.finalize().into()
     }
 }
+
+/// A proof of several keys at once, in the form of the tree's nodes leading to
+/// them, starting from the root. Unlike separate proofs of each key, internal
+/// nodes shared by the paths to several keys are only included once.
+///
+/// Subtrees that aren't needed are pruned, i.e. only their hashes are included.
+/// The nodes that are included have all their data, so whether any key that
+/// leads into them exists can be checked.
+pub type MultiProof<K, V> = MultiProofNode<K, V>;
+
+#[cw_serde]
+pub struct MultiProofNode<K, V> {
+    /// All of the node's children, ordered ascendingly by indexes
+    pub children: Vec<MultiProofChild<K, V>>,
+    pub data: Option<Record<K, V>>,
+}
+
+#[cw_serde]
+pub enum MultiProofChild<K, V> {
+    /// A child whose subtree is left out of the proof, except for its hash
+    Pruned(ProofChild),
+    /// A child whose node is included in the proof
+    Expanded {
+        index: Nibble,
+        node: MultiProofNode<K, V>,
+    },
+}
+
+impl<K, V> MultiProofChild<K, V> {
+    pub fn index(&self) -> Nibble {
+        match self {
+            MultiProofChild::Pruned(child) => child.index,
+            MultiProofChild::Expanded {
+                index,
+                ..
+            } => *index,
+        }
+    }
+}
+
+impl<K, V> MultiProofNode<K, V>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    /// Compute the node's hash, the same way as `Node::hash`, from the hashes
+    /// of the pruned children, and those computed for the expanded ones.
+    pub fn hash(&self) -> Hash {
+        let mut hasher = Hasher::new();
+
+        for child in &self.children {
+            let child = match child {
+                MultiProofChild::Pruned(child) => child.clone(),
+                MultiProofChild::Expanded {
+                    index,
+                    node,
+                } => ProofChild {
+                    index: *index,
+                    hash: node.hash(),
+                },
+            };
+            hash_proof_child(&mut hasher, &child);
+        }
+
+        if let Some(data) = &self.data {
+            hash_data(&mut hasher, data);
+        }
+
+        hasher.finalize().into()
+    }
+}
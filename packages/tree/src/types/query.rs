@@ -1,5 +1,5 @@
 use {
-    crate::Hash,
+    crate::{Hash, Record},
     cosmwasm_schema::cw_serde,
     cosmwasm_std::Binary,
 };
@@ -21,6 +21,18 @@ pub struct GetResponse<K, V> {
     pub proof: Option<Binary>,
 }
 
+#[cw_serde]
+pub struct RangeResponse<K, V> {
+    /// The key-value pairs in the range, in ascending order
+    pub records: Vec<Record<K, V>>,
+    /// Exclusive upper bound of the range that is proven. Same as the queried
+    /// one, unless the range holds more pairs than the limit, in which case it
+    /// is the first key left out, from which the next page starts
+    pub max: Option<K>,
+    /// The `MultiProof` that the records are exactly the ones in the range
+    pub proof: Binary,
+}
+
 #[cfg(feature = "debug")]
 #[cw_serde]
 pub struct NodeResponse<K, V> {
@@ -1,4 +1,9 @@
-use crate::{Hash, NibblePath, Proof, ProofChild, Record};
+use {
+    crate::{
+        Hash, MultiProof, MultiProofChild, MultiProofNode, NibblePath, Proof, ProofChild, Record,
+    },
+    std::cmp::Ordering,
+};
 
 pub fn verify_membership<K, V>(
     root_hash: &Hash,
@@ -85,16 +90,199 @@ where
 
     // now we have arrived at the root, the computed root hash should match the
     // given root hash
-    if hash != *root_hash {
+    check_root_hash(root_hash, hash)
+}
+
+/// Verify that the given records are exactly the ones in the tree whose keys
+/// are within the range `[min, max)`, in ascending order.
+///
+/// Besides the records being in the tree, this checks the range is complete,
+/// i.e. none of the subtrees pruned from the proof may contain a key in range.
+pub fn verify_range<K, V>(
+    root_hash: &Hash,
+    min: Option<&K>,
+    max: Option<&K>,
+    records: &[Record<K, V>],
+    proof: &MultiProof<K, V>,
+) -> Result<()>
+where
+    K: AsRef<[u8]> + PartialEq,
+    V: AsRef<[u8]> + PartialEq,
+{
+    check_root_hash(root_hash, proof.hash())?;
+
+    let min = min.map(|min| min.as_ref());
+    let max = max.map(|max| max.as_ref());
+
+    let mut proven_records = vec![];
+    collect_range(proof, &NibblePath::empty(), min, max, &mut proven_records)?;
+
+    if proven_records.len() != records.len()
+        || proven_records.into_iter().zip(records).any(|(proven, given)| proven != given)
+    {
+        return Err(VerificationError::RangeMismatch);
+    }
+
+    Ok(())
+}
+
+/// Verify the values of several keys at once against a single `MultiProof`.
+/// Each item is a key, and its expected value, or `None` if the key is
+/// expected to not exist.
+pub fn verify_multi<K, V>(
+    root_hash: &Hash,
+    items: &[(K, Option<V>)],
+    proof: &MultiProof<K, V>,
+) -> Result<()>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]> + PartialEq,
+{
+    check_root_hash(root_hash, proof.hash())?;
+
+    for (key, value) in items {
+        match (lookup(proof, key)?, value) {
+            (Some(proven), Some(value)) if proven == value => (),
+            (None, None) => (),
+            (Some(_), None) => return Err(VerificationError::KeyExists),
+            _ => {
+                return Err(VerificationError::ValueMismatch {
+                    key: hex::encode(key),
+                });
+            },
+        }
+    }
+
+    Ok(())
+}
+
+fn check_root_hash(root_hash: &Hash, computed: Hash) -> Result<()> {
+    if computed != *root_hash {
         return Err(VerificationError::RootHashMismatch {
             given: root_hash.clone(),
-            computed: hash,
+            computed,
         });
     }
 
     Ok(())
 }
 
+/// Depth-first search of the proof's nodes for data in the range, which finds
+/// them in ascending order, same as iterating the tree.
+fn collect_range<'a, K, V>(
+    node: &'a MultiProofNode<K, V>,
+    nibble_path: &NibblePath,
+    min: Option<&[u8]>,
+    max: Option<&[u8]>,
+    records: &mut Vec<&'a Record<K, V>>,
+) -> Result<()>
+where
+    K: AsRef<[u8]>,
+{
+    // a node's data, if any, is keyed exactly by the node's nibble path, so it
+    // comes before the data in any of its children
+    if let Some(data) = &node.data {
+        if in_range(data.key.as_ref(), min, max) {
+            records.push(data);
+        }
+    }
+
+    for child in &node.children {
+        let child_nibble_path = nibble_path.child(child.index());
+        match child {
+            MultiProofChild::Pruned(_) => {
+                if subtree_overlaps_range(&child_nibble_path, min, max) {
+                    return Err(VerificationError::RangeNotCovered);
+                }
+            },
+            MultiProofChild::Expanded {
+                node,
+                ..
+            } => {
+                collect_range(node, &child_nibble_path, min, max, records)?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the value of the given key in the proof, following its nibbles from
+/// the root, the same way as `Tree::get`.
+fn lookup<'a, K, V>(proof: &'a MultiProof<K, V>, key: &K) -> Result<Option<&'a V>>
+where
+    K: AsRef<[u8]>,
+{
+    let nibble_path = NibblePath::from(key);
+    let mut node = proof;
+    let mut depth = 0;
+
+    loop {
+        if let Some(Record { key: data_key, value }) = &node.data {
+            if data_key.as_ref() == key.as_ref() {
+                return Ok(Some(value));
+            }
+        }
+
+        if depth == nibble_path.num_nibbles {
+            return Ok(None);
+        }
+
+        let index = nibble_path.get_nibble(depth);
+        match node.children.iter().find(|child| child.index() == index) {
+            None => return Ok(None),
+            Some(MultiProofChild::Pruned(_)) => {
+                return Err(VerificationError::KeyNotCovered {
+                    key: hex::encode(key),
+                });
+            },
+            Some(MultiProofChild::Expanded {
+                node: child_node,
+                ..
+            }) => node = child_node,
+        }
+
+        depth += 1;
+    }
+}
+
+/// Whether the key is within the range `[min, max)`.
+pub(crate) fn in_range(key: &[u8], min: Option<&[u8]>, max: Option<&[u8]>) -> bool {
+    min.map_or(true, |min| key >= min) && max.map_or(true, |max| key < max)
+}
+
+/// Whether the subtree under the given nibble path may contain any key within
+/// the range `[min, max)`.
+pub(crate) fn subtree_overlaps_range(
+    nibble_path: &NibblePath,
+    min: Option<&[u8]>,
+    max: Option<&[u8]>,
+) -> bool {
+    // all keys in the subtree start with its nibble path, so are no smaller
+    // than the path's bytes, whose last nibble is zero padded if odd
+    if let Some(max) = max {
+        if nibble_path.bytes.as_slice() >= max {
+            return false;
+        }
+    }
+
+    // compare the nibbles that the path and min have in common. if they're all
+    // equal, either the path starts with min, or min starts with the path, and
+    // either way keys no smaller than min may be in the subtree
+    if let Some(min) = min {
+        let min = NibblePath::from(min);
+        for i in 0..nibble_path.num_nibbles.min(min.num_nibbles) {
+            match nibble_path.get_nibble(i).cmp(&min.get_nibble(i)) {
+                Ordering::Less => return false,
+                Ordering::Greater => return true,
+                Ordering::Equal => (),
+            }
+        }
+    }
+
+    true
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum VerificationError {
     #[error("proof cannot be empty")]
@@ -114,6 +302,22 @@ pub enum VerificationError {
         given: Hash,
         computed: Hash,
     },
+
+    #[error("a subtree that may contain keys in the range is pruned from the proof")]
+    RangeNotCovered,
+
+    #[error("records do not match the ones in the range")]
+    RangeMismatch,
+
+    #[error("path to key {key} is pruned from the proof")]
+    KeyNotCovered {
+        key: String,
+    },
+
+    #[error("value of key {key} does not match the proven one")]
+    ValueMismatch {
+        key: String,
+    },
 }
 
 type Result<T> = std::result::Result<T, VerificationError>;
@@ -124,9 +328,10 @@ type Result<T> = std::result::Result<T, VerificationError>;
 mod tests {
     use {
         crate::{
-            verify_membership, verify_non_membership, Hash, Nibble, Proof, ProofChild, ProofNode,
-            Record,
+            verify_membership, verify_multi, verify_non_membership, verify_range, Hash,
+            MultiProof, Nibble, Op, Proof, ProofChild, ProofNode, Record, Tree, VerificationError,
         },
+        cosmwasm_std::{from_binary, testing::MockStorage},
         test_case::test_case,
     };
 
@@ -252,4 +457,107 @@ mod tests {
     ) {
         assert!(verify_non_membership(&root_hash, &key, &proof).is_ok());
     }
+
+    #[test]
+    fn verifying_ranges() {
+        let mut store = MockStorage::new();
+        let tree = Tree::<String, String>::new_default();
+
+        let keys = ["app", "apple", "banana", "cherry", "date", "elderberry", "fig", "grape"];
+        let batch = keys.iter().map(|key| (key.to_string(), Op::Insert(key.to_uppercase())));
+        tree.apply(&mut store, batch.collect()).unwrap();
+        let root_hash = tree.root(&store, None).unwrap().root_hash;
+
+        let record = |key: &str| Record {
+            key: key.to_string(),
+            value: key.to_uppercase(),
+        };
+        let get_range = |min: Option<&str>, max: Option<&str>, limit: Option<usize>| {
+            let (min, max) = (min.map(String::from), max.map(String::from));
+            let res = tree
+                .get_range_with_proof(&store, min.as_ref(), max.as_ref(), limit, None)
+                .unwrap();
+            let proof: MultiProof<String, String> = from_binary(&res.proof).unwrap();
+            (res.records, res.max, proof)
+        };
+
+        // the full range
+        let (records, max, proof) = get_range(None, None, None);
+        assert_eq!(records, keys.map(record));
+        assert_eq!(max, None);
+        verify_range(&root_hash, None, None, &records, &proof).unwrap();
+
+        // a sub range
+        let (min, max) = ("b".to_string(), "e".to_string());
+        let (records, _, proof) = get_range(Some(&min), Some(&max), None);
+        assert_eq!(records, [record("banana"), record("cherry"), record("date")]);
+        verify_range(&root_hash, Some(&min), Some(&max), &records, &proof).unwrap();
+
+        // records left out of, added to, or changed in the range are rejected
+        let err = verify_range(&root_hash, Some(&min), Some(&max), &records[1..], &proof);
+        assert!(matches!(err, Err(VerificationError::RangeMismatch)));
+        let mut tampered = records.clone();
+        tampered.push(record("durian"));
+        let err = verify_range(&root_hash, Some(&min), Some(&max), &tampered, &proof);
+        assert!(matches!(err, Err(VerificationError::RangeMismatch)));
+        tampered = records.clone();
+        tampered[1].value = "cherry".into();
+        let err = verify_range(&root_hash, Some(&min), Some(&max), &tampered, &proof);
+        assert!(matches!(err, Err(VerificationError::RangeMismatch)));
+
+        // the proof doesn't prove a wider range than the queried one
+        let err = verify_range(&root_hash, None, Some(&max), &records, &proof);
+        assert!(matches!(err, Err(VerificationError::RangeNotCovered)));
+        let err = verify_range(&root_hash, Some(&min), None, &records, &proof);
+        assert!(matches!(err, Err(VerificationError::RangeNotCovered)));
+
+        // with a limit, the range ends at the first record left out, from which
+        // the next page starts
+        let (records, max, proof) = get_range(Some("apple"), None, Some(2));
+        assert_eq!(records, [record("apple"), record("banana")]);
+        assert_eq!(max, Some("cherry".to_string()));
+        let min = "apple".to_string();
+        verify_range(&root_hash, Some(&min), max.as_ref(), &records, &proof).unwrap();
+
+        let (records, max, proof) = get_range(max.as_deref(), None, Some(10));
+        assert_eq!(records, keys[3..].iter().copied().map(record).collect::<Vec<_>>());
+        assert_eq!(max, None);
+        verify_range(&root_hash, Some(&"cherry".to_string()), None, &records, &proof).unwrap();
+    }
+
+    #[test]
+    fn verifying_multiple_keys() {
+        let mut store = MockStorage::new();
+        let tree = Tree::<String, String>::new_default();
+
+        let keys = ["apple", "banana", "cherry", "date", "elderberry"];
+        let batch = keys.iter().map(|key| (key.to_string(), Op::Insert(key.to_uppercase())));
+        tree.apply(&mut store, batch.collect()).unwrap();
+        let root_hash = tree.root(&store, None).unwrap().root_hash;
+
+        let (min, max) = ("b".to_string(), "e".to_string());
+        let res = tree.get_range_with_proof(&store, Some(&min), Some(&max), None, None).unwrap();
+        let proof: MultiProof<String, String> = from_binary(&res.proof).unwrap();
+
+        let item = |key: &str, value: Option<&str>| (key.to_string(), value.map(String::from));
+
+        // keys in the proven range can be proven to exist or not exist at once
+        let items = [
+            item("banana", Some("BANANA")),
+            item("coconut", None),
+            item("date", Some("DATE")),
+        ];
+        verify_multi(&root_hash, &items, &proof).unwrap();
+
+        let err = verify_multi(&root_hash, &[item("cherry", None)], &proof);
+        assert!(matches!(err, Err(VerificationError::KeyExists)));
+        let err = verify_multi(&root_hash, &[item("date", Some("FIG"))], &proof);
+        assert!(matches!(err, Err(VerificationError::ValueMismatch { .. })));
+        let err = verify_multi(&root_hash, &[item("durian", Some("DURIAN"))], &proof);
+        assert!(matches!(err, Err(VerificationError::ValueMismatch { .. })));
+
+        // keys whose paths are pruned from the proof can't be proven either way
+        let err = verify_multi(&root_hash, &[item("apple", Some("APPLE"))], &proof);
+        assert!(matches!(err, Err(VerificationError::KeyNotCovered { .. })));
+    }
 }
@@ -54,7 +54,9 @@ pub mod internals {
     //! Please don't use any of these types directly, as
     //! they might change frequently or be removed in the future.
 
+    pub use crate::capabilities::required_capabilities_from_module;
     pub use crate::compatibility::check_wasm;
     pub use crate::instance::instance_from_module;
+    pub use crate::parsed_wasm::ParsedWasm;
     pub use crate::wasm_backend::{compile, make_compiling_engine, make_runtime_engine};
 }